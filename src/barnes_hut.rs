@@ -0,0 +1,235 @@
+use crate::Particle;
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Cells holding at most this many bodies are not subdivided further.
+const LEAF_CAPACITY: usize = 8;
+/// Stops subdivision when many bodies sit on (almost) the same point.
+const MAX_DEPTH: u32 = 32;
+
+#[derive(Copy, Clone)]
+struct Body {
+  index: u32,
+  pos: Vector3<f32>,
+  mass: f32,
+}
+
+struct Node {
+  center_of_mass: Vector3<f32>,
+  mass: f32,
+  /// Side length of the cell
+  size: f32,
+  /// Index of the first node after this subtree, used for stackless traversal
+  next: u32,
+  /// Bodies owned by this cell when it is a leaf
+  first_body: u32,
+  last_body: u32,
+  leaf: bool,
+}
+
+/// Octree over particle positions for Barnes-Hut force evaluation.
+///
+/// Nodes are stored in depth-first order so the walk needs no stack: opening a cell moves to the
+/// next node, accepting it (or finishing a leaf) jumps to `next`. Accepted cells are approximated by
+/// their monopole (total mass at the centre of mass).
+pub struct Octree {
+  nodes: Vec<Node>,
+  bodies: Vec<Body>,
+}
+
+impl Octree {
+  #[must_use]
+  pub fn build(particles: &[Particle]) -> Self {
+    let mut bodies: Vec<Body> = particles
+      .iter()
+      .enumerate()
+      .map(|(index, particle)| Body {
+        index: index as u32,
+        pos: Vector3::from(particle.pos),
+        mass: particle.mass,
+      })
+      .collect();
+    let mut tree = Octree {
+      nodes: Vec::with_capacity(2 * particles.len() / LEAF_CAPACITY + 1),
+      bodies: Vec::new(),
+    };
+    if bodies.is_empty() {
+      return tree;
+    }
+
+    let (min, max) = bodies
+      .iter()
+      .fold((bodies[0].pos, bodies[0].pos), |(min, max), body| {
+        (
+          Vector3::new(
+            min.x.min(body.pos.x),
+            min.y.min(body.pos.y),
+            min.z.min(body.pos.z),
+          ),
+          Vector3::new(
+            max.x.max(body.pos.x),
+            max.y.max(body.pos.y),
+            max.z.max(body.pos.z),
+          ),
+        )
+      });
+    let center = (min + max) / 2.0;
+    let extent = max - min;
+    // pad slightly so bodies on the boundary still fall inside the root cell
+    let half_size = extent.x.max(extent.y).max(extent.z) * 0.5 * 1.001 + f32::EPSILON;
+
+    tree.build_node(&mut bodies, 0, center, half_size, 0);
+    tree.bodies = bodies;
+    tree
+  }
+
+  fn build_node(
+    &mut self,
+    bodies: &mut [Body],
+    offset: usize,
+    center: Vector3<f32>,
+    half_size: f32,
+    depth: u32,
+  ) {
+    let mass: f32 = bodies.iter().map(|body| body.mass).sum();
+    let center_of_mass = if mass > 0.0 {
+      bodies
+        .iter()
+        .fold(Vector3::zero(), |acc, body| acc + body.pos * body.mass)
+        / mass
+    } else {
+      center
+    };
+    let leaf = bodies.len() <= LEAF_CAPACITY || depth >= MAX_DEPTH;
+    let node_index = self.nodes.len();
+    self.nodes.push(Node {
+      center_of_mass,
+      mass,
+      size: 2.0 * half_size,
+      next: 0,
+      first_body: offset as u32,
+      last_body: (offset + bodies.len()) as u32,
+      leaf,
+    });
+
+    if !leaf {
+      let octant = |pos: Vector3<f32>| {
+        usize::from(pos.x >= center.x)
+          | usize::from(pos.y >= center.y) << 1
+          | usize::from(pos.z >= center.z) << 2
+      };
+      bodies.sort_unstable_by_key(|body| octant(body.pos));
+
+      let mut start = 0;
+      while start < bodies.len() {
+        let child = octant(bodies[start].pos);
+        let end = start + bodies[start..].partition_point(|body| octant(body.pos) == child);
+        let quarter = half_size / 2.0;
+        let child_center = center
+          + Vector3::new(
+            if child & 1 == 0 { -quarter } else { quarter },
+            if child & 2 == 0 { -quarter } else { quarter },
+            if child & 4 == 0 { -quarter } else { quarter },
+          );
+        self.build_node(
+          &mut bodies[start..end],
+          offset + start,
+          child_center,
+          quarter,
+          depth + 1,
+        );
+        start = end;
+      }
+    }
+
+    self.nodes[node_index].next = self.nodes.len() as u32;
+  }
+
+  /// Plummer-softened acceleration at `position`, excluding the particle at `index`.
+  ///
+  /// A cell is accepted when `size / distance < theta`; `theta = 0` degenerates to direct
+  /// summation.
+  #[must_use]
+  pub fn acceleration(
+    &self,
+    position: Vector3<f32>,
+    index: usize,
+    theta: f32,
+    gravity: f32,
+    softening: f32,
+  ) -> Vector3<f32> {
+    let mut acceleration = Vector3::zero();
+    let theta_sq = theta * theta;
+    let mut i = 0;
+    while i < self.nodes.len() {
+      let node = &self.nodes[i];
+      if node.leaf {
+        for body in &self.bodies[node.first_body as usize..node.last_body as usize] {
+          if body.index as usize == index {
+            continue;
+          }
+          let displacement = body.pos - position;
+          let r = displacement.magnitude();
+          // Skip extremely close particles to prevent numerical instability
+          if r < 0.000_001 {
+            continue;
+          }
+          let dist_sq = r * r + softening;
+          acceleration += displacement * (gravity * body.mass / (dist_sq * dist_sq.sqrt()));
+        }
+        i = node.next as usize;
+        continue;
+      }
+
+      let displacement = node.center_of_mass - position;
+      let r_sq = displacement.magnitude2();
+      if node.size * node.size < theta_sq * r_sq {
+        let dist_sq = r_sq + softening;
+        acceleration += displacement * (gravity * node.mass / (dist_sq * dist_sq.sqrt()));
+        i = node.next as usize;
+      } else {
+        i += 1;
+      }
+    }
+    acceleration
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+  #[test]
+  fn opening_every_cell_matches_direct_summation() {
+    let mut rng = SmallRng::seed_from_u64(7);
+    let particles: Vec<Particle> = (0..500)
+      .map(|_| Particle {
+        pos: [
+          rng.gen_range(-1.0..1.0),
+          rng.gen_range(-1.0..1.0),
+          rng.gen_range(-0.1..0.1),
+        ],
+        mass: rng.gen_range(0.5..2.0),
+        ..bytemuck::Zeroable::zeroed()
+      })
+      .collect();
+    let (gravity, softening) = (1.0, 0.001);
+    let tree = Octree::build(&particles);
+    for (index, particle) in particles.iter().enumerate() {
+      let position = Vector3::from(particle.pos);
+      let mut direct = Vector3::zero();
+      for (i, other) in particles.iter().enumerate() {
+        if i != index {
+          let displacement = Vector3::from(other.pos) - position;
+          let dist_sq = displacement.magnitude2() + softening;
+          direct += displacement * (gravity * other.mass / (dist_sq * dist_sq.sqrt()));
+        }
+      }
+      let tree = tree.acceleration(position, index, 0.0, gravity, softening);
+      assert!(
+        (tree - direct).magnitude() <= 1e-4 * direct.magnitude(),
+        "particle {index}: {tree:?} vs {direct:?}"
+      );
+    }
+  }
+}
@@ -0,0 +1,161 @@
+use crate::{
+  cpu::{kicked_velocity, ForceField},
+  friction, halo, Particle, SimParams, Solver,
+};
+use cgmath::{InnerSpace, Vector3};
+use rayon::prelude::*;
+
+/// Hierarchical power-of-two timesteps for the kick-drift-kick leapfrog.
+///
+/// Each particle gets `delta_t / 2^level` with the level chosen from
+/// `dt_i = eta * sqrt(softening_length / |acc|)`, so particles deep in a galaxy core take many
+/// small steps while the disk outskirts take one `delta_t` step.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockTimesteps {
+  /// Deepest level; the smallest step is `delta_t / 2^max_level`
+  pub max_level: u32,
+  /// Accuracy parameter of the timestep criterion
+  pub eta: f32,
+}
+
+impl BlockTimesteps {
+  /// Accuracy parameter unless one is chosen
+  pub const DEFAULT_ETA: f32 = 0.025;
+  /// Deepest level `RunConfig::validate` accepts, keeping `2^max_level` substeps in a `u32`
+  pub const MAX_LEVEL: u32 = 16;
+
+  /// Level whose step is the largest power-of-two fraction of `delta_t` not exceeding the
+  /// criterion.
+  fn level(&self, acc: [f32; 3], sim_params: &SimParams) -> u32 {
+    let acc = Vector3::from(acc).magnitude();
+    if acc <= 0.0 {
+      return 0;
+    }
+    let softening_length = sim_params.calibrate.sqrt();
+    let dt = self.eta * (softening_length / acc).sqrt();
+    let level = (sim_params.delta_t / dt).log2().ceil();
+    if level.is_nan() || level <= 0.0 {
+      0
+    } else {
+      (level as u32).min(self.max_level)
+    }
+  }
+}
+
+/// Advances `particles` by one `delta_t` with individual block timesteps.
+///
+/// Every particle starts and ends the step synchronised: all are opened with a half kick, then
+/// for each of the `2^max_level` substeps everything drifts and only the particles whose own step
+/// ends at that substep get a new force, a closing half kick and (if the step is not over) a new
+/// level and opening half kick. A particle may only move to a coarser level when the current time
+/// is a multiple of that level's step. Expects `acc` to be current on entry and the cores of
+/// `particles` at `core_indices`.
+pub fn step(
+  particles: &mut [Particle],
+  core_indices: &[usize],
+  sim_params: &SimParams,
+  solver: Solver,
+  block: BlockTimesteps,
+) {
+  let substeps = 1u32 << block.max_level;
+  let dt = sim_params.delta_t;
+  let dt_min = dt / substeps as f32;
+  let level_dt = |level: u32| dt / (1u32 << level) as f32;
+
+  let mut levels: Vec<u32> = particles
+    .par_iter()
+    .map(|particle| block.level(particle.acc, sim_params))
+    .collect();
+  let everyone: Vec<usize> = (0..particles.len()).collect();
+  half_kick(
+    particles,
+    core_indices,
+    &everyone,
+    &levels,
+    sim_params,
+    level_dt,
+  );
+
+  for substep in 1..=substeps {
+    particles.par_iter_mut().for_each(|particle| {
+      particle.pos = (Vector3::from(particle.pos) + Vector3::from(particle.vel) * dt_min).into();
+    });
+
+    let active: Vec<usize> = (0..particles.len())
+      .filter(|&i| substep % (substeps >> levels[i]) == 0)
+      .collect();
+    if active.is_empty() {
+      continue;
+    }
+
+    let accelerations: Vec<[f32; 3]> = {
+      let force_field = ForceField::new(particles, sim_params, solver);
+      active
+        .par_iter()
+        .map(|&i| {
+          let position = Vector3::from(particles[i].pos);
+          (force_field.acceleration(position, i, sim_params)
+            + halo::acceleration(particles, position, sim_params))
+          .into()
+        })
+        .collect()
+    };
+    for (&i, acc) in active.iter().zip(accelerations) {
+      particles[i].acc = acc;
+    }
+    half_kick(
+      particles,
+      core_indices,
+      &active,
+      &levels,
+      sim_params,
+      level_dt,
+    );
+
+    if substep < substeps {
+      for &i in &active {
+        let current = levels[i];
+        let mut level = block.level(particles[i].acc, sim_params);
+        while level < current && substep % (substeps >> level) != 0 {
+          level += 1;
+        }
+        levels[i] = level;
+      }
+      half_kick(
+        particles,
+        core_indices,
+        &active,
+        &levels,
+        sim_params,
+        level_dt,
+      );
+    }
+  }
+
+  if log::log_enabled!(log::Level::Debug) {
+    let mut histogram = vec![0usize; block.max_level as usize + 1];
+    for particle in particles.iter() {
+      histogram[block.level(particle.acc, sim_params) as usize] += 1;
+    }
+    log::debug!("particles per timestep level: {histogram:?}");
+  }
+}
+
+/// Half kick of the particles in `active` with their own level's step.
+fn half_kick(
+  particles: &mut [Particle],
+  core_indices: &[usize],
+  active: &[usize],
+  levels: &[u32],
+  sim_params: &SimParams,
+  level_dt: impl Fn(u32) -> f32 + Sync,
+) {
+  let cores = friction::cores(particles, core_indices, sim_params);
+  let velocities: Vec<[f32; 3]> = active
+    .par_iter()
+    .map(|&i| kicked_velocity(particles, i, 0.5 * level_dt(levels[i]), &cores, sim_params).into())
+    .collect();
+  for (&i, vel) in active.iter().zip(velocities) {
+    particles[i].vel = vel;
+  }
+}
@@ -0,0 +1,256 @@
+use crate::{Particle, SimParams};
+use cgmath::Vector3;
+use std::f64::consts::PI;
+
+/// Comoving integration of a periodic box in an expanding Friedmann universe.
+///
+/// Positions are comoving and wrapped into a box of side `L` centred on the origin, and `vel`
+/// holds the canonical momentum per unit mass `p = a² dx/dt`. The equations of motion
+/// `dx/dt = p / a²` and `dp/dt = g / a`, with `g` the acceleration of the periodic comoving mass
+/// distribution, then keep the form of the Newtonian ones: kicks and drifts are scaled by
+/// `∫ dt / a` and `∫ dt / a²` over their interval instead of `dt`.
+///
+/// The Hubble constant follows from the mean density of the box and `omega_matter`, so the total
+/// mass of the particles sets the expansion rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cosmology {
+  /// Comoving side length of the periodic box
+  pub box_size: f32,
+  /// Matter density today in units of the critical density
+  pub omega_matter: f32,
+  /// Cosmological constant today in units of the critical density; the rest is curvature
+  pub omega_lambda: f32,
+  /// Redshift at time 0
+  pub initial_redshift: f32,
+}
+
+impl Cosmology {
+  /// The cosmology stored in `sim_params`, or `None` with isolated boundaries.
+  #[must_use]
+  pub fn from_sim_params(sim_params: &SimParams) -> Option<Self> {
+    (sim_params.box_size > 0.0).then_some(Self {
+      box_size: sim_params.box_size,
+      omega_matter: sim_params.omega_matter,
+      omega_lambda: sim_params.omega_lambda,
+      initial_redshift: sim_params.initial_redshift,
+    })
+  }
+
+  #[must_use]
+  pub fn initial_scale_factor(&self) -> f32 {
+    1.0 / (1.0 + self.initial_redshift)
+  }
+
+  /// `H0` for which `total_mass` spread over the box is the matter density
+  /// `Ωm ρ_crit = Ωm 3 H0² / (8 π G)`.
+  #[must_use]
+  pub fn hubble_constant(&self, total_mass: f64, gravity: f32) -> f64 {
+    let mean_density = total_mass / f64::from(self.box_size).powi(3);
+    (8.0 * PI * f64::from(gravity) * mean_density / (3.0 * f64::from(self.omega_matter))).sqrt()
+  }
+
+  /// `E(a) = H / H0 = sqrt(Ωm / a³ + Ωk / a² + ΩΛ)`
+  #[must_use]
+  pub fn hubble_ratio(&self, a: f64) -> f64 {
+    let (omega_matter, omega_lambda) = self.omegas();
+    let omega_curvature = 1.0 - omega_matter - omega_lambda;
+    (omega_matter / a.powi(3) + omega_curvature / (a * a) + omega_lambda)
+      .max(0.0)
+      .sqrt()
+  }
+
+  /// Linear growth factor `D(a) ∝ E(a) ∫ da / (a E)³`, normalised to `D(1) = 1`.
+  #[must_use]
+  pub fn growth_factor(&self, a: f64) -> f64 {
+    self.growth_integral(a) * self.hubble_ratio(a)
+      / (self.growth_integral(1.0) * self.hubble_ratio(1.0))
+  }
+
+  /// Logarithmic growth rate `f = d ln D / d ln a`.
+  #[must_use]
+  pub fn growth_rate(&self, a: f64) -> f64 {
+    let (omega_matter, omega_lambda) = self.omegas();
+    let omega_curvature = 1.0 - omega_matter - omega_lambda;
+    let e = self.hubble_ratio(a);
+    // a dE/da / E + 1 / (a² E³ ∫ da / (a E)³)
+    -(3.0 * omega_matter / a.powi(3) + 2.0 * omega_curvature / (a * a)) / (2.0 * e * e)
+      + 1.0 / (a * a * e.powi(3) * self.growth_integral(a))
+  }
+
+  /// `∫₀ᵃ da / (a E)³` by Simpson's rule; the integrand vanishes like `a^1.5` at the origin.
+  fn growth_integral(&self, a: f64) -> f64 {
+    const INTERVALS: usize = 1000;
+    let h = a / INTERVALS as f64;
+    let integrand = |x: f64| {
+      if x == 0.0 {
+        0.0
+      } else {
+        (x * self.hubble_ratio(x)).powi(-3)
+      }
+    };
+    let sum: f64 = (0..=INTERVALS)
+      .map(|i| {
+        let weight = if i == 0 || i == INTERVALS {
+          1.0
+        } else if i % 2 == 1 {
+          4.0
+        } else {
+          2.0
+        };
+        weight * integrand(h * i as f64)
+      })
+      .sum();
+    sum * h / 3.0
+  }
+
+  fn omegas(&self) -> (f64, f64) {
+    (f64::from(self.omega_matter), f64::from(self.omega_lambda))
+  }
+}
+
+/// Table steps per Hubble time `1 / H` at the initial redshift.
+const TABLE_RESOLUTION: f64 = 1000.0;
+
+/// Simpson intervals used for the kick and drift factors of one stage.
+const SIMPSON_INTERVALS: usize = 8;
+
+/// Scale factor `a(t)` of a `Cosmology`, with `t = 0` at its initial redshift.
+///
+/// The acceleration equation `d²a/dt² = H0² (ΩΛ a - Ωm / 2a²)` is integrated with RK4 into a table
+/// of `(a, da/dt)` that grows as later times are asked for, and sampled with cubic Hermite
+/// interpolation.
+pub struct Expansion {
+  cosmology: Cosmology,
+  hubble_constant: f64,
+  /// Time between table entries
+  interval: f64,
+  /// `(a, da/dt)` at multiples of `interval`
+  table: Vec<(f64, f64)>,
+}
+
+impl Expansion {
+  #[must_use]
+  pub fn new(cosmology: Cosmology, particles: &[Particle], sim_params: &SimParams) -> Self {
+    let total_mass: f64 = particles
+      .iter()
+      .map(|particle| f64::from(particle.mass))
+      .sum();
+    let mut expansion = Self {
+      cosmology,
+      hubble_constant: cosmology.hubble_constant(total_mass, sim_params.gravity),
+      interval: 0.0,
+      table: Vec::new(),
+    };
+    let a = f64::from(cosmology.initial_scale_factor());
+    let rate = expansion.hubble_rate(a);
+    expansion.interval = 1.0 / (TABLE_RESOLUTION * rate);
+    expansion.table.push((a, a * rate));
+    expansion
+  }
+
+  /// `H0` in inverse simulation time units.
+  #[must_use]
+  pub fn hubble_constant(&self) -> f64 {
+    self.hubble_constant
+  }
+
+  #[must_use]
+  pub fn hubble_rate(&self, a: f64) -> f64 {
+    self.hubble_constant * self.cosmology.hubble_ratio(a)
+  }
+
+  pub fn scale_factor(&mut self, time: f32) -> f32 {
+    self.sample(f64::from(time)) as f32
+  }
+
+  /// `∫ dt / a` from `start` to `end`, the factor that replaces `dt` in a kick.
+  pub fn kick_factor(&mut self, start: f32, end: f32) -> f32 {
+    self.integrate(start, end, 1)
+  }
+
+  /// `∫ dt / a²` from `start` to `end`, the factor that replaces `dt` in a drift.
+  pub fn drift_factor(&mut self, start: f32, end: f32) -> f32 {
+    self.integrate(start, end, 2)
+  }
+
+  /// Simpson's rule for `∫ a^-power dt`.
+  fn integrate(&mut self, start: f32, end: f32, power: i32) -> f32 {
+    let start = f64::from(start);
+    let h = (f64::from(end) - start) / SIMPSON_INTERVALS as f64;
+    let sum: f64 = (0..=SIMPSON_INTERVALS)
+      .map(|i| {
+        let weight = if i == 0 || i == SIMPSON_INTERVALS {
+          1.0
+        } else if i % 2 == 1 {
+          4.0
+        } else {
+          2.0
+        };
+        weight * self.sample(start + h * i as f64).powi(-power)
+      })
+      .sum();
+    (sum * h / 3.0) as f32
+  }
+
+  fn sample(&mut self, time: f64) -> f64 {
+    let position = time.max(0.0) / self.interval;
+    let index = position.floor() as usize;
+    while self.table.len() < index + 2 {
+      self.extend();
+    }
+    let (a0, rate0) = self.table[index];
+    let (a1, rate1) = self.table[index + 1];
+    let s = position - index as f64;
+    let (s2, s3) = (s * s, s * s * s);
+    (2.0 * s3 - 3.0 * s2 + 1.0) * a0
+      + (s3 - 2.0 * s2 + s) * self.interval * rate0
+      + (3.0 * s2 - 2.0 * s3) * a1
+      + (s3 - s2) * self.interval * rate1
+  }
+
+  /// Appends the next table entry with one RK4 step.
+  fn extend(&mut self) {
+    let h0_sq = self.hubble_constant * self.hubble_constant;
+    let (omega_matter, omega_lambda) = self.cosmology.omegas();
+    let acceleration = |a: f64| h0_sq * (omega_lambda * a - 0.5 * omega_matter / (a * a));
+    let h = self.interval;
+    let (a, rate) = *self
+      .table
+      .last()
+      .expect("the table starts with the initial state");
+    let (k1a, k1v) = (rate, acceleration(a));
+    let (k2a, k2v) = (rate + 0.5 * h * k1v, acceleration(a + 0.5 * h * k1a));
+    let (k3a, k3v) = (rate + 0.5 * h * k2v, acceleration(a + 0.5 * h * k2a));
+    let (k4a, k4v) = (rate + h * k3v, acceleration(a + h * k3a));
+    self.table.push((
+      a + h / 6.0 * (k1a + 2.0 * k2a + 2.0 * k3a + k4a),
+      rate + h / 6.0 * (k1v + 2.0 * k2v + 2.0 * k3v + k4v),
+    ));
+  }
+}
+
+/// Maps a position into the box of side `box_size` centred on the origin.
+#[must_use]
+pub fn wrap(position: Vector3<f32>, box_size: f32) -> Vector3<f32> {
+  position.map(|x| x - box_size * (x / box_size).round())
+}
+
+/// Nearest periodic image of the separation between two positions inside the box.
+#[must_use]
+pub fn minimum_image(separation: Vector3<f32>, box_size: f32) -> Vector3<f32> {
+  // adding and subtracting 1.5 * 2^23 rounds to the nearest integer without a branch or a libm
+  // call, which matters in the direct-summation inner loop
+  const ROUND: f32 = 12_582_912.0;
+  let inverse = box_size.recip();
+  separation.map(|x| x - box_size * ((x * inverse + ROUND) - ROUND))
+}
+
+/// `wrap` into the box of `sim_params`; positions are left alone with isolated boundaries.
+#[must_use]
+pub fn wrap_periodic(position: Vector3<f32>, sim_params: &SimParams) -> Vector3<f32> {
+  if sim_params.box_size > 0.0 {
+    wrap(position, sim_params.box_size)
+  } else {
+    position
+  }
+}
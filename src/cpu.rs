@@ -0,0 +1,506 @@
+use crate::{
+  barnes_hut::Octree,
+  block_timestep::{self, BlockTimesteps},
+  cosmology::{self, Cosmology, Expansion},
+  friction::{self, Core},
+  halo, initialize,
+  integrator::{Integrator, Stage},
+  particle_mesh::Mesh,
+  regularization::{self, Binary},
+  sink, sph, star_formation, Particle, RunConfig, SimParams, Solver,
+};
+use cgmath::{InnerSpace, Vector3, Zero};
+use rayon::prelude::*;
+
+/// Multi-threaded CPU implementation of `shaders/compute.wgsl`.
+///
+/// Runs the same integrator stages as the GPU passes (same Plummer softening, same central-mass
+/// friction) so it can be used on machines without a usable adapter and as a reference to
+/// validate the shaders against.
+pub struct CpuCompute {
+  particles: Vec<Particle>,
+  stepper: Stepper,
+}
+
+impl CpuCompute {
+  #[must_use]
+  pub fn init(sim_params: &SimParams, run_config: &RunConfig) -> Self {
+    Self {
+      particles: initialize::create_galaxies(sim_params, run_config),
+      stepper: Stepper::new(run_config),
+    }
+  }
+
+  pub fn compute(&mut self, sim_params: &SimParams) {
+    self.stepper.step(&mut self.particles, sim_params);
+  }
+
+  /// Particles produced by the most recent call to `compute`.
+  #[must_use]
+  pub fn particles(&self) -> &[Particle] {
+    &self.particles
+  }
+
+  /// See `Stepper::swallowed_stars`.
+  #[must_use]
+  pub fn swallowed_stars(&self) -> &[Particle] {
+    self.stepper.swallowed_stars()
+  }
+}
+
+/// Host-side integration state, shared by the CPU backend and by `Render` for options that have
+/// no GPU kernel.
+pub struct Stepper {
+  solver: Solver,
+  integrator: Integrator,
+  block_timesteps: Option<BlockTimesteps>,
+  primed: bool,
+  scratch: Vec<Particle>,
+  /// Core pairs whose mutual force the last force evaluation left out
+  binaries: Vec<Binary>,
+  cosmology: Option<Cosmology>,
+  /// Scale factor history, set up from the particles on the first step
+  expansion: Option<Expansion>,
+  /// Stars formed during the run that the sinks have swallowed since
+  swallowed_stars: Vec<Particle>,
+}
+
+impl Stepper {
+  #[must_use]
+  pub fn new(run_config: &RunConfig) -> Self {
+    Self {
+      solver: run_config.solver,
+      integrator: run_config.integrator,
+      block_timesteps: run_config.block_timesteps,
+      primed: false,
+      scratch: Vec::new(),
+      binaries: Vec::new(),
+      cosmology: run_config.cosmology,
+      expansion: None,
+      swallowed_stars: Vec::new(),
+    }
+  }
+
+  /// Advances `particles` by one `delta_t`, then turns dense gas into stars and lets black holes
+  /// accrete and merge.
+  pub fn step(&mut self, particles: &mut Vec<Particle>, sim_params: &SimParams) {
+    let mut stages = match self.block_timesteps {
+      Some(_) => Vec::new(),
+      None => self.integrator.stages(),
+    };
+    if !self.primed && self.integrator.needs_initial_force() {
+      stages.insert(0, Stage::Force);
+    }
+    if let (Some(cosmology), None) = (self.cosmology, &self.expansion) {
+      self.expansion = Some(Expansion::new(cosmology, particles, sim_params));
+    }
+    // nothing becomes or stops being a core until the sinks merge at the end of the step
+    let core_indices = friction::core_indices(particles);
+    self.update_binaries(particles, &core_indices, sim_params);
+    self.primed = true;
+    run_stages(
+      particles,
+      &mut self.scratch,
+      sim_params,
+      self.solver,
+      StepCores {
+        indices: &core_indices,
+        binaries: &self.binaries,
+      },
+      self.expansion.as_mut(),
+      &stages,
+    );
+    if let Some(block) = self.block_timesteps {
+      block_timestep::step(particles, &core_indices, sim_params, self.solver, block);
+    }
+    star_formation::form_stars(particles, sim_params);
+    let accretion = sink::accrete(particles, sim_params);
+    for merger in accretion.mergers {
+      log::info!("{merger}");
+    }
+    self.swallowed_stars.extend(accretion.formed_stars);
+  }
+
+  /// Stars formed during the run, as they were when the sinks swallowed them.
+  #[must_use]
+  pub fn swallowed_stars(&self) -> &[Particle] {
+    &self.swallowed_stars
+  }
+
+  /// Picks the core binaries to regularize this step, reporting the ones that form or dissolve,
+  /// and corrects the accelerations carried over from the previous step to match.
+  fn update_binaries(
+    &mut self,
+    particles: &mut [Particle],
+    core_indices: &[usize],
+    sim_params: &SimParams,
+  ) {
+    let binaries = regularization::find_binaries(particles, core_indices, sim_params);
+    for binary in binaries
+      .iter()
+      .filter(|binary| !self.binaries.contains(binary))
+    {
+      log::info!(
+        "Cores {} and {} regularized at time {:.3}: {}",
+        binary.primary,
+        binary.secondary,
+        sim_params.time,
+        regularization::orbit(particles, *binary, sim_params)
+      );
+    }
+    for binary in self
+      .binaries
+      .iter()
+      .filter(|binary| !binaries.contains(binary))
+    {
+      log::info!(
+        "Cores {} and {} no longer regularized at time {:.3}",
+        binary.primary,
+        binary.secondary,
+        sim_params.time
+      );
+    }
+    if self.primed {
+      regularization::rebind(particles, &self.binaries, &binaries, sim_params);
+    }
+    self.binaries = binaries;
+  }
+}
+
+/// Time a stage kicks and drifts by: `c * dt`, or with an `Expansion` the comoving
+/// `∫ dt / a` and `∫ dt / a²` over the same interval.
+#[derive(Copy, Clone, Debug)]
+struct StageSteps {
+  kick: f32,
+  drift: f32,
+}
+
+/// The galaxy cores as found at the start of a step, and the pairs of them being regularized.
+#[derive(Copy, Clone, Debug)]
+struct StepCores<'a> {
+  indices: &'a [usize],
+  binaries: &'a [Binary],
+}
+
+/// Runs `stages` over `particles`, using `scratch` as the other half of a ping-pong pair exactly
+/// like the GPU passes do. The step ends at `sim_params.time`.
+fn run_stages(
+  particles: &mut Vec<Particle>,
+  scratch: &mut Vec<Particle>,
+  sim_params: &SimParams,
+  solver: Solver,
+  step_cores: StepCores,
+  mut expansion: Option<&mut Expansion>,
+  stages: &[Stage],
+) {
+  if scratch.len() != particles.len() {
+    scratch.clone_from(particles);
+  }
+  let dt = sim_params.delta_t;
+  let start = sim_params.time - dt;
+  // kicks and drifts each cover the step in order, so each keeps its own clock
+  let (mut kick_clock, mut drift_clock) = (start, start);
+  let mut advance = |clock: &mut f32, c: f32, factor: fn(&mut Expansion, f32, f32) -> f32| {
+    let from = *clock;
+    *clock += c * dt;
+    match expansion.as_deref_mut() {
+      Some(expansion) if c != 0.0 => factor(expansion, from, *clock),
+      _ => c * dt,
+    }
+  };
+  for &stage in stages {
+    let (kick, drift) = match stage {
+      Stage::Force => (0.0, 0.0),
+      Stage::Kick(c) => (c, 0.0),
+      Stage::Drift(c) => (0.0, c),
+      Stage::VerletDrift => (0.5, 1.0),
+    };
+    let steps = StageSteps {
+      kick: advance(&mut kick_clock, kick, Expansion::kick_factor),
+      drift: advance(&mut drift_clock, drift, Expansion::drift_factor),
+    };
+    run_stage(
+      particles, scratch, sim_params, solver, step_cores, stage, steps,
+    );
+    std::mem::swap(particles, scratch);
+  }
+}
+
+fn run_stage(
+  src: &[Particle],
+  dst: &mut [Particle],
+  sim_params: &SimParams,
+  solver: Solver,
+  step_cores: StepCores,
+  stage: Stage,
+  steps: StageSteps,
+) {
+  match stage {
+    Stage::Force => {
+      let force_field = ForceField::new(src, sim_params, solver);
+      dst
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(index, particle)| {
+          *particle = src[index];
+          let position = Vector3::from(particle.pos);
+          particle.acc = (force_field.acceleration(position, index, sim_params)
+            + halo::acceleration(src, position, sim_params))
+          .into();
+        });
+      regularization::remove_mutual_forces(dst, step_cores.binaries, sim_params);
+      sph::apply(dst, sim_params);
+    }
+    Stage::Kick(_) => {
+      let cores = friction::cores(src, step_cores.indices, sim_params);
+      dst
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(index, particle)| {
+          *particle = src[index];
+          particle.vel = kicked_velocity(src, index, steps.kick, &cores, sim_params).into();
+          particle.internal_energy = sph::heated_internal_energy(particle, steps.kick, sim_params);
+        });
+    }
+    Stage::Drift(_) => {
+      dst.par_iter_mut().zip(src).for_each(|(particle, current)| {
+        *particle = *current;
+        let position = Vector3::from(current.pos) + Vector3::from(current.vel) * steps.drift;
+        particle.pos = cosmology::wrap_periodic(position, sim_params).into();
+      });
+      regularization::drift(src, dst, step_cores.binaries, steps.drift, sim_params);
+    }
+    Stage::VerletDrift => {
+      let cores = friction::cores(src, step_cores.indices, sim_params);
+      dst
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(index, particle)| {
+          *particle = src[index];
+          let velocity = kicked_velocity(src, index, steps.kick, &cores, sim_params);
+          particle.vel = velocity.into();
+          particle.internal_energy = sph::heated_internal_energy(particle, steps.kick, sim_params);
+          let position = Vector3::from(particle.pos) + velocity * steps.drift;
+          particle.pos = cosmology::wrap_periodic(position, sim_params).into();
+        });
+      regularization::drift(src, dst, step_cores.binaries, steps.drift, sim_params);
+    }
+  }
+}
+
+/// `vel + dt * acc` for the particle at `index`, followed by friction over the same `dt`.
+pub(crate) fn kicked_velocity(
+  src: &[Particle],
+  index: usize,
+  dt: f32,
+  cores: &[Core],
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let current = src[index];
+  let velocity = Vector3::from(current.vel) + Vector3::from(current.acc) * dt;
+  friction::apply(src, index, velocity, dt, cores, sim_params)
+}
+
+/// Gravitational acceleration source built once per force evaluation from the current positions.
+pub enum ForceField<'a> {
+  Direct(&'a [Particle]),
+  BarnesHut { tree: Octree, theta: f32 },
+  ParticleMesh(Mesh),
+}
+
+impl<'a> ForceField<'a> {
+  #[must_use]
+  pub fn new(src: &'a [Particle], sim_params: &SimParams, solver: Solver) -> Self {
+    match solver {
+      Solver::Direct => ForceField::Direct(src),
+      Solver::BarnesHut { theta } => ForceField::BarnesHut {
+        tree: Octree::build(src),
+        theta,
+      },
+      Solver::ParticleMesh { grid } if sim_params.box_size > 0.0 => ForceField::ParticleMesh(
+        Mesh::build_periodic(src, grid, sim_params.gravity, sim_params.box_size),
+      ),
+      Solver::ParticleMesh { grid } => ForceField::ParticleMesh(Mesh::build(
+        src,
+        grid,
+        sim_params.gravity,
+        sim_params.calibrate,
+      )),
+    }
+  }
+
+  /// Acceleration felt at `position` by the particle at `index`, which is excluded from the sum.
+  #[must_use]
+  pub fn acceleration(
+    &self,
+    position: Vector3<f32>,
+    index: usize,
+    sim_params: &SimParams,
+  ) -> Vector3<f32> {
+    match self {
+      ForceField::Direct(src) => direct_acceleration(src, position, index, sim_params),
+      ForceField::BarnesHut { tree, theta } => tree.acceleration(
+        position,
+        index,
+        *theta,
+        sim_params.gravity,
+        sim_params.calibrate,
+      ),
+      ForceField::ParticleMesh(mesh) => mesh.acceleration(position),
+    }
+  }
+}
+
+fn direct_acceleration(
+  src: &[Particle],
+  position: Vector3<f32>,
+  index: usize,
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let mut acceleration = Vector3::zero();
+  for (i, other) in src.iter().enumerate() {
+    if i == index {
+      continue;
+    }
+    let mut displacement = Vector3::from(other.pos) - position;
+    if sim_params.box_size > 0.0 {
+      displacement = cosmology::minimum_image(displacement, sim_params.box_size);
+    }
+    let r = displacement.magnitude();
+
+    // Skip extremely close particles to prevent numerical instability
+    if r < 0.000_001 {
+      continue;
+    }
+
+    // Plummer potential: F = GM * r / (r^2 + e)^1.5
+    let dist_sq = r * r + sim_params.calibrate;
+    let force_magnitude = sim_params.gravity * other.mass / (dist_sq * dist_sq.sqrt());
+    acceleration += displacement * force_magnitude;
+  }
+  acceleration
+}
+
+/// Largest position and velocity difference between two snapshots of the same particles.
+#[must_use]
+pub fn max_deviation(a: &[Particle], b: &[Particle]) -> (f32, f32) {
+  a.iter()
+    .zip(b)
+    .fold((0.0f32, 0.0f32), |(pos_error, vel_error), (a, b)| {
+      let dpos = (Vector3::from(a.pos) - Vector3::from(b.pos)).magnitude();
+      let dvel = (Vector3::from(a.vel) - Vector3::from(b.vel)).magnitude();
+      (pos_error.max(dpos), vel_error.max(dvel))
+    })
+}
+
+/// Summary of how far an approximate solver's accelerations are from direct summation.
+pub struct ForceError {
+  pub mean: f32,
+  pub p99: f32,
+  pub max: f32,
+}
+
+/// Relative acceleration error `|a - a_direct| / |a_direct|` of `solver` over every
+/// `stride`-th particle.
+#[must_use]
+pub fn force_error(
+  particles: &[Particle],
+  sim_params: &SimParams,
+  solver: Solver,
+  stride: usize,
+) -> ForceError {
+  let force_field = ForceField::new(particles, sim_params, solver);
+  let mut errors: Vec<f32> = (0..particles.len())
+    .into_par_iter()
+    .step_by(stride.max(1))
+    .map(|index| {
+      let position = Vector3::from(particles[index].pos);
+      let exact = direct_acceleration(particles, position, index, sim_params);
+      let approx = force_field.acceleration(position, index, sim_params);
+      (approx - exact).magnitude() / exact.magnitude().max(f32::MIN_POSITIVE)
+    })
+    .collect();
+  if errors.is_empty() {
+    return ForceError {
+      mean: 0.0,
+      p99: 0.0,
+      max: 0.0,
+    };
+  }
+  errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  ForceError {
+    mean: errors.iter().sum::<f32>() / errors.len() as f32,
+    p99: errors[(errors.len() - 1) * 99 / 100],
+    max: errors[errors.len() - 1],
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    diagnostics::{to_f64, Diagnostics},
+    friction::Friction,
+    halo::HaloProfile,
+    ParticleKind,
+  };
+
+  /// An eccentric softened two-body orbit stepped by the leapfrog keeps its energy and angular
+  /// momentum, and each step ends with the hand-computed Plummer-softened accelerations.
+  #[test]
+  fn two_body_orbit_conserves_energy_and_angular_momentum() {
+    let config = RunConfig {
+      halo: HaloProfile::None,
+      friction: Friction::None,
+      ..RunConfig::default()
+    };
+    let sim_params = SimParams {
+      calibrate: 1e-4,
+      ..config.sim_params()
+    };
+    let mass = 1e5;
+    let gravity_mass = f64::from(sim_params.gravity * mass);
+    // 0.8 of the circular speed at a separation of 0.2
+    let speed = (0.8 * 2.0 * gravity_mass / 0.2).sqrt() as f32;
+    let body = |x: f32, vy: f32| Particle {
+      pos: [x, 0.0, 0.0],
+      vel: [0.0, vy, 0.0],
+      acc: [0.0; 3],
+      mass,
+      galaxy_id: 0,
+      kind: ParticleKind::Star as u32,
+      density: 0.0,
+      internal_energy: 0.0,
+      energy_rate: 0.0,
+      formation_time: 0.0,
+    };
+    let mut particles = vec![body(-0.1, -0.5 * speed), body(0.1, 0.5 * speed)];
+    let initial = Diagnostics::measure(&particles, &sim_params);
+
+    let mut stepper = Stepper::new(&config);
+    // over one orbit of about 1.1 time units
+    for step in 0..1200 {
+      stepper.step(&mut particles, &sim_params);
+      let [a, b] = [0, 1].map(|i| to_f64(particles[i].pos));
+      let r = b - a;
+      let expected =
+        r * gravity_mass / (r.magnitude2() + f64::from(sim_params.calibrate)).powf(1.5);
+      let acc = to_f64(particles[0].acc);
+      assert!(
+        (acc - expected).magnitude() < 1e-4 * expected.magnitude(),
+        "step {step}: {acc:?} vs {expected:?}"
+      );
+    }
+
+    let last = Diagnostics::measure(&particles, &sim_params);
+    let drift = last.energy_drift(&initial);
+    assert!(drift.abs() < 1e-4, "energy drift {drift}");
+    let angular_momentum = (last.angular_momentum - initial.angular_momentum).magnitude();
+    assert!(
+      angular_momentum < 1e-5 * initial.angular_momentum.magnitude(),
+      "angular momentum {:?} vs {:?}",
+      last.angular_momentum,
+      initial.angular_momentum
+    );
+  }
+}
@@ -0,0 +1,150 @@
+use crate::{cosmology, halo, regularization, Particle, ParticleKind, SimParams};
+use cgmath::{InnerSpace, Vector3, Zero};
+use rayon::prelude::*;
+use std::fmt;
+
+/// Conserved quantities of a snapshot, accumulated in f64 on the host.
+///
+/// Without dynamical friction (`damping` = 0) the energy, momentum and angular momentum are
+/// conserved by the equations of motion, so their drift measures integration error.
+#[derive(Copy, Clone, Debug)]
+pub struct Diagnostics {
+  pub kinetic: f64,
+  /// Plummer-softened pair potential, consistent with the forces the solvers approximate; pairs
+  /// in a periodic box are taken at their nearest image
+  pub potential: f64,
+  /// Virial `Σ r · F` of the softened pair forces; `potential` without softening
+  pub virial: f64,
+  /// Potential energy in the analytic halos
+  pub external: f64,
+  /// Virial `Σ r · F` of the analytic halos' forces, see `halo::virial`
+  pub external_virial: f64,
+  /// Internal energy of the gas
+  pub thermal: f64,
+  pub momentum: Vector3<f64>,
+  /// Angular momentum about the origin
+  pub angular_momentum: Vector3<f64>,
+}
+
+impl Diagnostics {
+  /// Measures `particles`. The potential is an O(N²) direct sum, so this is meant to be called
+  /// every few hundred steps rather than every frame.
+  #[must_use]
+  pub fn measure(particles: &[Particle], sim_params: &SimParams) -> Self {
+    let gravity = f64::from(sim_params.gravity);
+    let softening = f64::from(sim_params.calibrate);
+
+    let (potential, virial) = (0..particles.len())
+      .into_par_iter()
+      .map(|i| {
+        let position = Vector3::from(particles[i].pos);
+        particles[i + 1..]
+          .iter()
+          .map(|other| {
+            let mut displacement = Vector3::from(other.pos) - position;
+            if sim_params.box_size > 0.0 {
+              displacement = cosmology::minimum_image(displacement, sim_params.box_size);
+            }
+            let r2 = to_f64(displacement.into()).magnitude2();
+            let potential = -gravity * f64::from(particles[i].mass) * f64::from(other.mass)
+              / (r2 + softening).sqrt();
+            (potential, potential * r2 / (r2 + softening))
+          })
+          .fold((0.0, 0.0), |sum, pair| (sum.0 + pair.0, sum.1 + pair.1))
+      })
+      .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+    let potential = potential + regularization::potential_correction(particles, sim_params);
+
+    let mut diagnostics = Self {
+      kinetic: 0.0,
+      potential,
+      virial,
+      external: halo::potential_energy(particles, sim_params),
+      external_virial: halo::virial(particles, sim_params),
+      thermal: 0.0,
+      momentum: Vector3::zero(),
+      angular_momentum: Vector3::zero(),
+    };
+    for particle in particles {
+      let mass = f64::from(particle.mass);
+      let velocity = to_f64(particle.vel);
+      diagnostics.kinetic += 0.5 * mass * velocity.magnitude2();
+      if particle.kind() == ParticleKind::Gas {
+        diagnostics.thermal += mass * f64::from(particle.internal_energy);
+      }
+      diagnostics.momentum += velocity * mass;
+      diagnostics.angular_momentum += to_f64(particle.pos).cross(velocity) * mass;
+    }
+    diagnostics
+  }
+
+  #[must_use]
+  pub fn total_energy(&self) -> f64 {
+    self.kinetic + self.potential + self.external + self.thermal
+  }
+
+  /// `2K / |Σ r · F|` over the pair forces and the analytic halos, which is 1 for a system in
+  /// virial equilibrium.
+  #[must_use]
+  pub fn virial_ratio(&self) -> f64 {
+    2.0 * self.kinetic / (self.virial + self.external_virial).abs()
+  }
+
+  /// `(E - E0) / |E0|`.
+  #[must_use]
+  pub fn energy_drift(&self, initial: &Diagnostics) -> f64 {
+    (self.total_energy() - initial.total_energy()) / initial.total_energy().abs()
+  }
+}
+
+impl fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "E = {:.6e} (K = {:.4e}, W = {:.4e}, W_ext = {:.4e}, thermal = {:.4e}), \
+       2K/|r.F| = {:.3}, |P| = {:.3e}, |L| = {:.3e}",
+      self.total_energy(),
+      self.kinetic,
+      self.potential,
+      self.external,
+      self.thermal,
+      self.virial_ratio(),
+      self.momentum.magnitude(),
+      self.angular_momentum.magnitude()
+    )
+  }
+}
+
+/// A particle's position or velocity in the f64 the host accumulates in.
+pub(crate) fn to_f64(v: [f32; 3]) -> Vector3<f64> {
+  Vector3::new(f64::from(v[0]), f64::from(v[1]), f64::from(v[2]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Two particles on opposite faces of a periodic box are as close as their nearest images.
+  #[test]
+  fn periodic_pairs_use_the_nearest_image() {
+    let sim_params = SimParams {
+      box_size: 1.0,
+      ..SimParams::default()
+    };
+    let particle = |x: f32| Particle {
+      pos: [x, 0.5, 0.5],
+      vel: [0.0; 3],
+      acc: [0.0; 3],
+      mass: 1.0,
+      galaxy_id: 0,
+      kind: ParticleKind::Star as u32,
+      density: 0.0,
+      internal_energy: 0.0,
+      energy_rate: 0.0,
+      formation_time: 0.0,
+    };
+    let across = Diagnostics::measure(&[particle(0.05), particle(0.95)], &sim_params);
+    let near = Diagnostics::measure(&[particle(0.45), particle(0.55)], &sim_params);
+    assert!((across.potential - near.potential).abs() < 1e-6 * near.potential.abs());
+  }
+}
@@ -0,0 +1,558 @@
+use crate::{halo::HaloProfile, SimParams};
+use cgmath::Vector3;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use std::f64::consts::PI;
+
+/// Disks are cut off at this many scale radii, and `sech²` disks at this many scale heights
+const DISK_TRUNCATION: f64 = 5.0;
+/// Radii of the rotation curve and dispersion tables
+const RADIAL_BINS: usize = 512;
+/// Cells per axis of the Miyamoto-Nagai density table
+const CELLS: usize = 256;
+/// `σ_R κ / (G Σ)` of a marginally stable stellar disk
+const TOOMRE_STELLAR: f64 = 3.36;
+
+/// Density profile of a stellar disk with scale radius `a` and scale height `b`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiskProfile {
+  /// `Σ ∝ exp(-R / a)` with a `sech²(z / b)` vertical profile
+  Exponential,
+  /// `Φ = -G M / sqrt(R² + (a + sqrt(z² + b²))²)`
+  MiyamotoNagai,
+}
+
+impl DiskProfile {
+  /// Fraction of the mass inside cylindrical radius `x = R / a` for the exponential disk.
+  fn exponential_enclosed_mass(x: f64) -> f64 {
+    1.0 - (1.0 + x) * (-x).exp()
+  }
+
+  /// Density of a disk of unit mass at `(R, z)`.
+  fn density(self, r: f64, z: f64, a: f64, b: f64) -> f64 {
+    match self {
+      DiskProfile::Exponential => {
+        let sech = 1.0 / (z / b).cosh();
+        (-r / a).exp() / (2.0 * PI * a * a) * sech * sech / (2.0 * b)
+      }
+      DiskProfile::MiyamotoNagai => {
+        let zeta = (z * z + b * b).sqrt();
+        let outer = a + zeta;
+        b * b / (4.0 * PI) * (a * r * r + (a + 3.0 * zeta) * outer * outer)
+          / ((r * r + outer * outer).powf(2.5) * zeta.powi(3))
+      }
+    }
+  }
+
+  /// Squared midplane circular speed at `R` of a disk of mass `gravity_mass / G`.
+  fn circular_speed_sq(self, r: f64, a: f64, b: f64, gravity_mass: f64) -> f64 {
+    match self {
+      // Freeman (1970), with y = R / 2a
+      DiskProfile::Exponential => {
+        let y = 0.5 * r / a;
+        let sigma0 = gravity_mass / (2.0 * PI * a * a);
+        4.0 * PI * sigma0 * a * y * y * (bessel_i0(y) * bessel_k0(y) - bessel_i1(y) * bessel_k1(y))
+      }
+      DiskProfile::MiyamotoNagai => gravity_mass * r * r / (r * r + (a + b) * (a + b)).powf(1.5),
+    }
+  }
+}
+
+/// Sampler of a rotating disk of `count` unit-mass particles in approximate equilibrium in its
+/// own gravity, the softened central mass, the bulge and the halo, spinning about +z.
+///
+/// Mean rotation follows the combined rotation curve less the asymmetric drift, with Gaussian
+/// velocity dispersions in the epicyclic approximation: `σ_R = 3.36 Q G Σ / κ` for the target
+/// Toomre `Q`, `σ_φ = σ_R κ / 2Ω` and the isothermal-sheet `σ_z² = π G Σ b`. The disk's gravity is
+/// that of the untruncated profile in the midplane, and the bulge and halo are not adjusted to it.
+pub struct EquilibriumDisk {
+  profile: DiskProfile,
+  scale_radius: f64,
+  scale_height: f64,
+  /// Cell edges and cumulative mass of the Miyamoto-Nagai density table
+  cell_radii: Vec<f64>,
+  cell_heights: Vec<f64>,
+  cumulative: Vec<f64>,
+  radii: Vec<f64>,
+  circular_speed: Vec<f64>,
+  mean_speed: Vec<f64>,
+  sigma_r: Vec<f64>,
+  sigma_phi: Vec<f64>,
+  sigma_z: Vec<f64>,
+}
+
+impl EquilibriumDisk {
+  /// `bulge_mass` is the bulge mass inside radius `r`.
+  #[must_use]
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    profile: DiskProfile,
+    count: u32,
+    scale_radius: f32,
+    scale_height: f32,
+    toomre_q: f32,
+    bulge_mass: &dyn Fn(f64) -> f64,
+    halo: HaloProfile,
+    sim_params: &SimParams,
+  ) -> Self {
+    let (a, b) = (f64::from(scale_radius), f64::from(scale_height));
+    let gravity = f64::from(sim_params.gravity);
+    let max_radius = DISK_TRUNCATION * a;
+
+    // Miyamoto-Nagai disks are sampled from a table of cells, finer towards the midplane
+    let (cell_radii, cell_heights, cumulative) = if profile == DiskProfile::MiyamotoNagai {
+      let cell_radii: Vec<f64> = (0..=CELLS)
+        .map(|i| max_radius * i as f64 / CELLS as f64)
+        .collect();
+      let stretch = (max_radius / b).asinh();
+      let cell_heights: Vec<f64> = (0..=CELLS)
+        .map(|j| b * (stretch * j as f64 / CELLS as f64).sinh())
+        .collect();
+      let mut total = 0.0;
+      let mut cumulative = Vec::with_capacity(CELLS * CELLS);
+      for i in 0..CELLS {
+        let r = 0.5 * (cell_radii[i] + cell_radii[i + 1]);
+        for j in 0..CELLS {
+          let z = 0.5 * (cell_heights[j] + cell_heights[j + 1]);
+          // both sides of the midplane
+          total += 4.0
+            * PI
+            * profile.density(r, z, a, b)
+            * r
+            * (cell_radii[i + 1] - cell_radii[i])
+            * (cell_heights[j + 1] - cell_heights[j]);
+          cumulative.push(total);
+        }
+      }
+      (cell_radii, cell_heights, cumulative)
+    } else {
+      (Vec::new(), Vec::new(), Vec::new())
+    };
+    let truncated_fraction = match profile {
+      DiskProfile::Exponential => DiskProfile::exponential_enclosed_mass(DISK_TRUNCATION),
+      DiskProfile::MiyamotoNagai => cumulative.last().copied().unwrap_or(1.0),
+    };
+    // the particles make up the truncated disk; the profile extends beyond it
+    let mass = f64::from(count) / truncated_fraction;
+
+    let radii: Vec<f64> = (1..=RADIAL_BINS)
+      .map(|k| max_radius * k as f64 / RADIAL_BINS as f64)
+      .collect();
+    let surface_density: Vec<f64> = radii
+      .iter()
+      .map(|&r| match profile {
+        DiskProfile::Exponential => mass * (-r / a).exp() / (2.0 * PI * a * a),
+        DiskProfile::MiyamotoNagai => {
+          2.0
+            * mass
+            * cell_heights
+              .windows(2)
+              .map(|edge| profile.density(r, 0.5 * (edge[0] + edge[1]), a, b) * (edge[1] - edge[0]))
+              .sum::<f64>()
+        }
+      })
+      .collect();
+    let speed_sq: Vec<f64> = radii
+      .iter()
+      .map(|&r| {
+        let dist_sq = r * r + f64::from(sim_params.calibrate);
+        let central =
+          gravity * f64::from(sim_params.central_mass) * r * r / (dist_sq * dist_sq.sqrt());
+        let halo = f64::from(halo.circular_speed_sq(r as f32, sim_params));
+        central
+          + halo
+          + gravity * bulge_mass(r) / r
+          + profile.circular_speed_sq(r, a, b, gravity * mass)
+      })
+      .collect();
+
+    let slope_sq = gradient(&radii, &speed_sq);
+    let mut sigma_r = Vec::with_capacity(RADIAL_BINS);
+    let mut sigma_phi = Vec::with_capacity(RADIAL_BINS);
+    let mut sigma_z = Vec::with_capacity(RADIAL_BINS);
+    for k in 0..RADIAL_BINS {
+      let r = radii[k];
+      let omega_sq = speed_sq[k] / (r * r);
+      let kappa_sq = (slope_sq[k] / r + 2.0 * omega_sq).max(f64::MIN_POSITIVE);
+      let radial =
+        TOOMRE_STELLAR * f64::from(toomre_q) * gravity * surface_density[k] / kappa_sq.sqrt();
+      sigma_r.push(radial);
+      sigma_phi.push(radial * (kappa_sq / (4.0 * omega_sq)).sqrt());
+      sigma_z.push((PI * gravity * surface_density[k] * b).sqrt());
+    }
+
+    // asymmetric drift from the radial Jeans equation, with the surface density standing in for
+    // the midplane density
+    let pressure: Vec<f64> = (0..RADIAL_BINS)
+      .map(|k| {
+        (surface_density[k] * sigma_r[k] * sigma_r[k])
+          .max(f64::MIN_POSITIVE)
+          .ln()
+      })
+      .collect();
+    let log_radii: Vec<f64> = radii.iter().map(|r| r.ln()).collect();
+    let log_slope = gradient(&log_radii, &pressure);
+    let mean_speed = (0..RADIAL_BINS)
+      .map(|k| {
+        let anisotropy = (sigma_phi[k] / sigma_r[k].max(f64::MIN_POSITIVE)).powi(2);
+        let drift = sigma_r[k] * sigma_r[k] * (log_slope[k] + 1.0 - anisotropy);
+        (speed_sq[k] + drift).max(0.0).sqrt()
+      })
+      .collect();
+
+    Self {
+      profile,
+      scale_radius: a,
+      scale_height: b,
+      cell_radii,
+      cell_heights,
+      cumulative,
+      circular_speed: speed_sq.iter().map(|v| v.sqrt()).collect(),
+      radii,
+      mean_speed,
+      sigma_r,
+      sigma_phi,
+      sigma_z,
+    }
+  }
+
+  /// Draws the position and velocity of one particle relative to the galaxy. `cold` particles,
+  /// such as gas held up by pressure, move on circular orbits without dispersion.
+  pub fn sample(&self, rng: &mut impl Rng, cold: bool) -> (Vector3<f32>, Vector3<f32>) {
+    let (r, z) = match self.profile {
+      DiskProfile::Exponential => {
+        let max_radius = DISK_TRUNCATION * self.scale_radius;
+        // the radii of an exponential disk follow a gamma distribution of shape 2
+        let r = loop {
+          let r = -self.scale_radius * (rng.gen::<f64>() * rng.gen::<f64>()).ln();
+          if r < max_radius {
+            break r;
+          }
+        };
+        let z = loop {
+          let z = self.scale_height * (2.0 * rng.gen::<f64>() - 1.0).atanh();
+          if z.abs() < DISK_TRUNCATION * self.scale_height {
+            break z;
+          }
+        };
+        (r, z)
+      }
+      DiskProfile::MiyamotoNagai => {
+        let target = rng.gen::<f64>() * self.cumulative.last().copied().unwrap_or(0.0);
+        let cell = self
+          .cumulative
+          .partition_point(|&mass| mass < target)
+          .min(self.cumulative.len() - 1);
+        let (i, j) = (cell / CELLS, cell % CELLS);
+        let r =
+          self.cell_radii[i] + rng.gen::<f64>() * (self.cell_radii[i + 1] - self.cell_radii[i]);
+        let z = self.cell_heights[j]
+          + rng.gen::<f64>() * (self.cell_heights[j + 1] - self.cell_heights[j]);
+        (r, if rng.gen::<bool>() { z } else { -z })
+      }
+    };
+
+    let theta = rng.gen::<f64>() * 2.0 * PI;
+    let (sin, cos) = theta.sin_cos();
+    let (v_r, v_phi, v_z) = if cold {
+      (0.0, interpolate(&self.radii, &self.circular_speed, r), 0.0)
+    } else {
+      let mut gaussian = || rng.sample::<f64, _>(StandardNormal);
+      (
+        interpolate(&self.radii, &self.sigma_r, r) * gaussian(),
+        interpolate(&self.radii, &self.mean_speed, r)
+          + interpolate(&self.radii, &self.sigma_phi, r) * gaussian(),
+        interpolate(&self.radii, &self.sigma_z, r) * gaussian(),
+      )
+    };
+    let pos = Vector3::new(r * cos, r * sin, z);
+    let vel = Vector3::new(v_r * cos - v_phi * sin, v_r * sin + v_phi * cos, v_z);
+    (pos.cast().unwrap(), vel.cast().unwrap())
+  }
+}
+
+/// Non-axisymmetric structure imprinted on a disk rotating about +z: `arms` trailing logarithmic
+/// spiral arms, `Σ ∝ 1 + A cos(m (φ + ln(R / R₀) / tan p)))`, and a bar along x inside `bar_radius`.
+///
+/// The arms redistribute particles in azimuth, rotating their velocities with them, so the
+/// axisymmetric velocity field is kept. The bar squeezes the disk along y by `1 - bar_strength`
+/// near the centre, tapering off at `bar_radius`, which maps circular orbits onto ellipses aligned
+/// with the bar. The arms start at `R₀ = bar_radius`, at the ends of the bar.
+#[derive(Copy, Clone, Debug)]
+pub struct Pattern {
+  pub arms: u32,
+  /// Angle between the arms and the circles about the centre, in radians
+  pub pitch_angle: f32,
+  /// Relative density amplitude `A` of the arms
+  pub arm_strength: f32,
+  pub bar_strength: f32,
+  pub bar_radius: f32,
+}
+
+impl Pattern {
+  /// Moves a particle sampled from an axisymmetric disk into the pattern.
+  pub fn apply(
+    &self,
+    rng: &mut impl Rng,
+    pos: Vector3<f32>,
+    vel: Vector3<f32>,
+  ) -> (Vector3<f32>, Vector3<f32>) {
+    let radius = pos.x.hypot(pos.y);
+    let (mut pos, mut vel) = (pos, vel);
+    if self.arms > 0 && self.arm_strength > 0.0 && radius > 0.0 {
+      let winding = (radius / self.bar_radius).ln() / self.pitch_angle.tan();
+      let azimuth = loop {
+        let azimuth = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
+        let density = 1.0 + self.arm_strength * (self.arms as f32 * (azimuth + winding)).cos();
+        if rng.gen::<f32>() * (1.0 + self.arm_strength) < density {
+          break azimuth;
+        }
+      };
+      let (sin, cos) = (azimuth - pos.y.atan2(pos.x)).sin_cos();
+      let rotate =
+        |v: Vector3<f32>| Vector3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z);
+      pos = rotate(pos);
+      vel = rotate(vel);
+    }
+    if self.bar_strength > 0.0 {
+      let squeeze = 1.0 - self.bar_strength / (1.0 + (radius / self.bar_radius).powi(4));
+      pos.y *= squeeze;
+      vel.y *= squeeze;
+    }
+    (pos, vel)
+  }
+}
+
+/// Linear interpolation of `values` tabulated at increasing `xs`, clamped at both ends.
+fn interpolate(xs: &[f64], values: &[f64], x: f64) -> f64 {
+  let i = xs.partition_point(|&at| at < x).clamp(1, xs.len() - 1);
+  let t = ((x - xs[i - 1]) / (xs[i] - xs[i - 1])).clamp(0.0, 1.0);
+  values[i - 1] + (values[i] - values[i - 1]) * t
+}
+
+/// `dy/dx` by central differences, one-sided at the ends.
+fn gradient(xs: &[f64], ys: &[f64]) -> Vec<f64> {
+  let last = xs.len() - 1;
+  (0..=last)
+    .map(|i| {
+      let (lo, hi) = (i.saturating_sub(1), (i + 1).min(last));
+      (ys[hi] - ys[lo]) / (xs[hi] - xs[lo])
+    })
+    .collect()
+}
+
+/// Polynomial `Σ cᵢ xⁱ` by Horner's rule.
+fn polynomial(coefficients: &[f64], x: f64) -> f64 {
+  coefficients.iter().rev().fold(0.0, |sum, c| sum * x + c)
+}
+
+// Modified Bessel functions, Abramowitz & Stegun 9.8.1-9.8.8
+
+fn bessel_i0(x: f64) -> f64 {
+  if x <= 3.75 {
+    let t = (x / 3.75).powi(2);
+    polynomial(
+      &[
+        1.0,
+        3.515_622_9,
+        3.089_942_4,
+        1.206_749_2,
+        0.265_973_2,
+        0.036_076_8,
+        0.004_581_3,
+      ],
+      t,
+    )
+  } else {
+    let t = 3.75 / x;
+    polynomial(
+      &[
+        0.398_942_28,
+        0.013_285_92,
+        0.002_253_19,
+        -0.001_575_65,
+        0.009_162_81,
+        -0.020_577_06,
+        0.026_355_37,
+        -0.016_476_33,
+        0.003_923_77,
+      ],
+      t,
+    ) * x.exp()
+      / x.sqrt()
+  }
+}
+
+fn bessel_i1(x: f64) -> f64 {
+  if x <= 3.75 {
+    let t = (x / 3.75).powi(2);
+    x * polynomial(
+      &[
+        0.5,
+        0.878_905_94,
+        0.514_988_69,
+        0.150_849_34,
+        0.026_587_33,
+        0.003_015_32,
+        0.000_324_11,
+      ],
+      t,
+    )
+  } else {
+    let t = 3.75 / x;
+    polynomial(
+      &[
+        0.398_942_28,
+        -0.039_880_24,
+        -0.003_620_18,
+        0.001_638_01,
+        -0.010_315_55,
+        0.022_829_67,
+        -0.028_953_12,
+        0.017_876_54,
+        -0.004_200_59,
+      ],
+      t,
+    ) * x.exp()
+      / x.sqrt()
+  }
+}
+
+fn bessel_k0(x: f64) -> f64 {
+  if x <= 2.0 {
+    let t = (0.5 * x).powi(2);
+    -(0.5 * x).ln() * bessel_i0(x)
+      + polynomial(
+        &[
+          -0.577_215_66,
+          0.422_784_20,
+          0.230_697_56,
+          0.034_885_90,
+          0.002_626_98,
+          0.000_107_50,
+          0.000_007_40,
+        ],
+        t,
+      )
+  } else {
+    let t = 2.0 / x;
+    polynomial(
+      &[
+        1.253_314_14,
+        -0.078_323_58,
+        0.021_895_68,
+        -0.010_624_46,
+        0.005_878_72,
+        -0.002_515_40,
+        0.000_532_08,
+      ],
+      t,
+    ) * (-x).exp()
+      / x.sqrt()
+  }
+}
+
+fn bessel_k1(x: f64) -> f64 {
+  if x <= 2.0 {
+    let t = (0.5 * x).powi(2);
+    (0.5 * x).ln() * bessel_i1(x)
+      + polynomial(
+        &[
+          1.0,
+          0.154_431_44,
+          -0.672_785_79,
+          -0.181_568_97,
+          -0.019_194_02,
+          -0.001_104_04,
+          -0.000_046_86,
+        ],
+        t,
+      ) / x
+  } else {
+    let t = 2.0 / x;
+    polynomial(
+      &[
+        1.253_314_14,
+        0.234_986_19,
+        -0.036_556_20,
+        0.015_042_68,
+        -0.007_803_53,
+        0.003_256_14,
+        -0.000_682_45,
+      ],
+      t,
+    ) * (-x).exp()
+      / x.sqrt()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{rngs::SmallRng, SeedableRng};
+
+  #[test]
+  fn miyamoto_nagai_samples_follow_the_cell_table() {
+    let disk = EquilibriumDisk::new(
+      DiskProfile::MiyamotoNagai,
+      1000,
+      0.3,
+      0.05,
+      1.5,
+      &|_| 0.0,
+      HaloProfile::None,
+      &SimParams::default(),
+    );
+    let total = disk.cumulative[disk.cumulative.len() - 1];
+    let cell_masses: Vec<f64> = std::iter::once(0.0)
+      .chain(disk.cumulative.iter().copied())
+      .collect::<Vec<_>>()
+      .windows(2)
+      .map(|pair| (pair[1] - pair[0]) / total)
+      .collect();
+    // fractions of the mass inside the edge of every eighth column of radial and row of height
+    // cells
+    let edges: Vec<usize> = (1..=8).map(|k| k * CELLS / 8).collect();
+    let expected_radial: Vec<f64> = edges
+      .iter()
+      .map(|&edge| cell_masses[..edge * CELLS].iter().sum())
+      .collect();
+    let expected_vertical: Vec<f64> = edges
+      .iter()
+      .map(|&edge| {
+        (0..CELLS)
+          .map(|i| cell_masses[i * CELLS..i * CELLS + edge].iter().sum::<f64>())
+          .sum()
+      })
+      .collect();
+
+    let mut rng = SmallRng::seed_from_u64(1);
+    let samples = 50_000;
+    let mut radial = [0usize; 8];
+    let mut vertical = [0usize; 8];
+    for _ in 0..samples {
+      let (pos, _) = disk.sample(&mut rng, false);
+      let (r, z) = (f64::from(pos.x.hypot(pos.y)), f64::from(pos.z.abs()));
+      for (k, &edge) in edges.iter().enumerate() {
+        radial[k] += usize::from(r < disk.cell_radii[edge]);
+        vertical[k] += usize::from(z < disk.cell_heights[edge]);
+      }
+    }
+    // a few standard deviations of a binomial fraction
+    let tolerance = 0.01;
+    for k in 0..8 {
+      let radial = radial[k] as f64 / f64::from(samples);
+      let vertical = vertical[k] as f64 / f64::from(samples);
+      assert!(
+        (radial - expected_radial[k]).abs() < tolerance,
+        "R bin {k}: {radial} vs {}",
+        expected_radial[k]
+      );
+      assert!(
+        (vertical - expected_vertical[k]).abs() < tolerance,
+        "z bin {k}: {vertical} vs {}",
+        expected_vertical[k]
+      );
+    }
+  }
+}
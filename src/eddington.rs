@@ -0,0 +1,166 @@
+use rand::Rng;
+
+/// Isotropic distribution function `f(E)` of a spherical density profile, obtained by numerically
+/// inverting Eddington's formula
+///
+/// `f(E) = 1 / (√8 π²) d/dE ∫₀^E (dρ/dΨ) / √(E - Ψ) dΨ`
+///
+/// where `Ψ = -Φ` is the relative potential (zero at infinity). Only the shape of `f` is kept,
+/// which is all that is needed to sample velocities.
+pub struct Eddington {
+  /// Relative energies on a uniform grid from 0 to the central potential
+  energies: Vec<f64>,
+  df: Vec<f64>,
+}
+
+const ENERGY_BINS: usize = 1000;
+const INTEGRATION_STEPS: usize = 200;
+
+/// Radii at which profiles of scale radius `scale_radius` are tabulated for `Eddington::new`:
+/// 2000 points spaced logarithmically from 10⁻⁴ to 10³ scale radii.
+#[must_use]
+pub fn log_radii(scale_radius: f64) -> Vec<f64> {
+  (0..2000)
+    .map(|i| scale_radius * 10f64.powf(-4.0 + 7.0 * f64::from(i) / 1999.0))
+    .collect()
+}
+
+impl Eddington {
+  /// Tabulates `f(E)` from the density `density` and relative potential `psi` sampled at
+  /// increasing `radii`. The radii should reach far enough out that the density is negligible.
+  #[must_use]
+  pub fn new(radii: &[f64], density: &[f64], psi: &[f64]) -> Self {
+    // dρ/dΨ at each radius, ordered by increasing Ψ (decreasing radius)
+    let mut slope: Vec<(f64, f64)> = (1..radii.len() - 1)
+      .map(|i| {
+        let drho = density[i + 1] - density[i - 1];
+        let dpsi = psi[i + 1] - psi[i - 1];
+        (psi[i], drho / dpsi)
+      })
+      .collect();
+    slope.reverse();
+    let slope_at = |psi: f64| interpolate(&slope, psi);
+
+    let psi_max = slope.last().map_or(0.0, |&(psi, _)| psi);
+    let energies: Vec<f64> = (0..=ENERGY_BINS)
+      .map(|i| psi_max * i as f64 / ENERGY_BINS as f64)
+      .collect();
+    // Ψ = E - u² removes the inverse square root singularity
+    let integral: Vec<f64> = energies
+      .iter()
+      .map(|&energy| {
+        let du = energy.sqrt() / INTEGRATION_STEPS as f64;
+        (0..INTEGRATION_STEPS)
+          .map(|k| {
+            let u = (k as f64 + 0.5) * du;
+            2.0 * slope_at(energy - u * u) * du
+          })
+          .sum()
+      })
+      .collect();
+    let df = (0..energies.len())
+      .map(|i| {
+        let (lo, hi) = (i.saturating_sub(1), (i + 1).min(energies.len() - 1));
+        let derivative = (integral[hi] - integral[lo]) / (energies[hi] - energies[lo]);
+        derivative.max(0.0)
+      })
+      .collect();
+    Self { energies, df }
+  }
+
+  /// Unnormalised distribution function at relative energy `energy`.
+  #[must_use]
+  pub fn df(&self, energy: f64) -> f64 {
+    if energy <= 0.0 {
+      return 0.0;
+    }
+    let step = self.energies[1] - self.energies[0];
+    let position = (energy / step).min((self.df.len() - 1) as f64);
+    let i = (position as usize).min(self.df.len() - 2);
+    let t = position - i as f64;
+    self.df[i] * (1.0 - t) + self.df[i + 1] * t
+  }
+
+  /// Draws a speed at a point with relative potential `psi` from `p(v) ∝ v² f(Ψ - v²/2)`.
+  pub fn sample_speed(&self, rng: &mut impl Rng, psi: f64) -> f64 {
+    let escape_speed = (2.0 * psi).sqrt();
+    let density = |v: f64| v * v * self.df(psi - 0.5 * v * v);
+    let peak = (1..100)
+      .map(|i| density(escape_speed * f64::from(i) / 100.0))
+      .fold(0.0, f64::max)
+      * 1.1;
+    if peak <= 0.0 {
+      return 0.0;
+    }
+    loop {
+      let v = rng.gen::<f64>() * escape_speed;
+      if rng.gen::<f64>() * peak <= density(v) {
+        return v;
+      }
+    }
+  }
+}
+
+/// Linear interpolation in a table sorted by its first column, clamped at both ends.
+fn interpolate(table: &[(f64, f64)], x: f64) -> f64 {
+  let i = table.partition_point(|&(xi, _)| xi < x);
+  if i == 0 {
+    return table[0].1;
+  }
+  if i == table.len() {
+    return table[table.len() - 1].1;
+  }
+  let (x0, y0) = table[i - 1];
+  let (x1, y1) = table[i];
+  y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+/// CDF of `q = v / v_esc` in a Plummer sphere, whose exact `f ∝ E^(7/2)` gives `q` the density
+/// `∝ q² (1 - q²)^(7/2)` at every radius; tabulated by the midpoint rule.
+#[cfg(test)]
+pub(crate) fn plummer_speed_cdf() -> impl Fn(f64) -> f64 {
+  const STEPS: usize = 10_000;
+  let mut cdf = vec![0.0; STEPS + 1];
+  for i in 0..STEPS {
+    let q = (i as f64 + 0.5) / STEPS as f64;
+    cdf[i + 1] = cdf[i] + q * q * (1.0 - q * q).powf(3.5);
+  }
+  move |q| cdf[((q * STEPS as f64) as usize).min(STEPS)] / cdf[STEPS]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{rngs::SmallRng, SeedableRng};
+
+  /// Speeds drawn from the tabulated `f(E)` of a Plummer sphere (`G = M = a = 1`) follow the
+  /// exact `f ∝ E^(7/2)`, for which `q = v / v_esc` has density `∝ q² (1 - q²)^(7/2)` at every
+  /// radius.
+  #[test]
+  fn plummer_speeds_follow_the_exact_distribution() {
+    let radii = log_radii(1.0);
+    let density: Vec<f64> = radii.iter().map(|r| (1.0 + r * r).powf(-2.5)).collect();
+    let psi: Vec<f64> = radii.iter().map(|r| 1.0 / (1.0 + r * r).sqrt()).collect();
+    let eddington = Eddington::new(&radii, &density, &psi);
+
+    let cdf_at = plummer_speed_cdf();
+
+    let mut rng = SmallRng::seed_from_u64(5);
+    for psi in [0.9f64, 0.5, 0.1] {
+      let escape_speed = (2.0 * psi).sqrt();
+      let mut samples: Vec<f64> = (0..20_000)
+        .map(|_| eddington.sample_speed(&mut rng, psi) / escape_speed)
+        .collect();
+      samples.sort_by(f64::total_cmp);
+      let distance = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &q)| (cdf_at(q) - (i as f64 + 0.5) / samples.len() as f64).abs())
+        .fold(0.0, f64::max);
+      assert!(
+        distance < 0.015,
+        "Ψ = {psi}: Kolmogorov-Smirnov distance {distance}"
+      );
+    }
+  }
+}
@@ -0,0 +1,159 @@
+use crate::Particle;
+use cgmath::{Deg, Matrix3, Vector3};
+
+/// Two galaxies approaching each other on a Kepler orbit, as in Toomre & Toomre (1972).
+///
+/// The orbit lies in the xy plane with pericenter along +x and its angular momentum along +z, and
+/// its total mass is that of both galaxies' particles; analytic halos do not count towards it. The
+/// galaxies start at `separation` on the way in, their centre of mass at rest at the origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Encounter {
+  /// Closest approach of the point-mass orbit
+  pub pericenter: f32,
+  /// 1 for a parabolic orbit, below 1 bound and above 1 hyperbolic
+  pub eccentricity: f32,
+  /// Initial distance between the centres; raised to the pericenter if it is smaller
+  pub separation: f32,
+  /// Orientation of the first and the second galaxy's disk
+  pub disks: [Orientation; 2],
+}
+
+/// Orientation of a disk relative to the orbit, using the disk as the reference plane of the
+/// orbital elements.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Orientation {
+  /// Angle between the disk's spin and the orbital angular momentum in degrees: 0 is prograde and
+  /// 180 retrograde
+  pub inclination: f32,
+  /// Angle in the orbital plane from the ascending node on the disk to the pericenter, in degrees
+  pub pericenter_argument: f32,
+}
+
+impl Orientation {
+  /// Rotation taking a disk spinning about +z into this orientation.
+  #[must_use]
+  pub fn rotation(&self) -> Matrix3<f32> {
+    // the ascending node N = s × z lies at -ω from the pericenter, and tilting about it by -i
+    // keeps N pointing along s × z
+    let node = Matrix3::from_angle_z(Deg(-self.pericenter_argument)) * Vector3::unit_x();
+    Matrix3::from_axis_angle(node, Deg(-self.inclination))
+  }
+}
+
+impl Encounter {
+  /// Position and velocity of the second galaxy relative to the first, for a total mass of
+  /// `gravity_mass / G`.
+  #[must_use]
+  pub fn relative_orbit(&self, gravity_mass: f32) -> (Vector3<f32>, Vector3<f32>) {
+    let (q, e) = (f64::from(self.pericenter), f64::from(self.eccentricity));
+    let semi_latus_rectum = q * (1.0 + e);
+    let mut r = f64::from(self.separation).max(q);
+    if e < 1.0 {
+      // a bound orbit never gets further than its apocenter
+      r = r.min(semi_latus_rectum / (1.0 - e));
+    }
+    // approaching, so before pericenter
+    let anomaly = if e > 0.0 {
+      -((semi_latus_rectum / r - 1.0) / e).clamp(-1.0, 1.0).acos()
+    } else {
+      0.0
+    };
+    let speed = (f64::from(gravity_mass) / semi_latus_rectum).sqrt();
+    let (sin, cos) = anomaly.sin_cos();
+    let radial = speed * e * sin;
+    let tangential = speed * (1.0 + e * cos);
+    let pos = Vector3::new(r * cos, r * sin, 0.0);
+    let vel = Vector3::new(
+      radial * cos - tangential * sin,
+      radial * sin + tangential * cos,
+      0.0,
+    );
+    (pos.cast().unwrap(), vel.cast().unwrap())
+  }
+
+  /// Orients the two galaxies, generated at rest around the origin in the blocks `galaxies` of
+  /// `particles`, and sets them on the orbit.
+  pub fn place(
+    &self,
+    particles: &mut [Particle],
+    galaxies: [std::ops::Range<usize>; 2],
+    gravity: f32,
+  ) {
+    let masses = galaxies
+      .clone()
+      .map(|range| particles[range].iter().map(|p| p.mass).sum::<f32>());
+    let total = masses[0] + masses[1];
+    let (pos, vel) = self.relative_orbit(gravity * total);
+    let offsets = [
+      (-pos * (masses[1] / total), -vel * (masses[1] / total)),
+      (pos * (masses[0] / total), vel * (masses[0] / total)),
+    ];
+    for ((range, disk), (center, velocity)) in galaxies.into_iter().zip(self.disks).zip(offsets) {
+      let rotation = disk.rotation();
+      for particle in &mut particles[range] {
+        particle.pos = (rotation * Vector3::from(particle.pos) + center).into();
+        particle.vel = (rotation * Vector3::from(particle.vel) + velocity).into();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::InnerSpace;
+
+  #[test]
+  fn relative_orbit_has_the_pericenter_energy_and_spin_of_its_elements() {
+    let gravity_mass = 3.0;
+    for eccentricity in [1.0, 0.5] {
+      let encounter = Encounter {
+        pericenter: 0.3,
+        eccentricity,
+        separation: 0.5,
+        disks: [Orientation::default(); 2],
+      };
+      let (pos, vel) = encounter.relative_orbit(gravity_mass);
+      assert!((pos.magnitude() - 0.5).abs() < 1e-5, "{pos:?}");
+      assert!(pos.dot(vel) < 0.0, "not approaching: {pos:?} {vel:?}");
+
+      let angular_momentum = pos.cross(vel);
+      assert!(
+        angular_momentum.z > 0.0 && angular_momentum.x.abs() + angular_momentum.y.abs() < 1e-6,
+        "{angular_momentum:?}"
+      );
+      let pericenter = angular_momentum.magnitude2() / gravity_mass / (1.0 + eccentricity);
+      assert!(
+        (pericenter - 0.3).abs() < 1e-4,
+        "e = {eccentricity}: q = {pericenter}"
+      );
+      let energy = 0.5 * vel.magnitude2() - gravity_mass / pos.magnitude();
+      let expected = -gravity_mass * (1.0 - eccentricity) / (2.0 * 0.3);
+      assert!(
+        (energy - expected).abs() < 1e-4,
+        "e = {eccentricity}: energy {energy} vs {expected}"
+      );
+    }
+  }
+
+  #[test]
+  fn rotation_tilts_the_spin_by_the_inclination_about_the_node() {
+    let retrograde = Orientation {
+      inclination: 180.0,
+      pericenter_argument: 40.0,
+    };
+    let spin = retrograde.rotation() * Vector3::unit_z();
+    assert!((spin + Vector3::unit_z()).magnitude() < 1e-6, "{spin:?}");
+
+    let tilted = Orientation {
+      inclination: 60.0,
+      pericenter_argument: 30.0,
+    };
+    let spin = tilted.rotation() * Vector3::unit_z();
+    assert!((spin.z - 0.5).abs() < 1e-6, "{spin:?}");
+    // the ascending node lies 30° before the pericenter on +x
+    let node = spin.cross(Vector3::unit_z()).normalize();
+    let expected = Vector3::new(30f32.to_radians().cos(), -30f32.to_radians().sin(), 0.0);
+    assert!((node - expected).magnitude() < 1e-6, "{node:?}");
+  }
+}
@@ -0,0 +1,186 @@
+use crate::{Particle, ParticleKind, SimParams};
+use cgmath::{InnerSpace, Vector3, Zero};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Dynamical friction applied to galaxy cores (black-hole particles) when they are kicked.
+/// Must match `dynamical_friction` in `shaders/compute.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Friction {
+  /// Cores feel only gravity
+  None = 0,
+  /// `-damping * (v - v_other)` towards every core of another galaxy
+  #[default]
+  Legacy = 1,
+  /// Chandrasekhar's formula with the density and velocity dispersion measured around each core
+  Chandrasekhar = 2,
+}
+
+impl Friction {
+  /// Inverse of `friction as u32`, as stored in `SimParams`.
+  #[must_use]
+  pub fn from_discriminant(discriminant: u32) -> Self {
+    match discriminant {
+      1 => Friction::Legacy,
+      2 => Friction::Chandrasekhar,
+      _ => Friction::None,
+    }
+  }
+}
+
+/// A core and the background it moves through, measured within `friction_radius`.
+#[derive(Copy, Clone, Debug)]
+pub struct Core {
+  pub index: usize,
+  pub density: f32,
+  /// One-dimensional velocity dispersion
+  pub sigma: f32,
+  pub background_velocity: Vector3<f32>,
+}
+
+/// Indices of every core, i.e. the only particles friction acts on.
+#[must_use]
+pub fn core_indices(particles: &[Particle]) -> Vec<usize> {
+  particles
+    .iter()
+    .enumerate()
+    .filter(|(_, particle)| particle.kind() == ParticleKind::BlackHole)
+    .map(|(index, _)| index)
+    .collect()
+}
+
+/// Measures the environment of the cores at `core_indices`. The background is only needed by the
+/// Chandrasekhar model, so the other modes just get the core list.
+#[must_use]
+pub fn cores(particles: &[Particle], core_indices: &[usize], sim_params: &SimParams) -> Vec<Core> {
+  let chandrasekhar = Friction::from_discriminant(sim_params.friction) == Friction::Chandrasekhar;
+  core_indices
+    .iter()
+    .map(|&index| {
+      if chandrasekhar {
+        measure(particles, index, sim_params)
+      } else {
+        Core {
+          index,
+          density: 0.0,
+          sigma: 0.0,
+          background_velocity: Vector3::zero(),
+        }
+      }
+    })
+    .collect()
+}
+
+/// Mass-weighted density, mean velocity and dispersion of the particles within
+/// `friction_radius` of the core at `index`, excluding other cores.
+fn measure(particles: &[Particle], index: usize, sim_params: &SimParams) -> Core {
+  let center = Vector3::from(particles[index].pos);
+  let radius = sim_params.friction_radius;
+  let (mass, momentum, energy) = particles
+    .par_iter()
+    .filter(|other| {
+      other.kind() != ParticleKind::BlackHole
+        && (Vector3::from(other.pos) - center).magnitude2() < radius * radius
+    })
+    .map(|other| {
+      let velocity = Vector3::from(other.vel);
+      (
+        other.mass,
+        velocity * other.mass,
+        other.mass * velocity.magnitude2(),
+      )
+    })
+    .reduce(
+      || (0.0, Vector3::zero(), 0.0),
+      |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2),
+    );
+  if mass <= 0.0 {
+    return Core {
+      index,
+      density: 0.0,
+      sigma: 0.0,
+      background_velocity: Vector3::zero(),
+    };
+  }
+  let mean = momentum / mass;
+  Core {
+    index,
+    density: mass / (4.0 / 3.0 * PI * radius * radius * radius),
+    sigma: ((energy / mass - mean.magnitude2()) / 3.0).max(0.0).sqrt(),
+    background_velocity: mean,
+  }
+}
+
+/// Friction on the particle at `index` over `dt`, given its freshly kicked `velocity`.
+#[must_use]
+pub fn apply(
+  particles: &[Particle],
+  index: usize,
+  velocity: Vector3<f32>,
+  dt: f32,
+  cores: &[Core],
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let current = particles[index];
+  if current.kind() != ParticleKind::BlackHole {
+    return velocity;
+  }
+  let mut velocity = velocity;
+  match Friction::from_discriminant(sim_params.friction) {
+    Friction::None => {}
+    Friction::Legacy => {
+      for core in cores {
+        let other = particles[core.index];
+        if other.galaxy_id != current.galaxy_id {
+          let relative_velocity = velocity - Vector3::from(other.vel);
+          let friction_force = -sim_params.damping * relative_velocity;
+          velocity += friction_force * dt;
+        }
+      }
+    }
+    Friction::Chandrasekhar => {
+      if let Some(core) = cores.iter().find(|core| core.index == index) {
+        // an explicit step can overshoot; at most bring the core to rest in its background
+        let relative = velocity - core.background_velocity;
+        let change = chandrasekhar(core, current.mass, velocity, sim_params) * dt;
+        velocity += if change.magnitude2() > relative.magnitude2() {
+          -relative
+        } else {
+          change
+        };
+      }
+    }
+  }
+  velocity
+}
+
+/// `-4π G² M ρ ln Λ [erf(X) - 2X/√π exp(-X²)] v / |v|³` with `X = |v| / (√2 σ)` and `v`
+/// relative to the background.
+fn chandrasekhar(
+  core: &Core,
+  mass: f32,
+  velocity: Vector3<f32>,
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let relative = velocity - core.background_velocity;
+  let speed = relative.magnitude();
+  if core.density <= 0.0 || speed < 0.000_001 {
+    return Vector3::zero();
+  }
+  let x = speed / (2.0f32.sqrt() * core.sigma.max(0.000_001));
+  let fraction = erf(x) - 2.0 * x / PI.sqrt() * (-x * x).exp();
+  let gravity = sim_params.gravity;
+  -relative
+    * (4.0 * PI * gravity * gravity * mass * core.density * sim_params.coulomb_log * fraction
+      / (speed * speed * speed))
+}
+
+/// Abramowitz & Stegun 7.1.26, accurate to 1.5e-7; WGSL has no built-in `erf`.
+fn erf(x: f32) -> f32 {
+  let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+  let poly = t
+    * (0.254_829_6
+      + t * (-0.284_496_74 + t * (1.421_413_8 + t * (-1.453_152_1 + t * 1.061_405_4))));
+  (1.0 - poly * (-x * x).exp()).copysign(x)
+}
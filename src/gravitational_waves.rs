@@ -0,0 +1,69 @@
+use crate::{diagnostics::to_f64, friction, regularization, Particle, SimParams};
+use cgmath::{InnerSpace, Matrix3, Vector3};
+
+/// Where the gravitational waves of the regularized binaries are observed from.
+#[derive(Copy, Clone, Debug)]
+pub struct Observer {
+  /// Polarisation basis `p`, `q` spanning the sky plane, with `p × q` towards the observer
+  p: Vector3<f64>,
+  q: Vector3<f64>,
+  distance: f64,
+}
+
+impl Observer {
+  /// An observer at `distance` in `direction`. `h+` is referred to the sky axis `n × z`, or to
+  /// the x axis when looking along z.
+  #[must_use]
+  pub fn new(direction: [f32; 3], distance: f32) -> Self {
+    let n = Vector3::new(
+      f64::from(direction[0]),
+      f64::from(direction[1]),
+      f64::from(direction[2]),
+    )
+    .normalize();
+    let p = if n.cross(Vector3::unit_z()).magnitude2() < 1e-12 {
+      Vector3::unit_x()
+    } else {
+      n.cross(Vector3::unit_z()).normalize()
+    };
+    Self {
+      p,
+      q: n.cross(p),
+      distance: f64::from(distance),
+    }
+  }
+
+  /// Quadrupole-formula strain `(h+, h×)` of every regularized binary in `particles`,
+  /// `h_ij = 2 G / (c⁴ D) d²I_ij/dt²` projected onto the sky with `d²I_ij/dt²` of the Newtonian
+  /// relative orbit.
+  #[must_use]
+  pub fn strain(&self, particles: &[Particle], sim_params: &SimParams) -> (f64, f64) {
+    let gravity = f64::from(sim_params.gravity);
+    let c = f64::from(sim_params.speed_of_light);
+    let scale = 2.0 * gravity / (c.powi(4) * self.distance);
+    regularization::find_binaries(particles, &friction::core_indices(particles), sim_params)
+      .into_iter()
+      .map(|binary| {
+        let (a, b) = (particles[binary.primary], particles[binary.secondary]);
+        let (m1, m2) = (f64::from(a.mass), f64::from(b.mass));
+        let reduced_mass = m1 * m2 / (m1 + m2);
+        let r = to_f64(b.pos) - to_f64(a.pos);
+        let v = to_f64(b.vel) - to_f64(a.vel);
+        let distance = r.magnitude();
+        // d²I/dt² = 2μ (v vᵀ - G M r rᵀ / r³)
+        let second_derivative = (outer(v, v)
+          - outer(r, r) * (gravity * (m1 + m2) / distance.powi(3)))
+          * (2.0 * reduced_mass);
+        let project = |x: Vector3<f64>, y: Vector3<f64>| x.dot(second_derivative * y);
+        (
+          0.5 * scale * (project(self.p, self.p) - project(self.q, self.q)),
+          scale * project(self.p, self.q),
+        )
+      })
+      .fold((0.0, 0.0), |sum, h| (sum.0 + h.0, sum.1 + h.1))
+  }
+}
+
+fn outer(a: Vector3<f64>, b: Vector3<f64>) -> Matrix3<f64> {
+  Matrix3::from_cols(a * b.x, a * b.y, a * b.z)
+}
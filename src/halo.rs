@@ -0,0 +1,222 @@
+use crate::{Particle, SimParams};
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Analytic dark-matter halo centred on every galaxy's central mass.
+///
+/// All profiles are scaled by `halo_velocity` (V) and `halo_radius` (r_s): the pseudo-isothermal
+/// halo has asymptotic circular speed V and core radius r_s, while NFW and Hernquist use
+/// `V² = G M / r_s` with M the NFW characteristic mass or the total Hernquist mass.
+/// Must match `halo_acceleration` in `shaders/compute.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum HaloProfile {
+  /// No external potential
+  None = 0,
+  /// `v² = V² r² / (r² + r_s²)`
+  #[default]
+  PseudoIsothermal = 1,
+  /// Navarro-Frenk-White, `ρ ∝ 1 / (x (1 + x)²)` with `x = r / r_s`
+  Nfw = 2,
+  /// `ρ ∝ 1 / (x (1 + x)³)`
+  Hernquist = 3,
+}
+
+impl HaloProfile {
+  /// Inverse of `profile as u32`, as stored in `SimParams`.
+  #[must_use]
+  pub fn from_discriminant(discriminant: u32) -> Self {
+    match discriminant {
+      1 => HaloProfile::PseudoIsothermal,
+      2 => HaloProfile::Nfw,
+      3 => HaloProfile::Hernquist,
+      _ => HaloProfile::None,
+    }
+  }
+
+  /// Squared circular speed at radius `r`.
+  #[must_use]
+  pub fn circular_speed_sq(self, r: f32, sim_params: &SimParams) -> f32 {
+    let v_sq = sim_params.halo_velocity * sim_params.halo_velocity;
+    let r_s = sim_params.halo_radius;
+    match self {
+      HaloProfile::None => 0.0,
+      HaloProfile::PseudoIsothermal => v_sq * r * r / (r * r + r_s * r_s),
+      HaloProfile::Nfw => {
+        let x = r / r_s;
+        // ln(1 + x) - x / (1 + x) cancels badly in f32 near the centre
+        let enclosed = if x < 0.01 {
+          x * x * (0.5 - 2.0 * x / 3.0)
+        } else {
+          (1.0 + x).ln() - x / (1.0 + x)
+        };
+        v_sq * enclosed / x
+      }
+      HaloProfile::Hernquist => v_sq * r_s * r / ((r + r_s) * (r + r_s)),
+    }
+  }
+
+  /// Dimensionless mass inside `x = r / r_s`, so that `M(r) = V² r_s / G * enclosed_mass(x)`.
+  /// Zero for the pseudo-isothermal halo, which has no finite-mass live counterpart.
+  #[must_use]
+  pub fn enclosed_mass(self, x: f64) -> f64 {
+    match self {
+      HaloProfile::None | HaloProfile::PseudoIsothermal => 0.0,
+      HaloProfile::Nfw => (1.0 + x).ln() - x / (1.0 + x),
+      HaloProfile::Hernquist => x * x / ((1.0 + x) * (1.0 + x)),
+    }
+  }
+
+  /// Density at `x = r / r_s` up to a constant factor.
+  #[must_use]
+  pub fn density_shape(self, x: f64) -> f64 {
+    match self {
+      HaloProfile::None => 0.0,
+      HaloProfile::PseudoIsothermal => 1.0 / (1.0 + x * x),
+      HaloProfile::Nfw => 1.0 / (x * (1.0 + x) * (1.0 + x)),
+      HaloProfile::Hernquist => 1.0 / (x * (1.0 + x).powi(3)),
+    }
+  }
+
+  /// Potential per unit mass at radius `r`, zero at infinity except for the pseudo-isothermal
+  /// halo whose logarithmic potential is zero at `r = 0`.
+  #[must_use]
+  pub fn potential(self, r: f32, sim_params: &SimParams) -> f32 {
+    let v_sq = sim_params.halo_velocity * sim_params.halo_velocity;
+    let r_s = sim_params.halo_radius;
+    match self {
+      HaloProfile::None => 0.0,
+      HaloProfile::PseudoIsothermal => 0.5 * v_sq * (1.0 + (r * r) / (r_s * r_s)).ln(),
+      HaloProfile::Nfw => {
+        let x = r / r_s;
+        if x < 0.01 {
+          -v_sq * (1.0 - x / 2.0)
+        } else {
+          -v_sq * (1.0 + x).ln() / x
+        }
+      }
+      HaloProfile::Hernquist => -v_sq * r_s / (r + r_s),
+    }
+  }
+}
+
+/// Where the halo of one galaxy sits and how it is scaled. Must match `GalaxyHalo` in
+/// `shaders/compute.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GalaxyHalo {
+  /// Index of the galaxy's central mass, which `create_galaxies` places first in its block
+  pub core: u32,
+  pub velocity: f32,
+  pub radius: f32,
+  _pad: u32,
+}
+
+impl GalaxyHalo {
+  #[must_use]
+  pub const fn new(core: u32, velocity: f32, radius: f32) -> Self {
+    Self {
+      core,
+      velocity,
+      radius,
+      _pad: 0,
+    }
+  }
+}
+
+/// Centre of galaxy `galaxy`, i.e. the position of its central mass, and `sim_params` with the
+/// scale of its halo.
+fn galaxy_halo(
+  particles: &[Particle],
+  galaxy: u32,
+  sim_params: &SimParams,
+) -> (Vector3<f32>, SimParams) {
+  let halo = sim_params.galaxies[galaxy as usize];
+  let scaled = SimParams {
+    halo_velocity: halo.velocity,
+    halo_radius: halo.radius,
+    ..*sim_params
+  };
+  (Vector3::from(particles[halo.core as usize].pos), scaled)
+}
+
+/// Acceleration at `position` from the halos of every galaxy in `particles`.
+#[must_use]
+pub fn acceleration(
+  particles: &[Particle],
+  position: Vector3<f32>,
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let profile = HaloProfile::from_discriminant(sim_params.halo_profile);
+  if profile == HaloProfile::None {
+    return Vector3::zero();
+  }
+  (0..sim_params.num_galaxies)
+    .map(|galaxy| {
+      let (center, scaled) = galaxy_halo(particles, galaxy, sim_params);
+      let offset = position - center;
+      let r = offset.magnitude();
+      if r < 0.000_001 {
+        return Vector3::zero();
+      }
+      -offset * (profile.circular_speed_sq(r, &scaled) / (r * r))
+    })
+    .sum()
+}
+
+/// Potential energy of `particles` in the halos of every galaxy, leaving out each central mass in
+/// its own halo since that term is constant.
+#[must_use]
+pub fn potential_energy(particles: &[Particle], sim_params: &SimParams) -> f64 {
+  let profile = HaloProfile::from_discriminant(sim_params.halo_profile);
+  if profile == HaloProfile::None {
+    return 0.0;
+  }
+  let halos: Vec<(Vector3<f32>, SimParams)> = (0..sim_params.num_galaxies)
+    .map(|galaxy| galaxy_halo(particles, galaxy, sim_params))
+    .collect();
+  particles
+    .iter()
+    .map(|particle| {
+      let position = Vector3::from(particle.pos);
+      halos
+        .iter()
+        .map(|(center, scaled)| {
+          let r = (position - center).magnitude();
+          if r < 0.000_001 {
+            return 0.0;
+          }
+          f64::from(particle.mass) * f64::from(profile.potential(r, scaled))
+        })
+        .sum::<f64>()
+    })
+    .sum()
+}
+
+/// Virial `Σ m (r - c) · a` of `particles` in the halos of every galaxy, each measured from the
+/// halo's centre `c`. For a spherical halo this is `-Σ m v_c²(r)`.
+#[must_use]
+pub fn virial(particles: &[Particle], sim_params: &SimParams) -> f64 {
+  let profile = HaloProfile::from_discriminant(sim_params.halo_profile);
+  if profile == HaloProfile::None {
+    return 0.0;
+  }
+  let halos: Vec<(Vector3<f32>, SimParams)> = (0..sim_params.num_galaxies)
+    .map(|galaxy| galaxy_halo(particles, galaxy, sim_params))
+    .collect();
+  particles
+    .iter()
+    .map(|particle| {
+      let position = Vector3::from(particle.pos);
+      halos
+        .iter()
+        .map(|(center, scaled)| {
+          let r = (position - center).magnitude();
+          if r < 0.000_001 {
+            return 0.0;
+          }
+          -f64::from(particle.mass) * f64::from(profile.circular_speed_sq(r, scaled))
+        })
+        .sum::<f64>()
+    })
+    .sum()
+}
@@ -1,17 +1,125 @@
-use crate::{Particle, SimParams};
-use cgmath::{InnerSpace, Vector3};
+use crate::{
+  disk::{DiskProfile, EquilibriumDisk, Pattern},
+  eddington::{self, Eddington},
+  halo::HaloProfile,
+  particle_mesh::fft3,
+  sph,
+  sphere::{Sphere, SphereProfile},
+  star_formation, zeldovich, Galaxy, Particle, ParticleKind, RunConfig, SimParams,
+};
+use cgmath::{InnerSpace, Vector3, Zero};
 use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use rustfft::{num_complex::Complex, FftPlanner};
 use std::f32::consts::PI;
 
+/// Live halos are cut off at this many scale radii
+const HALO_TRUNCATION: f64 = 10.0;
+/// Bars of barred galaxies reach this many disk scale radii
+const BAR_LENGTH: f32 = 1.5;
+
+/// Which generator `create_galaxies` builds the initial particles with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum InitialConditions {
+  /// Disk and bulge galaxies around central masses, see `elliptical`
+  #[default]
+  Elliptical = 0,
+  /// Cosmological dark matter lattice, see `zeldovich.rs`
+  Zeldovich = 1,
+  /// Rotating spheres of stars and gas that collapse into disks, see `cloud`
+  Cloud = 2,
+  /// Plummer star clusters in equilibrium, see `sphere`
+  Plummer = 3,
+  /// Hernquist star clusters in equilibrium, see `sphere`
+  Hernquist = 4,
+  /// `elliptical` galaxies with grand-design spiral arms, see `Pattern`
+  Spiral = 5,
+  /// `elliptical` galaxies with a bar and spiral arms from its ends, see `Pattern`
+  Barred = 6,
+}
+
+impl InitialConditions {
+  /// Central mass of a galaxy unless one is given: none for the star clusters, which are then
+  /// the bare Plummer and Hernquist profiles.
+  #[must_use]
+  pub fn default_central_mass(self) -> f32 {
+    match self {
+      InitialConditions::Plummer | InitialConditions::Hernquist => 0.0,
+      _ => SimParams::default().central_mass,
+    }
+  }
+}
+
+/// Bulge model of `elliptical` galaxies.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Bulge {
+  /// Exponential radii with uniform random velocities; not in equilibrium
+  #[default]
+  Legacy = 0,
+  /// Plummer sphere with velocities from its distribution function
+  Plummer = 1,
+  /// Hernquist sphere with velocities from its distribution function
+  Hernquist = 2,
+}
+
+impl Bulge {
+  #[must_use]
+  pub fn profile(self) -> Option<SphereProfile> {
+    match self {
+      Bulge::Legacy => None,
+      Bulge::Plummer => Some(SphereProfile::Plummer),
+      Bulge::Hernquist => Some(SphereProfile::Hernquist),
+    }
+  }
+}
+
+/// Disk model of `elliptical` galaxies.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Disk {
+  /// Exponential radii with uniform random velocities; not in equilibrium
+  #[default]
+  Legacy = 0,
+  /// Exponential disk with dispersions set by the Toomre Q, see `disk.rs`
+  Exponential = 1,
+  /// Miyamoto-Nagai disk with dispersions set by the Toomre Q, see `disk.rs`
+  MiyamotoNagai = 2,
+}
+
+impl Disk {
+  #[must_use]
+  pub fn profile(self) -> Option<DiskProfile> {
+    match self {
+      Disk::Legacy => None,
+      Disk::Exponential => Some(DiskProfile::Exponential),
+      Disk::MiyamotoNagai => Some(DiskProfile::MiyamotoNagai),
+    }
+  }
+}
+
 #[must_use]
-pub fn create_galaxies(sim_params: &SimParams) -> Vec<Particle> {
-  let mut rng = SmallRng::seed_from_u64(42);
+pub fn create_galaxies(sim_params: &SimParams, config: &RunConfig) -> Vec<Particle> {
+  if config.initial_conditions == InitialConditions::Zeldovich {
+    return zeldovich::create_lattice(sim_params, config);
+  }
+  let mut rng = SmallRng::seed_from_u64(u64::from(config.seed));
   let mut particles = Vec::with_capacity(sim_params.num_particles as usize);
-  for i in 0..sim_params.num_galaxies {
+  // encounters generate both galaxies at rest at the origin and place them afterwards
+  let encounter = config.encounter.filter(|_| sim_params.num_galaxies == 2);
+  let mut blocks = Vec::new();
+  for (i, galaxy) in (0..).zip(&config.galaxies) {
+    let sim_params = &sim_params.galaxy(galaxy);
     let mut center: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
     let mut velocity = Vector3::new(sim_params.galaxy_velocity, 0.0, 0.0);
-    // based on unit circle
-    if sim_params.num_galaxies > 1 {
+    if encounter.is_some() {
+      velocity = Vector3::zero();
+    } else if let Some(orbit) = galaxy.orbit {
+      center = orbit.position.into();
+      velocity = orbit.velocity.into();
+    } else if sim_params.num_galaxies > 1 {
+      // based on unit circle
       let theta = (2.0 * PI) / sim_params.num_galaxies as f32 * i as f32;
       center = Vector3::new(
         theta.sin() * sim_params.distance_between_galaxies,
@@ -24,54 +132,122 @@ pub fn create_galaxies(sim_params: &SimParams) -> Vec<Particle> {
         0.0,
       );
     }
-    println!("center: {center:?}");
-    elliptical(
+    let start = particles.len();
+    let generate = match galaxy.generator {
+      InitialConditions::Cloud => cloud,
+      InitialConditions::Plummer | InitialConditions::Hernquist => sphere,
+      _ => elliptical,
+    };
+    generate(
       &mut rng,
       &mut particles,
-      sim_params.num_particles,
-      sim_params.gravity,
+      sim_params,
+      config,
       &velocity,
       &center,
-      sim_params.central_mass,
-      sim_params.calibrate,
-      sim_params.halo_velocity,
-      sim_params.halo_radius,
       i,
     );
+    if let Some(live_halo) = LiveHalo::new(sim_params, galaxy) {
+      live_halo.populate(&mut rng, &mut particles, &velocity, &center, i);
+    }
+    blocks.push(start..particles.len());
+  }
+  if let (Some(encounter), [first, second]) = (encounter, &blocks[..]) {
+    encounter.place(
+      &mut particles,
+      [first.clone(), second.clone()],
+      sim_params.gravity,
+    );
+  }
+  for block in &blocks {
+    log::debug!("center: {:?}", Vector3::from(particles[block.start].pos));
   }
   particles
 }
 
-#[allow(clippy::too_many_arguments)]
 fn elliptical(
   rng: &mut SmallRng,
   particles: &mut Vec<Particle>,
-  num_particles: u32,
-  gravity: f32,
+  sim_params: &SimParams,
+  config: &RunConfig,
   velocity: &Vector3<f32>,
   center: &Vector3<f32>,
-  central_mass: f32,
-  softening: f32,
-  halo_velocity: f32,
-  halo_radius: f32,
   galaxy_id: u32,
 ) {
-  particles.push(Particle {
-    pos: [center.x, center.y, center.z],
-    vel: [velocity.x, velocity.y, velocity.z],
-    acc: [0.0; 3],
-    mass: central_mass,
-    galaxy_id,
-  });
+  let galaxy = &config.galaxies[galaxy_id as usize];
+  let (num_particles, gravity, central_mass, softening) = (
+    galaxy.particles - live_halo_particles(sim_params, galaxy),
+    sim_params.gravity,
+    sim_params.central_mass,
+    sim_params.calibrate,
+  );
+  let halo = host_halo(sim_params);
+  push_core(particles, sim_params, velocity, center, galaxy_id);
 
-  let bulge_fraction: f32 = 0.4;
-  let bulge_scale_radius: f32 = 0.15;
-  let disk_scale_radius: f32 = 0.3;
-  let disk_scale_height: f32 = 0.02;
+  let bulge_fraction = galaxy.bulge_fraction;
+  let bulge_scale_radius = galaxy.bulge_radius;
+  let disk_scale_radius = galaxy.disk_radius;
+  let disk_scale_height = galaxy.disk_height;
+
+  let bulge_count = (bulge_fraction * num_particles.saturating_sub(1) as f32).round() as u32;
+  // equilibrium bulges get the half-mass radius of the legacy one, b ln 2
+  let bulge = galaxy.bulge.profile().map(|profile| {
+    let scale_radius = bulge_scale_radius * 2f32.ln() / profile.radius_enclosing(0.5) as f32;
+    Sphere::new(profile, bulge_count, scale_radius, halo, sim_params)
+  });
+  let disk = galaxy.disk.profile().map(|profile| {
+    let bulge_mass = |r: f64| match &bulge {
+      Some(bulge) => bulge.enclosed_mass(r),
+      None => f64::from(bulge_count) * (1.0 - (-r / f64::from(bulge_scale_radius)).exp()),
+    };
+    EquilibriumDisk::new(
+      profile,
+      num_particles.saturating_sub(1) - bulge_count,
+      disk_scale_radius,
+      disk_scale_height,
+      galaxy.toomre_q,
+      &bulge_mass,
+      halo,
+      sim_params,
+    )
+  });
+  let pattern = match galaxy.generator {
+    InitialConditions::Spiral => Some(Pattern {
+      bar_strength: 0.0,
+      bar_radius: disk_scale_radius,
+      ..pattern(galaxy)
+    }),
+    InitialConditions::Barred => Some(Pattern {
+      bar_radius: BAR_LENGTH * disk_scale_radius,
+      ..pattern(galaxy)
+    }),
+    _ => None,
+  };
 
   // Generate particles
   for _ in 1..num_particles {
     let is_bulge = rng.gen::<f32>() < bulge_fraction;
+    // gas is a cold disk component
+    let is_gas =
+      !is_bulge && sim_params.gas_fraction > 0.0 && rng.gen::<f32>() < sim_params.gas_fraction;
+
+    if let (true, Some(bulge)) = (is_bulge, &bulge) {
+      let (r, speed) = bulge.sample(rng);
+      let pos = random_direction(rng) * r + *center;
+      let vel = random_direction(rng) * speed + *velocity;
+      particles.push(star(pos, vel, galaxy_id));
+      continue;
+    }
+    if let (false, Some(disk)) = (is_bulge, &disk) {
+      let (pos, vel) = disk.sample(rng, is_gas);
+      let (pos, vel) = match &pattern {
+        Some(pattern) => pattern.apply(rng, pos, vel),
+        None => (pos, vel),
+      };
+      let (pos, vel) = (pos + *center, vel + *velocity);
+      particles.push(disk_particle(pos, vel, galaxy_id, is_gas, sim_params));
+      continue;
+    }
 
     let pos = if is_bulge {
       // Generate bulge particle with spherical distribution
@@ -85,7 +261,7 @@ fn elliptical(
         let z = r * phi.cos();
 
         let pos = Vector3::new(x, y, z);
-        if pos.magnitude() <= 0.6 {
+        if pos.magnitude() <= 4.0 * bulge_scale_radius {
           break pos;
         }
       }
@@ -101,25 +277,25 @@ fn elliptical(
         let y = r * theta.sin();
 
         let pos = Vector3::new(x, y, z);
-        if pos.magnitude() <= 0.6 && pos.magnitude() >= 0.02 {
+        let distance = pos.magnitude();
+        if distance <= 2.0 * disk_scale_radius && distance >= disk_scale_radius / 15.0 {
           break pos;
         }
       }
     };
 
     let relative_pos = pos;
-    let final_pos = pos + *center;
 
     let vel = {
       let rotation_dir = Vector3::new(-relative_pos.y, relative_pos.x, 0.0).normalize();
       let distance = relative_pos.magnitude();
       let dist_sq = distance * distance + softening;
-      let central_speed_sq = gravity * central_mass * distance * distance / (dist_sq * dist_sq.sqrt());
-      
-      // Halo velocity contribution: v^2 = V_halo^2 * r^2 / (r^2 + R_c^2)
-      let halo_dist_sq = distance * distance + halo_radius * halo_radius;
-      let halo_speed_sq = (halo_velocity * halo_velocity * distance * distance) / halo_dist_sq;
-      
+      let central_speed_sq =
+        gravity * central_mass * distance * distance / (dist_sq * dist_sq.sqrt());
+
+      // Halo velocity contribution from the same profile the compute pass applies
+      let halo_speed_sq = halo.circular_speed_sq(distance, sim_params);
+
       let rotation_speed = (central_speed_sq + halo_speed_sq).sqrt();
       // Add more random motion for bulge particles
       let variation = if is_bulge {
@@ -128,6 +304,12 @@ fn elliptical(
           rng.gen::<f32>() * 0.15 - 0.075,
           rng.gen::<f32>() * 0.15 - 0.075,
         )
+      } else if is_gas {
+        Vector3::new(
+          rng.gen::<f32>() * 0.02 - 0.01,
+          rng.gen::<f32>() * 0.02 - 0.01,
+          rng.gen::<f32>() * 0.004 - 0.002,
+        )
       } else {
         Vector3::new(
           rng.gen::<f32>() * 0.05 - 0.025,
@@ -136,17 +318,407 @@ fn elliptical(
         )
       };
 
-      rotation_dir * rotation_speed + variation + velocity
+      rotation_dir * rotation_speed + variation
+    };
+    let (pos, vel) = match (&pattern, is_bulge) {
+      (Some(pattern), false) => pattern.apply(rng, pos, vel),
+      _ => (pos, vel),
     };
 
-    let mass = 1.0;
+    let (pos, vel) = (pos + *center, vel + *velocity);
+    particles.push(disk_particle(pos, vel, galaxy_id, is_gas, sim_params));
+  }
+}
+
+/// Spiral arms and bar of `galaxy`, with the bar radius still to be filled in.
+fn pattern(galaxy: &Galaxy) -> Pattern {
+  Pattern {
+    arms: galaxy.arms,
+    pitch_angle: galaxy.pitch_angle.to_radians(),
+    arm_strength: galaxy.arm_strength,
+    bar_strength: galaxy.bar_strength,
+    bar_radius: 0.0,
+  }
+}
+
+/// A unit-mass disk particle, gas at the initial temperature or a star of the initial population.
+fn disk_particle(
+  pos: Vector3<f32>,
+  vel: Vector3<f32>,
+  galaxy_id: u32,
+  is_gas: bool,
+  sim_params: &SimParams,
+) -> Particle {
+  if !is_gas {
+    return star(pos, vel, galaxy_id);
+  }
+  Particle {
+    kind: ParticleKind::Gas as u32,
+    internal_energy: sph::initial_internal_energy(sim_params),
+    ..star(pos, vel, galaxy_id)
+  }
+}
+
+/// Uniform sphere of stars and gas of radius `cloud_radius` around the central mass, which
+/// collapses and settles into a rotating disk.
+///
+/// The cloud turns as a solid body about z with spin parameter `λ' = J / (√2 M V R)` (Bullock et
+/// al. 2001, `V` the circular speed at the edge `R`), and random motions bring the virial ratio
+/// `2T / |W|` up to `virial_ratio`, with `W = -Σ m v_c²` in the gravity of the central mass, the
+/// cloud and the halo. The random motions are isotropic and uncorrelated, or with `turbulent` a
+/// Gaussian random field with the `P(k) ∝ k⁻⁴` spectrum of supersonic turbulence.
+fn cloud(
+  rng: &mut SmallRng,
+  particles: &mut Vec<Particle>,
+  sim_params: &SimParams,
+  config: &RunConfig,
+  velocity: &Vector3<f32>,
+  center: &Vector3<f32>,
+  galaxy_id: u32,
+) {
+  let galaxy = &config.galaxies[galaxy_id as usize];
+  let count = (galaxy.particles - live_halo_particles(sim_params, galaxy)).saturating_sub(1);
+  let radius = galaxy.cloud_radius;
+  let halo = host_halo(sim_params);
+  push_core(particles, sim_params, velocity, center, galaxy_id);
+  if count == 0 {
+    return;
+  }
+
+  let mass = 1.0;
+  let cloud_mass = count as f32 * mass;
+  let circular_speed_sq = |r: f32| {
+    let dist_sq = r * r + sim_params.calibrate;
+    let central = sim_params.gravity * sim_params.central_mass * r * r / (dist_sq * dist_sq.sqrt());
+    let cloud = sim_params.gravity * cloud_mass * r * r / radius.powi(3);
+    central + cloud + halo.circular_speed_sq(r, sim_params)
+  };
+  let positions: Vec<Vector3<f32>> = (0..count)
+    .map(|_| random_direction(rng) * radius * rng.gen::<f32>().cbrt())
+    .collect();
+
+  // solid-body rotation carrying J = λ' √2 M V R
+  let inertia: f32 = positions
+    .iter()
+    .map(|pos| mass * (pos.x * pos.x + pos.y * pos.y))
+    .sum();
+  let edge_speed = circular_speed_sq(radius).sqrt();
+  let angular_momentum = config.spin_parameter * 2f32.sqrt() * cloud_mass * edge_speed * radius;
+  let omega = angular_momentum / inertia.max(f32::EPSILON);
+  let rotational_energy = 0.5 * omega * omega * inertia;
+  let potential_energy: f32 = positions
+    .iter()
+    .map(|pos| mass * circular_speed_sq(pos.magnitude()))
+    .sum();
+  let random_energy = 0.5 * config.virial_ratio * potential_energy - rotational_energy;
+  if random_energy < 0.0 {
+    log::info!(
+      "cloud rotation alone gives a virial ratio of {:.3}; no random motions added",
+      2.0 * rotational_energy / potential_energy
+    );
+  }
+
+  let mut random_velocities = if config.turbulent {
+    turbulent_velocities(rng, &positions, 2.0 * radius)
+  } else {
+    (0..count)
+      .map(|_| {
+        Vector3::new(
+          rng.sample(StandardNormal),
+          rng.sample(StandardNormal),
+          rng.sample(StandardNormal),
+        )
+      })
+      .collect()
+  };
+  let mean = random_velocities
+    .iter()
+    .fold(Vector3::zero(), |sum, v| sum + *v)
+    / count as f32;
+  let energy: f32 = random_velocities
+    .iter()
+    .map(|v| 0.5 * mass * (*v - mean).magnitude2())
+    .sum();
+  let scale = (random_energy.max(0.0) / energy.max(f32::MIN_POSITIVE)).sqrt();
+  for v in &mut random_velocities {
+    *v = (*v - mean) * scale;
+  }
 
+  for (pos, random_velocity) in positions.iter().zip(random_velocities) {
+    let is_gas = sim_params.gas_fraction > 0.0 && rng.gen::<f32>() < sim_params.gas_fraction;
+    let (kind, internal_energy) = if is_gas {
+      (ParticleKind::Gas, sph::initial_internal_energy(sim_params))
+    } else {
+      (ParticleKind::Star, 0.0)
+    };
+    let final_pos = *pos + *center;
+    let vel = Vector3::new(-pos.y, pos.x, 0.0) * omega + random_velocity + *velocity;
     particles.push(Particle {
       pos: [final_pos.x, final_pos.y, final_pos.z],
       vel: [vel.x, vel.y, vel.z],
       acc: [0.0; 3],
       mass,
       galaxy_id,
+      kind: kind as u32,
+      density: 0.0,
+      internal_energy,
+      energy_rate: 0.0,
+      formation_time: star_formation::INITIAL_POPULATION,
     });
   }
 }
+
+/// Isotropic Plummer or Hernquist star cluster of scale radius `sphere_radius` around the central
+/// mass, by default massless, with velocities from the distribution function (see `sphere.rs`).
+/// Useful as a test cluster: it should keep its shape indefinitely.
+fn sphere(
+  rng: &mut SmallRng,
+  particles: &mut Vec<Particle>,
+  sim_params: &SimParams,
+  config: &RunConfig,
+  velocity: &Vector3<f32>,
+  center: &Vector3<f32>,
+  galaxy_id: u32,
+) {
+  let galaxy = &config.galaxies[galaxy_id as usize];
+  let count = (galaxy.particles - live_halo_particles(sim_params, galaxy)).saturating_sub(1);
+  let profile = match galaxy.generator {
+    InitialConditions::Hernquist => SphereProfile::Hernquist,
+    _ => SphereProfile::Plummer,
+  };
+  push_core(particles, sim_params, velocity, center, galaxy_id);
+  let sampler = Sphere::new(
+    profile,
+    count,
+    galaxy.sphere_radius,
+    host_halo(sim_params),
+    sim_params,
+  );
+  for _ in 0..count {
+    let (r, speed) = sampler.sample(rng);
+    let pos = random_direction(rng) * r + *center;
+    let vel = random_direction(rng) * speed + *velocity;
+    particles.push(star(pos, vel, galaxy_id));
+  }
+}
+
+/// A unit-mass star of the initial population.
+fn star(pos: Vector3<f32>, vel: Vector3<f32>, galaxy_id: u32) -> Particle {
+  Particle {
+    pos: pos.into(),
+    vel: vel.into(),
+    acc: [0.0; 3],
+    mass: 1.0,
+    galaxy_id,
+    kind: ParticleKind::Star as u32,
+    density: 0.0,
+    internal_energy: 0.0,
+    energy_rate: 0.0,
+    formation_time: star_formation::INITIAL_POPULATION,
+  }
+}
+
+/// Gaussian random velocity field with `P(k) ∝ k⁻⁴` in each component, on a periodic grid of
+/// side `extent` centred on the origin, interpolated trilinearly at `positions`. Unnormalised.
+fn turbulent_velocities(
+  rng: &mut SmallRng,
+  positions: &[Vector3<f32>],
+  extent: f32,
+) -> Vec<Vector3<f32>> {
+  const N: usize = 32;
+  let mut planner = FftPlanner::new();
+  let forward = planner.plan_fft_forward(N);
+  let inverse = planner.plan_fft_inverse(N);
+  let wavenumber = |d: usize| {
+    if d > N / 2 {
+      d as f32 - N as f32
+    } else {
+      d as f32
+    }
+  };
+  let fields: Vec<Vec<f32>> = (0..3)
+    .map(|_| {
+      let mut field: Vec<Complex<f32>> = (0..N * N * N)
+        .map(|_| Complex::new(rng.sample(StandardNormal), 0.0))
+        .collect();
+      fft3(&mut field, N, &forward);
+      for (i, value) in field.iter_mut().enumerate() {
+        let k_sq = [i % N, i / N % N, i / (N * N)]
+          .map(wavenumber)
+          .iter()
+          .map(|k| k * k)
+          .sum::<f32>();
+        // sqrt(P) = k⁻²
+        *value *= if i == 0 { 0.0 } else { 1.0 / k_sq };
+      }
+      fft3(&mut field, N, &inverse);
+      field.iter().map(|value| value.re).collect()
+    })
+    .collect();
+
+  positions
+    .iter()
+    .map(|pos| {
+      let grid = pos.map(|x| (x / extent + 0.5) * N as f32);
+      let mut velocity = Vector3::zero();
+      for corner in 0..8 {
+        let mut index = 0;
+        let mut weight = 1.0;
+        for axis in (0..3).rev() {
+          let cell = grid[axis].floor();
+          let fraction = grid[axis] - cell;
+          let upper = corner >> axis & 1 == 1;
+          let cell = (cell as i64 + i64::from(upper)).rem_euclid(N as i64) as usize;
+          index = index * N + cell;
+          weight *= if upper { fraction } else { 1.0 - fraction };
+        }
+        velocity += Vector3::new(fields[0][index], fields[1][index], fields[2][index]) * weight;
+      }
+      velocity
+    })
+    .collect()
+}
+
+/// Pushes the central mass of a galaxy.
+fn push_core(
+  particles: &mut Vec<Particle>,
+  sim_params: &SimParams,
+  velocity: &Vector3<f32>,
+  center: &Vector3<f32>,
+  galaxy_id: u32,
+) {
+  particles.push(Particle {
+    pos: [center.x, center.y, center.z],
+    vel: [velocity.x, velocity.y, velocity.z],
+    acc: [0.0; 3],
+    mass: sim_params.central_mass,
+    galaxy_id,
+    kind: ParticleKind::BlackHole as u32,
+    density: 0.0,
+    internal_energy: 0.0,
+    energy_rate: 0.0,
+    formation_time: star_formation::INITIAL_POPULATION,
+  });
+}
+
+/// Halo the baryons orbit in: the live halo if there is one, otherwise the analytic one.
+fn host_halo(sim_params: &SimParams) -> HaloProfile {
+  match HaloProfile::from_discriminant(sim_params.live_halo) {
+    HaloProfile::None => HaloProfile::from_discriminant(sim_params.halo_profile),
+    live => live,
+  }
+}
+
+/// Live halo particles of `galaxy`, leaving at least the central mass.
+fn live_halo_particles(sim_params: &SimParams, galaxy: &Galaxy) -> u32 {
+  match HaloProfile::from_discriminant(sim_params.live_halo) {
+    HaloProfile::None | HaloProfile::PseudoIsothermal => 0,
+    _ => galaxy
+      .live_halo_particles
+      .min(galaxy.particles.saturating_sub(1)),
+  }
+}
+
+/// NFW or Hernquist halo of particles in equilibrium with itself and the central mass.
+///
+/// Uses the same `halo_velocity` and `halo_radius` scaling as the analytic halo, truncated at
+/// `HALO_TRUNCATION` scale radii. Velocities are isotropic and drawn from the distribution
+/// function of the untruncated profile in the potential of the halo plus the softened central
+/// mass; the disk's own gravity is ignored.
+struct LiveHalo {
+  profile: HaloProfile,
+  sim_params: SimParams,
+  count: u32,
+  particle_mass: f32,
+  eddington: Eddington,
+}
+
+impl LiveHalo {
+  fn new(sim_params: &SimParams, galaxy: &Galaxy) -> Option<Self> {
+    let profile = HaloProfile::from_discriminant(sim_params.live_halo);
+    let count = live_halo_particles(sim_params, galaxy);
+    if count == 0 {
+      return None;
+    }
+
+    let scale_radius = f64::from(sim_params.halo_radius);
+    let v_sq = f64::from(sim_params.halo_velocity).powi(2);
+    let total_mass =
+      v_sq * scale_radius / f64::from(sim_params.gravity) * profile.enclosed_mass(HALO_TRUNCATION);
+
+    let radii = eddington::log_radii(scale_radius);
+    let density: Vec<f64> = radii
+      .iter()
+      .map(|r| profile.density_shape(r / scale_radius))
+      .collect();
+    let psi: Vec<f64> = radii
+      .iter()
+      .map(|&r| relative_potential(profile, sim_params, r))
+      .collect();
+
+    Some(Self {
+      profile,
+      sim_params: *sim_params,
+      count,
+      particle_mass: (total_mass / f64::from(count)) as f32,
+      eddington: Eddington::new(&radii, &density, &psi),
+    })
+  }
+
+  fn populate(
+    &self,
+    rng: &mut SmallRng,
+    particles: &mut Vec<Particle>,
+    velocity: &Vector3<f32>,
+    center: &Vector3<f32>,
+    galaxy_id: u32,
+  ) {
+    let scale_radius = f64::from(self.sim_params.halo_radius);
+    let enclosed_max = self.profile.enclosed_mass(HALO_TRUNCATION);
+    for _ in 0..self.count {
+      // invert the enclosed mass by bisection
+      let target = rng.gen::<f64>() * enclosed_max;
+      let (mut lo, mut hi) = (0.0, HALO_TRUNCATION);
+      for _ in 0..50 {
+        let mid = 0.5 * (lo + hi);
+        if self.profile.enclosed_mass(mid) < target {
+          lo = mid;
+        } else {
+          hi = mid;
+        }
+      }
+      let r = 0.5 * (lo + hi) * scale_radius;
+      let psi = relative_potential(self.profile, &self.sim_params, r);
+      let speed = self.eddington.sample_speed(rng, psi);
+
+      let pos = random_direction(rng) * r as f32 + *center;
+      let vel = random_direction(rng) * speed as f32 + *velocity;
+      particles.push(Particle {
+        pos: [pos.x, pos.y, pos.z],
+        vel: [vel.x, vel.y, vel.z],
+        acc: [0.0; 3],
+        mass: self.particle_mass,
+        galaxy_id,
+        kind: ParticleKind::DarkMatter as u32,
+        density: 0.0,
+        internal_energy: 0.0,
+        energy_rate: 0.0,
+        formation_time: star_formation::INITIAL_POPULATION,
+      });
+    }
+  }
+}
+
+/// `-Φ` of the halo plus the Plummer-softened central mass at radius `r`.
+fn relative_potential(profile: HaloProfile, sim_params: &SimParams, r: f64) -> f64 {
+  let central = f64::from(sim_params.gravity) * f64::from(sim_params.central_mass)
+    / (r * r + f64::from(sim_params.calibrate)).sqrt();
+  central - f64::from(profile.potential(r as f32, sim_params))
+}
+
+fn random_direction(rng: &mut SmallRng) -> Vector3<f32> {
+  let theta = rng.gen::<f32>() * 2.0 * PI;
+  let cos_phi = 2.0 * rng.gen::<f32>() - 1.0;
+  let sin_phi = (1.0 - cos_phi * cos_phi).sqrt();
+  Vector3::new(sin_phi * theta.cos(), sin_phi * theta.sin(), cos_phi)
+}
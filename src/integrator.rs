@@ -0,0 +1,66 @@
+/// Time integration scheme, expressed as a sequence of [`Stage`]s per step.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Integrator {
+  /// First order: drift and kick with the forces at the start of the step
+  Euler,
+  /// Second order kick-drift-kick leapfrog
+  #[default]
+  Leapfrog,
+  /// Second order velocity Verlet; the opening half kick and the drift share one pass
+  VelocityVerlet,
+  /// Fourth order Yoshida / Forest-Ruth composition of three leapfrog steps
+  Yoshida,
+}
+
+/// One pass over every particle. Coefficients are fractions of `delta_t`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stage {
+  /// Recompute `acc` from the current positions
+  Force,
+  /// `vel += c * dt * acc`, plus dynamical friction over the same interval
+  Kick(f32),
+  /// `pos += c * dt * vel`
+  Drift(f32),
+  /// Half kick followed by a full drift with the kicked velocity
+  VerletDrift,
+}
+
+impl Integrator {
+  #[must_use]
+  pub fn stages(self) -> Vec<Stage> {
+    match self {
+      Integrator::Euler => vec![Stage::Force, Stage::Drift(1.0), Stage::Kick(1.0)],
+      Integrator::Leapfrog => vec![
+        Stage::Kick(0.5),
+        Stage::Drift(1.0),
+        Stage::Force,
+        Stage::Kick(0.5),
+      ],
+      Integrator::VelocityVerlet => vec![Stage::VerletDrift, Stage::Force, Stage::Kick(0.5)],
+      Integrator::Yoshida => {
+        let cbrt_2 = 2.0f32.cbrt();
+        let w1 = 1.0 / (2.0 - cbrt_2);
+        let w0 = -cbrt_2 / (2.0 - cbrt_2);
+        vec![
+          Stage::Kick(w1 / 2.0),
+          Stage::Drift(w1),
+          Stage::Force,
+          Stage::Kick((w1 + w0) / 2.0),
+          Stage::Drift(w0),
+          Stage::Force,
+          Stage::Kick((w0 + w1) / 2.0),
+          Stage::Drift(w1),
+          Stage::Force,
+          Stage::Kick(w1 / 2.0),
+        ]
+      }
+    }
+  }
+
+  /// Whether the first stage kicks with `acc`, which must then be evaluated before the first step
+  /// instead of starting at zero.
+  #[must_use]
+  pub fn needs_initial_force(self) -> bool {
+    self != Integrator::Euler
+  }
+}
@@ -1,27 +1,117 @@
+pub mod barnes_hut;
+pub mod block_timestep;
 pub mod camera;
+pub mod cosmology;
+pub mod cpu;
+pub mod diagnostics;
+pub mod disk;
+pub mod eddington;
+pub mod encounter;
+pub mod friction;
+pub mod gravitational_waves;
+pub mod halo;
 pub mod initialize;
+pub mod integrator;
+pub mod particle_mesh;
+pub mod post_newtonian;
+pub mod regularization;
 pub mod render;
+pub mod scenario;
+pub mod sink;
+pub mod sph;
+pub mod sphere;
+pub mod star_formation;
 pub mod state;
+pub mod zeldovich;
 
+use block_timestep::BlockTimesteps;
+use cosmology::Cosmology;
+use encounter::Encounter;
+use friction::Friction;
+use halo::{GalaxyHalo, HaloProfile};
+use initialize::{Bulge, Disk, InitialConditions};
+use integrator::Integrator;
+use post_newtonian::PostNewtonian;
+use sph::Eos;
+use std::path::PathBuf;
+use zeldovich::PowerSpectrum;
+
+/// Galaxies `SimParams` has room for. Must match `MAX_GALAXIES` in `shaders/compute.wgsl`.
+pub const MAX_GALAXIES: usize = 16;
+
+/// Simulation parameters, uploaded as a uniform to the shaders.
+///
+/// The central mass and halo fields hold the defaults; `create_galaxies` hands each generator a
+/// copy with the galaxy's own values (see `SimParams::galaxy`) and the rest of its `Galaxy`.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct SimParams {
   delta_t: f32,
   gravity: f32,
   calibrate: f32,
+  /// Mass of a galaxy's central black hole
   central_mass: f32,
+  /// Particles in all galaxies
   num_particles: u32,
   particles_per_group: u32,
   triangle_size: f32,
   num_galaxies: u32,
   distance_between_galaxies: f32,
   galaxy_velocity: f32,
+  /// Velocity scale of a galaxy's halo; see `HaloProfile`
   halo_velocity: f32,
+  /// Scale radius of a galaxy's halo
   halo_radius: f32,
   damping: f32,
   time: f32,
+  /// `HaloProfile` discriminant
+  halo_profile: u32,
+  /// `HaloProfile` discriminant of the live halo particles added to each galaxy
+  live_halo: u32,
+  /// `Friction` discriminant
+  friction: u32,
+  /// ln Λ of the Chandrasekhar friction model
+  coulomb_log: f32,
+  /// Radius around each core in which the friction background is measured
+  friction_radius: f32,
+  /// Fraction of each disk made of SPH gas particles
+  gas_fraction: f32,
+  /// SPH smoothing length `h`; the kernel reaches `2h`
+  smoothing_length: f32,
+  /// Isothermal sound speed, or the initial one of adiabatic gas
+  sound_speed: f32,
+  /// `Eos` discriminant
+  eos: u32,
+  /// Monaghan artificial viscosity `alpha`
+  viscosity_alpha: f32,
+  /// Gas density above which particles turn into stars
+  star_formation_threshold: f32,
+  /// Fraction of dense gas turned into stars per free-fall time; 0 disables star formation
+  star_formation_efficiency: f32,
+  /// Radius within which black holes swallow bound particles and merge; 0 disables sinks
+  accretion_radius: f32,
+  /// Separation below which bound core pairs are integrated in KS coordinates; 0 disables it
+  regularization_radius: f32,
+  /// `PostNewtonian` discriminant
+  post_newtonian: u32,
+  /// Speed of light in simulation units, setting the strength of post-Newtonian effects
+  speed_of_light: f32,
+  /// Side of the periodic comoving box of cosmology mode; 0 for isolated boundaries
+  box_size: f32,
+  /// Matter density parameter of cosmology mode
+  omega_matter: f32,
+  /// Cosmological constant density parameter of cosmology mode
+  omega_lambda: f32,
+  /// Redshift at time 0 in cosmology mode
+  initial_redshift: f32,
+  /// Pads `galaxies` to the 16-byte offset uniform arrays require
+  _pad: [u32; 2],
+  /// Core and halo of each galaxy, used by the halo acceleration. Must stay the last field
+  galaxies: [GalaxyHalo; MAX_GALAXIES],
 }
 
+const _: () = assert!(std::mem::offset_of!(SimParams, galaxies) % 16 == 0);
+
 impl Default for SimParams {
   fn default() -> Self {
     Self {
@@ -33,17 +123,760 @@ impl Default for SimParams {
       particles_per_group: 64,
       triangle_size: 0.002f32,
       num_galaxies: 1,
-      distance_between_galaxies: 0.9, 
-      galaxy_velocity: 0.005,      
+      distance_between_galaxies: 0.9,
+      galaxy_velocity: 0.005,
       halo_velocity: 2.0,
       halo_radius: 2.0,
-      damping: 0.1, 
+      damping: 0.1,
       time: 0.0,
+      halo_profile: HaloProfile::default() as u32,
+      live_halo: HaloProfile::None as u32,
+      friction: Friction::default() as u32,
+      coulomb_log: 3.0,
+      friction_radius: 0.1,
+      gas_fraction: 0.0,
+      smoothing_length: 0.02,
+      sound_speed: 0.05,
+      eos: Eos::default() as u32,
+      viscosity_alpha: 1.0,
+      star_formation_threshold: 100_000.0,
+      star_formation_efficiency: 0.1,
+      accretion_radius: 0.0,
+      regularization_radius: 0.0,
+      post_newtonian: PostNewtonian::default() as u32,
+      speed_of_light: 10.0,
+      box_size: 0.0,
+      omega_matter: 0.3,
+      omega_lambda: 0.7,
+      initial_redshift: 50.0,
+      _pad: [0; 2],
+      galaxies: [GalaxyHalo::new(0, 2.0, 2.0); MAX_GALAXIES],
+    }
+  }
+}
+
+impl SimParams {
+  /// These parameters with the central mass and halo of `galaxy`.
+  #[must_use]
+  pub fn galaxy(&self, galaxy: &Galaxy) -> Self {
+    Self {
+      central_mass: galaxy.central_mass,
+      halo_velocity: galaxy.halo_velocity,
+      halo_radius: galaxy.halo_radius,
+      ..*self
+    }
+  }
+}
+
+/// Properties that may differ from one galaxy to the next.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Galaxy {
+  /// Particles in the galaxy, including its central mass
+  pub particles: u32,
+  /// Mass of the central black hole
+  pub central_mass: f32,
+  /// Fraction of the stars of disk galaxies in the bulge
+  pub bulge_fraction: f32,
+  /// Scale radius of the bulge
+  pub bulge_radius: f32,
+  /// Scale radius of the disk
+  pub disk_radius: f32,
+  /// Scale height of the disk
+  pub disk_height: f32,
+  /// Velocity scale of the analytic or live halo; see `HaloProfile`
+  pub halo_velocity: f32,
+  /// Scale radius of the analytic or live halo
+  pub halo_radius: f32,
+  /// Particles given to the live halo, taken out of `particles`
+  pub live_halo_particles: u32,
+  /// Radius of a collapsing cloud
+  pub cloud_radius: f32,
+  /// Scale radius of a Plummer or Hernquist star cluster
+  pub sphere_radius: f32,
+  /// Toomre Q of an equilibrium disk
+  pub toomre_q: f32,
+  /// Number of spiral arms of a spiral or barred galaxy
+  pub arms: u32,
+  /// Pitch angle of the spiral arms in degrees
+  pub pitch_angle: f32,
+  /// Relative density amplitude of the spiral arms
+  pub arm_strength: f32,
+  /// Squeeze of the disk into the bar of a barred galaxy
+  pub bar_strength: f32,
+  /// Generator of the galaxy's particles; anything but `Zeldovich`
+  pub generator: InitialConditions,
+  /// Bulge model of an `elliptical` galaxy
+  pub bulge: Bulge,
+  /// Disk model of an `elliptical` galaxy
+  pub disk: Disk,
+  /// Starting position and velocity, replacing the placement on a circle
+  pub orbit: Option<Orbit>,
+}
+
+impl Default for Galaxy {
+  fn default() -> Self {
+    let defaults = SimParams::default();
+    Self {
+      particles: defaults.num_particles,
+      central_mass: defaults.central_mass,
+      bulge_fraction: 0.4,
+      bulge_radius: 0.15,
+      disk_radius: 0.3,
+      disk_height: 0.02,
+      halo_velocity: defaults.halo_velocity,
+      halo_radius: defaults.halo_radius,
+      live_halo_particles: Self::default_live_halo_particles(defaults.num_particles),
+      cloud_radius: 0.5,
+      sphere_radius: 0.1,
+      toomre_q: 1.5,
+      arms: 2,
+      pitch_angle: 15.0,
+      arm_strength: 0.5,
+      bar_strength: 0.5,
+      generator: InitialConditions::default(),
+      bulge: Bulge::default(),
+      disk: Disk::default(),
+      orbit: None,
+    }
+  }
+}
+
+impl Galaxy {
+  /// Live halo particles of a galaxy of `particles` particles unless chosen: half of them.
+  #[must_use]
+  pub fn default_live_halo_particles(particles: u32) -> u32 {
+    particles / 2
+  }
+}
+
+/// Where a galaxy's centre starts and how fast it moves.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Orbit {
+  pub position: [f32; 3],
+  pub velocity: [f32; 3],
+}
+
+/// Constants of the integration and the physics that no generator depends on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Physics {
+  /// Timestep
+  pub delta_t: f32,
+  /// Gravitational constant
+  pub gravity: f32,
+  /// Square of the softening length, added to every squared distance in the forces
+  pub softening: f32,
+  /// Strength of the legacy friction between galaxy cores
+  pub damping: f32,
+  /// ln Λ of the Chandrasekhar friction model
+  pub coulomb_log: f32,
+  /// Radius around each core in which the friction background is measured
+  pub friction_radius: f32,
+  /// SPH smoothing length
+  pub smoothing_length: f32,
+  /// Isothermal sound speed of the gas, or its initial one if adiabatic
+  pub sound_speed: f32,
+  /// Monaghan artificial viscosity `alpha`
+  pub viscosity_alpha: f32,
+}
+
+impl Default for Physics {
+  fn default() -> Self {
+    let defaults = SimParams::default();
+    Self {
+      delta_t: defaults.delta_t,
+      gravity: defaults.gravity,
+      softening: defaults.calibrate,
+      damping: defaults.damping,
+      coulomb_log: defaults.coulomb_log,
+      friction_radius: defaults.friction_radius,
+      smoothing_length: defaults.smoothing_length,
+      sound_speed: defaults.sound_speed,
+      viscosity_alpha: defaults.viscosity_alpha,
+    }
+  }
+}
+
+/// Where the particle update runs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+  /// `shaders/compute.wgsl` on the GPU
+  #[default]
+  Gpu,
+  /// Multi-threaded CPU port of the compute shader
+  Cpu,
+}
+
+/// Which `shaders/compute.wgsl` entry point performs direct summation on the GPU.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Kernel {
+  /// Every thread reads every source particle from global memory (`force`)
+  #[default]
+  Simple,
+  /// Source particles are staged through workgroup memory one tile at a time (`force_tiled`)
+  Tiled,
+}
+
+impl Kernel {
+  #[must_use]
+  pub fn entry_point(self) -> &'static str {
+    match self {
+      Kernel::Simple => "force",
+      Kernel::Tiled => "force_tiled",
+    }
+  }
+}
+
+/// How gravitational accelerations are evaluated.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Solver {
+  /// All-pairs summation, as in `shaders/compute.wgsl`
+  #[default]
+  Direct,
+  /// Octree with opening angle `theta`; evaluated on the host
+  BarnesHut { theta: f32 },
+  /// FFT Poisson solve on a `grid`³ mesh; evaluated on the host
+  ParticleMesh { grid: u32 },
+}
+
+impl Solver {
+  /// Opening angle of `BarnesHut` unless one is chosen
+  pub const DEFAULT_THETA: f32 = 0.5;
+  /// Mesh of `ParticleMesh` unless one is chosen
+  pub const DEFAULT_GRID: u32 = 64;
+}
+
+/// Choices whose defaults depend on the rest of the configuration, `None` where none was made.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DependentChoices {
+  pub halo: Option<HaloProfile>,
+  pub friction: Option<Friction>,
+  pub diagnostics_every: Option<u32>,
+}
+
+/// Startup options chosen on the command line.
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+  /// Galaxies to simulate, at most `MAX_GALAXIES`
+  pub galaxies: Vec<Galaxy>,
+  /// Constants of the simulation itself
+  pub physics: Physics,
+  /// Run without a window
+  pub headless: bool,
+  /// Starting view and speed of the window's camera
+  pub camera: CameraParams,
+  pub backend: Backend,
+  pub solver: Solver,
+  pub kernel: Kernel,
+  pub integrator: Integrator,
+  /// Analytic halo potential around each galaxy's central mass
+  pub halo: HaloProfile,
+  /// Live halo made of particles, or `HaloProfile::None`
+  pub live_halo: HaloProfile,
+  /// Dynamical friction model for galaxy cores
+  pub friction: Friction,
+  /// Fraction of each disk made of SPH gas particles
+  pub gas_fraction: f32,
+  pub eos: Eos,
+  /// Gas density above which stars form
+  pub star_formation_threshold: f32,
+  /// Fraction of dense gas turned into stars per free-fall time
+  pub star_formation_efficiency: f32,
+  /// CSV file the star formation history is written to when a headless run ends
+  pub sfr_output: Option<PathBuf>,
+  /// Width of the star formation history bins in simulation time
+  pub sfr_bin: f32,
+  /// Black hole accretion and merger radius; 0 keeps them as plain point masses
+  pub accretion_radius: f32,
+  /// Separation below which bound core binaries are regularized; 0 disables it
+  pub regularization_radius: f32,
+  /// Post-Newtonian order of regularized binaries
+  pub post_newtonian: PostNewtonian,
+  pub speed_of_light: f32,
+  /// CSV file the gravitational-wave strain is written to every step of a headless run
+  pub gw_output: Option<PathBuf>,
+  /// Direction from the origin towards the gravitational-wave observer
+  pub gw_observer: [f32; 3],
+  /// Distance of the gravitational-wave observer
+  pub gw_distance: f32,
+  /// Comoving integration in a periodic box; stepped on the host
+  pub cosmology: Option<Cosmology>,
+  /// Orbit and disk orientations of a two-galaxy encounter, replacing the placement on a circle
+  pub encounter: Option<Encounter>,
+  /// `Zeldovich` replaces the galaxies with a lattice; other generators are chosen per galaxy
+  pub initial_conditions: InitialConditions,
+  /// Seed of the random initial conditions
+  pub seed: u32,
+  /// Linear power spectrum of Zel'dovich initial conditions
+  pub power_spectrum: PowerSpectrum,
+  pub spectral_index: f32,
+  /// Length scale at which the CDM power spectrum turns over
+  pub turnover_scale: f32,
+  /// Rms linear density contrast of Zel'dovich initial conditions, extrapolated to redshift 0
+  pub fluctuation_amplitude: f32,
+  /// Spin parameter `λ'` of collapsing clouds
+  pub spin_parameter: f32,
+  /// Virial ratio `2T / |W|` of collapsing clouds
+  pub virial_ratio: f32,
+  /// Give collapsing clouds turbulent rather than uncorrelated random motions
+  pub turbulent: bool,
+  /// Individual power-of-two timesteps; only valid with the leapfrog integrator
+  pub block_timesteps: Option<BlockTimesteps>,
+  /// Steps between energy/momentum reports; 0 disables them
+  pub diagnostics_every: u32,
+}
+
+impl Default for RunConfig {
+  /// One galaxy in a window, with the command line's defaults.
+  fn default() -> Self {
+    let defaults = SimParams::default();
+    Self {
+      galaxies: vec![Galaxy::default()],
+      physics: Physics::default(),
+      headless: false,
+      camera: CameraParams::default(),
+      backend: Backend::default(),
+      solver: Solver::default(),
+      kernel: Kernel::default(),
+      integrator: Integrator::default(),
+      halo: HaloProfile::default(),
+      live_halo: HaloProfile::None,
+      friction: Friction::default(),
+      gas_fraction: defaults.gas_fraction,
+      eos: Eos::default(),
+      star_formation_threshold: defaults.star_formation_threshold,
+      star_formation_efficiency: defaults.star_formation_efficiency,
+      sfr_output: None,
+      sfr_bin: 0.01,
+      accretion_radius: defaults.accretion_radius,
+      regularization_radius: defaults.regularization_radius,
+      post_newtonian: PostNewtonian::default(),
+      speed_of_light: defaults.speed_of_light,
+      gw_output: None,
+      gw_observer: [0.0, 0.0, 1.0],
+      gw_distance: 1.0,
+      cosmology: None,
+      encounter: None,
+      initial_conditions: InitialConditions::default(),
+      seed: 42,
+      power_spectrum: PowerSpectrum::default(),
+      spectral_index: 1.0,
+      turnover_scale: 0.05,
+      fluctuation_amplitude: 1.0,
+      spin_parameter: 0.05,
+      virial_ratio: 0.3,
+      turbulent: false,
+      block_timesteps: None,
+      diagnostics_every: 0,
+    }
+  }
+}
+
+impl RunConfig {
+  /// Sets `choices`, and defaults for those not made given the live halo, cosmology and
+  /// headless mode already set: a pseudo-isothermal halo unless there is a live halo or a box,
+  /// legacy friction outside a box, and diagnostics every 100 steps of isolated headless runs.
+  pub fn choose(&mut self, choices: DependentChoices) {
+    let in_box = self.cosmology.is_some();
+    self.halo = choices
+      .halo
+      .unwrap_or(if self.live_halo == HaloProfile::None && !in_box {
+        HaloProfile::PseudoIsothermal
+      } else {
+        HaloProfile::None
+      });
+    self.friction = choices.friction.unwrap_or(if in_box {
+      Friction::None
+    } else {
+      Friction::Legacy
+    });
+    self.diagnostics_every = choices
+      .diagnostics_every
+      .unwrap_or(if self.headless && !in_box { 100 } else { 0 });
+  }
+
+  #[must_use]
+  pub fn sim_params(&self) -> SimParams {
+    let defaults = match self.cosmology {
+      Some(cosmology) => SimParams {
+        box_size: cosmology.box_size,
+        omega_matter: cosmology.omega_matter,
+        omega_lambda: cosmology.omega_lambda,
+        initial_redshift: cosmology.initial_redshift,
+        ..Default::default()
+      },
+      None => SimParams::default(),
+    };
+    let mut galaxies = defaults.galaxies;
+    let mut core = 0;
+    for (halo, galaxy) in galaxies.iter_mut().zip(&self.galaxies) {
+      *halo = GalaxyHalo::new(core, galaxy.halo_velocity, galaxy.halo_radius);
+      core += galaxy.particles;
+    }
+    SimParams {
+      delta_t: self.physics.delta_t,
+      gravity: self.physics.gravity,
+      calibrate: self.physics.softening,
+      damping: self.physics.damping,
+      coulomb_log: self.physics.coulomb_log,
+      friction_radius: self.physics.friction_radius,
+      smoothing_length: self.physics.smoothing_length,
+      sound_speed: self.physics.sound_speed,
+      viscosity_alpha: self.physics.viscosity_alpha,
+      num_galaxies: self.galaxies.len() as u32,
+      num_particles: core,
+      galaxies,
+      halo_profile: self.halo as u32,
+      live_halo: self.live_halo as u32,
+      friction: self.friction as u32,
+      gas_fraction: self.gas_fraction,
+      eos: self.eos as u32,
+      star_formation_threshold: self.star_formation_threshold,
+      star_formation_efficiency: self.star_formation_efficiency,
+      accretion_radius: self.accretion_radius,
+      regularization_radius: self.regularization_radius,
+      post_newtonian: self.post_newtonian as u32,
+      speed_of_light: self.speed_of_light,
+      ..defaults
+    }
+  }
+
+  /// Whether stepping needs a feature that only exists on the host, in which case the GPU
+  /// backend reads particles back and steps them on the CPU.
+  #[must_use]
+  pub fn runs_on_host(&self) -> bool {
+    self.solver != Solver::Direct
+      || self.block_timesteps.is_some()
+      || self.accretion_radius > 0.0
+      || self.regularization_radius > 0.0
+      || self.cosmology.is_some()
+  }
+
+  /// Checks what the types of the configuration cannot.
+  pub fn validate(&self) -> Result<(), InvalidConfig> {
+    let physics = &self.physics;
+    positive(Setting::DeltaT, physics.delta_t)?;
+    positive(Setting::Gravity, physics.gravity)?;
+    not_negative(Setting::Softening, physics.softening)?;
+    positive(Setting::SmoothingLength, physics.smoothing_length)?;
+    positive(Setting::SoundSpeed, physics.sound_speed)?;
+    if let Some(block) = self.block_timesteps {
+      if block.max_level > BlockTimesteps::MAX_LEVEL {
+        return Err(InvalidConfig::new(
+          Setting::BlockLevels,
+          format!("must be at most {}", BlockTimesteps::MAX_LEVEL),
+        ));
+      }
+      positive(Setting::BlockEta, block.eta)?;
+      if self.integrator != Integrator::Leapfrog {
+        return Err(InvalidConfig::new(
+          Setting::BlockLevels,
+          "needs the leapfrog integrator",
+        ));
+      }
+      let conflict = if self.gas_fraction > 0.0 {
+        Some(Setting::GasFraction)
+      } else if self.regularization_radius > 0.0 {
+        Some(Setting::RegularizationRadius)
+      } else {
+        None
+      };
+      if let Some(conflict) = conflict {
+        return Err(InvalidConfig::new(Setting::BlockLevels, "does not support").with(conflict));
+      }
+    }
+    if self.post_newtonian != PostNewtonian::None && self.regularization_radius <= 0.0 {
+      return Err(
+        InvalidConfig::new(
+          Setting::PostNewtonian,
+          "acts on regularized binaries and needs",
+        )
+        .with(Setting::RegularizationRadius),
+      );
+    }
+    if self.gw_output.is_some() && self.regularization_radius <= 0.0 {
+      return Err(
+        InvalidConfig::new(
+          Setting::GwOutput,
+          "records the strain of regularized binaries and needs",
+        )
+        .with(Setting::RegularizationRadius),
+      );
+    }
+    if self.live_halo == HaloProfile::PseudoIsothermal {
+      return Err(InvalidConfig::new(
+        Setting::LiveHalo,
+        "must be nfw or hernquist; a pseudo-isothermal halo has infinite mass",
+      ));
+    }
+    if self.live_halo != HaloProfile::None && self.halo != HaloProfile::None {
+      return Err(InvalidConfig::new(Setting::LiveHalo, "replaces").with(Setting::Halo));
+    }
+    if self.gw_observer.iter().all(|&x| x == 0.0) {
+      return Err(InvalidConfig::new(
+        Setting::GwObserver,
+        "must be a nonzero direction",
+      ));
+    }
+    positive(Setting::SfrBin, self.sfr_bin)?;
+    if let Some(cosmology) = self.cosmology {
+      if matches!(self.solver, Solver::BarnesHut { .. }) {
+        return Err(InvalidConfig::new(
+          Setting::Cosmology,
+          "does not support the barnes-hut solver",
+        ));
+      }
+      let conflict = if self.block_timesteps.is_some() {
+        Some(Setting::BlockLevels)
+      } else if self.gas_fraction > 0.0 {
+        Some(Setting::GasFraction)
+      } else if self.regularization_radius > 0.0 {
+        Some(Setting::RegularizationRadius)
+      } else if self.accretion_radius > 0.0 {
+        Some(Setting::AccretionRadius)
+      } else if self.halo != HaloProfile::None {
+        Some(Setting::Halo)
+      } else if self.friction != Friction::None {
+        Some(Setting::Friction)
+      } else {
+        None
+      };
+      if let Some(conflict) = conflict {
+        return Err(InvalidConfig::new(Setting::Cosmology, "does not support").with(conflict));
+      }
+      positive(Setting::BoxSize, cosmology.box_size)?;
+      positive(Setting::OmegaMatter, cosmology.omega_matter)?;
+      not_negative(Setting::InitialRedshift, cosmology.initial_redshift)?;
+    }
+    if self.initial_conditions == InitialConditions::Zeldovich {
+      if self.cosmology.is_none() {
+        return Err(InvalidConfig::new(
+          Setting::Cosmology,
+          "is needed by the zeldovich generator, which fills a periodic box",
+        ));
+      }
+      return positive(Setting::TurnoverScale, self.turnover_scale);
+    }
+    not_negative(Setting::SpinParameter, self.spin_parameter)?;
+    not_negative(Setting::VirialRatio, self.virial_ratio)?;
+    if self.galaxies.is_empty() || self.galaxies.len() > MAX_GALAXIES {
+      return Err(InvalidConfig::new(
+        Setting::Galaxies,
+        format!("must be between 1 and {MAX_GALAXIES}"),
+      ));
+    }
+    for (i, galaxy) in (1..).zip(&self.galaxies) {
+      validate_galaxy(galaxy).map_err(|error| error.in_galaxy(i))?;
+    }
+    if self
+      .galaxies
+      .iter()
+      .try_fold(0u32, |total, galaxy| total.checked_add(galaxy.particles))
+      .is_none()
+    {
+      return Err(InvalidConfig::new(
+        Setting::Particles,
+        "must fit in 32 bits summed over the galaxies",
+      ));
+    }
+    if let Some(encounter) = self.encounter {
+      if self.galaxies.len() != 2 {
+        return Err(
+          InvalidConfig::new(Setting::Galaxies, "must be 2 for").with(Setting::Encounter),
+        );
+      }
+      if let Some(i) = (1..)
+        .zip(&self.galaxies)
+        .find_map(|(i, galaxy)| galaxy.orbit.is_some().then_some(i))
+      {
+        return Err(
+          InvalidConfig::new(Setting::Orbit, "cannot be given with")
+            .with(Setting::Encounter)
+            .in_galaxy(i),
+        );
+      }
+      positive(Setting::Pericenter, encounter.pericenter)?;
+      positive(Setting::Separation, encounter.separation)?;
+      not_negative(Setting::Eccentricity, encounter.eccentricity)?;
     }
+    Ok(())
   }
 }
 
+/// Checks the settings of one galaxy.
+fn validate_galaxy(galaxy: &Galaxy) -> Result<(), InvalidConfig> {
+  if galaxy.generator == InitialConditions::Zeldovich {
+    return Err(InvalidConfig::new(
+      Setting::Generator,
+      "cannot be zeldovich, which replaces the galaxies",
+    ));
+  }
+  if galaxy.particles == 0 {
+    return Err(InvalidConfig::new(Setting::Particles, "must be positive"));
+  }
+  not_negative(Setting::CentralMass, galaxy.central_mass)?;
+  if !(0.0..=1.0).contains(&galaxy.bulge_fraction) {
+    return Err(InvalidConfig::new(
+      Setting::BulgeFraction,
+      "must be between 0 and 1",
+    ));
+  }
+  positive(Setting::BulgeRadius, galaxy.bulge_radius)?;
+  positive(Setting::DiskRadius, galaxy.disk_radius)?;
+  positive(Setting::DiskHeight, galaxy.disk_height)?;
+  not_negative(Setting::HaloVelocity, galaxy.halo_velocity)?;
+  positive(Setting::HaloRadius, galaxy.halo_radius)?;
+  positive(Setting::CloudRadius, galaxy.cloud_radius)?;
+  positive(Setting::SphereRadius, galaxy.sphere_radius)?;
+  positive(Setting::ToomreQ, galaxy.toomre_q)?;
+  if !(0.0..90.0).contains(&galaxy.pitch_angle) || galaxy.pitch_angle == 0.0 {
+    return Err(InvalidConfig::new(
+      Setting::PitchAngle,
+      "must be between 0 and 90 degrees",
+    ));
+  }
+  if !(0.0..=1.0).contains(&galaxy.arm_strength) {
+    return Err(InvalidConfig::new(
+      Setting::ArmStrength,
+      "must be between 0 and 1",
+    ));
+  }
+  if !(0.0..1.0).contains(&galaxy.bar_strength) {
+    return Err(InvalidConfig::new(
+      Setting::BarStrength,
+      "must be at least 0 and below 1",
+    ));
+  }
+  Ok(())
+}
+
+fn positive(setting: Setting, value: f32) -> Result<(), InvalidConfig> {
+  if value > 0.0 {
+    Ok(())
+  } else {
+    Err(InvalidConfig::new(setting, "must be positive"))
+  }
+}
+
+fn not_negative(setting: Setting, value: f32) -> Result<(), InvalidConfig> {
+  if value >= 0.0 {
+    Ok(())
+  } else {
+    Err(InvalidConfig::new(setting, "must not be negative"))
+  }
+}
+
+/// A setting of `RunConfig` that `RunConfig::validate` can reject. Each front end names it its own
+/// way: the command line by its flag, scenarios by their key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Setting {
+  DeltaT,
+  Gravity,
+  Softening,
+  SmoothingLength,
+  SoundSpeed,
+  BlockLevels,
+  BlockEta,
+  GasFraction,
+  RegularizationRadius,
+  AccretionRadius,
+  PostNewtonian,
+  Halo,
+  LiveHalo,
+  Friction,
+  GwOutput,
+  GwObserver,
+  SfrBin,
+  Theta,
+  Grid,
+  /// Cosmology mode as a whole
+  Cosmology,
+  BoxSize,
+  OmegaMatter,
+  InitialRedshift,
+  TurnoverScale,
+  SpinParameter,
+  VirialRatio,
+  /// The number of galaxies
+  Galaxies,
+  /// The encounter as a whole
+  Encounter,
+  Pericenter,
+  Separation,
+  Eccentricity,
+  /// Generator of one galaxy
+  Generator,
+  Particles,
+  CentralMass,
+  BulgeFraction,
+  BulgeRadius,
+  DiskRadius,
+  DiskHeight,
+  HaloVelocity,
+  HaloRadius,
+  CloudRadius,
+  SphereRadius,
+  ToomreQ,
+  PitchAngle,
+  ArmStrength,
+  BarStrength,
+  /// Starting position and velocity of one galaxy
+  Orbit,
+}
+
+/// Why `RunConfig::validate` rejected a configuration: `setting` followed by `problem`, which may
+/// end in a `related` setting, of galaxy `galaxy` (counted from 1) for per-galaxy settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidConfig {
+  pub setting: Setting,
+  pub problem: String,
+  pub related: Option<Setting>,
+  pub galaxy: Option<usize>,
+}
+
+impl InvalidConfig {
+  pub(crate) fn new(setting: Setting, problem: impl Into<String>) -> Self {
+    Self {
+      setting,
+      problem: problem.into(),
+      related: None,
+      galaxy: None,
+    }
+  }
+
+  fn with(self, related: Setting) -> Self {
+    Self {
+      related: Some(related),
+      ..self
+    }
+  }
+
+  fn in_galaxy(self, galaxy: usize) -> Self {
+    Self {
+      galaxy: Some(galaxy),
+      ..self
+    }
+  }
+
+  /// The message, with settings named by `name`.
+  #[must_use]
+  pub fn describe(&self, name: impl Fn(Setting) -> &'static str) -> String {
+    let mut message = format!("{} {}", name(self.setting), self.problem);
+    if let Some(related) = self.related {
+      message = format!("{message} {}", name(related));
+    }
+    if let Some(galaxy) = self.galaxy {
+      message = format!("{message} in galaxy {galaxy}");
+    }
+    message
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct CameraParams {
+  /// Where the camera starts
+  pub eye: [f32; 3],
+  /// Point the camera starts looking at
+  pub target: [f32; 3],
   pub speed: f32,
   pub rotational_speed: f32,
 }
@@ -51,6 +884,9 @@ pub struct CameraParams {
 impl Default for CameraParams {
   fn default() -> Self {
     Self {
+      // 1 unit up and 2 units back
+      eye: [0.0, 1.0, 2.0],
+      target: [0.0, 0.0, 0.0],
       speed: 0.02,
       rotational_speed: 0.02,
     }
@@ -65,4 +901,48 @@ pub struct Particle {
   pub acc: [f32; 3],
   pub mass: f32,
   pub galaxy_id: u32,
+  /// `ParticleKind` discriminant
+  pub kind: u32,
+  /// SPH density; only meaningful for gas
+  pub density: f32,
+  /// Specific internal energy of gas
+  pub internal_energy: f32,
+  /// `du/dt` of gas from the last force evaluation
+  pub energy_rate: f32,
+  /// Time a star formed from gas, or `star_formation::INITIAL_POPULATION`
+  pub formation_time: f32,
+}
+
+impl Particle {
+  #[must_use]
+  pub fn kind(&self) -> ParticleKind {
+    ParticleKind::from_discriminant(self.kind)
+  }
+}
+
+/// Species of a particle. Must match the `KIND_*` constants in the shaders.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ParticleKind {
+  Star = 0,
+  Gas = 1,
+  DarkMatter = 2,
+  /// A galaxy's central mass, and a sink if accretion is enabled
+  BlackHole = 3,
+  /// Swallowed by a black hole; massless and not drawn
+  Accreted = 4,
+}
+
+impl ParticleKind {
+  /// Inverse of `kind as u32`; unknown values are treated as stars.
+  #[must_use]
+  pub fn from_discriminant(discriminant: u32) -> Self {
+    match discriminant {
+      1 => ParticleKind::Gas,
+      2 => ParticleKind::DarkMatter,
+      3 => ParticleKind::BlackHole,
+      4 => ParticleKind::Accreted,
+      _ => ParticleKind::Star,
+    }
+  }
 }
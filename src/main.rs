@@ -1,21 +1,379 @@
 use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::{generate, Shell};
-use std::io;
+use galaxy_sim::{
+  block_timestep::BlockTimesteps,
+  cosmology::Cosmology,
+  encounter::{Encounter, Orientation},
+  friction::Friction,
+  halo::HaloProfile,
+  initialize::{Bulge, Disk, InitialConditions},
+  integrator::Integrator,
+  post_newtonian::PostNewtonian,
+  scenario,
+  sph::Eos,
+  zeldovich::PowerSpectrum,
+  Backend, CameraParams, DependentChoices, Galaxy, Kernel, Physics, RunConfig, Setting, Solver,
+};
+use std::{io, path::PathBuf};
 
 /// Galaxy simulation with N-body physics
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-  /// Number of galaxies to simulate
+  /// TOML file describing the whole simulation, replacing every other option; its tables and
+  /// keys are listed in the README
+  #[arg(long, exclusive = true)]
+  scenario: Option<PathBuf>,
+  /// Number of galaxies to simulate, at most 16: each galaxy's halo is stored in the fixed-size
+  /// parameter block shared with the shaders
   #[arg(short, long, default_value_t = 1)]
   galaxies: u32,
+  /// Number of particles per galaxy, including its central mass. Like the other per-galaxy
+  /// options, takes one value for every galaxy or a comma-separated value for each
+  #[arg(short = 'n', long, value_delimiter = ',', default_values_t = [Galaxy::default().particles])]
+  particles: Vec<u32>,
+  /// Mass of each galaxy's central black hole; defaults to 0 for the Plummer and Hernquist
+  /// clusters and 100000 otherwise
+  #[arg(long, value_delimiter = ',')]
+  central_mass: Option<Vec<f32>>,
+  /// Fraction of the stars of each disk galaxy in its bulge
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().bulge_fraction])]
+  bulge_fraction: Vec<f32>,
+  /// Scale radius of each galaxy's bulge
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().bulge_radius])]
+  bulge_radius: Vec<f32>,
+  /// Scale radius of each galaxy's disk
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().disk_radius])]
+  disk_radius: Vec<f32>,
+  /// Scale height of each galaxy's disk
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().disk_height])]
+  disk_height: Vec<f32>,
+  /// Velocity scale of each galaxy's halo: the asymptotic circular speed of a pseudo-isothermal
+  /// halo, or sqrt(G M / r_s) of NFW and Hernquist halos
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().halo_velocity])]
+  halo_velocity: Vec<f32>,
+  /// Scale radius of each galaxy's halo
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().halo_radius])]
+  halo_radius: Vec<f32>,
+  /// How the initial particles are generated
+  #[arg(long, value_enum, default_value_t = InitialConditions::Elliptical)]
+  initial_conditions: InitialConditions,
+  /// Seed of the random initial conditions
+  #[arg(long, default_value_t = 42)]
+  seed: u32,
+  /// Particles per side of the Zel'dovich lattice; replaces --galaxies and --particles
+  #[arg(long, default_value_t = 32)]
+  lattice: u32,
+  /// Linear power spectrum of the Zel'dovich initial conditions
+  #[arg(long, value_enum, default_value_t = PowerSpectrum::Cdm)]
+  power_spectrum: PowerSpectrum,
+  /// Spectral index n of the primordial power spectrum k^n
+  #[arg(long, default_value_t = 1.0)]
+  spectral_index: f32,
+  /// Length at which the CDM power spectrum turns over, in the units of --box-size
+  #[arg(long, default_value_t = 0.05)]
+  turnover_scale: f32,
+  /// Rms linear density contrast of the Zel'dovich lattice extrapolated to redshift 0; it is
+  /// scaled back to --initial-redshift with the growth factor
+  #[arg(long, default_value_t = 1.0)]
+  fluctuation_amplitude: f32,
+  /// Radius of each collapsing cloud
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().cloud_radius])]
+  cloud_radius: Vec<f32>,
+  /// Spin parameter J / (sqrt(2) M V R) of the collapsing clouds, V being the circular speed at
+  /// their edge
+  #[arg(long, default_value_t = 0.05)]
+  spin_parameter: f32,
+  /// Virial ratio 2T / |W| of the collapsing clouds; below 1 they collapse
+  #[arg(long, default_value_t = 0.3)]
+  virial_ratio: f32,
+  /// Give the collapsing clouds turbulent instead of uncorrelated random motions
+  #[arg(long, default_value_t = false)]
+  turbulent: bool,
+  /// Scale radius of each Plummer and Hernquist star cluster
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().sphere_radius])]
+  sphere_radius: Vec<f32>,
+  /// Bulge model of elliptical galaxies
+  #[arg(long, value_enum, default_value_t = Bulge::Legacy)]
+  bulge: Bulge,
+  /// Disk model of elliptical galaxies
+  #[arg(long, value_enum, default_value_t = Disk::Legacy)]
+  disk: Disk,
+  /// Toomre Q of each exponential and Miyamoto-Nagai disk, setting its radial velocity
+  /// dispersion; above 1 it is stable against axisymmetric collapse
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().toomre_q])]
+  toomre_q: Vec<f32>,
+  /// Number of spiral arms of each spiral and barred galaxy
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().arms])]
+  arms: Vec<u32>,
+  /// Pitch angle of each galaxy's spiral arms in degrees; small angles wind them tightly
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().pitch_angle])]
+  pitch_angle: Vec<f32>,
+  /// Relative density amplitude of each galaxy's spiral arms, from 0 to 1
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().arm_strength])]
+  arm_strength: Vec<f32>,
+  /// How much each barred galaxy is squeezed into a bar along x, from 0 (no bar) to below 1
+  #[arg(long, value_delimiter = ',', default_values_t = [Galaxy::default().bar_strength])]
+  bar_strength: Vec<f32>,
+  /// Pericenter distance of a Toomre-style encounter of two galaxies on a Kepler orbit in the xy
+  /// plane, replacing their placement on a circle
+  #[arg(long)]
+  pericenter: Option<f32>,
+  /// Eccentricity of the encounter orbit; 1 is parabolic
+  #[arg(long, default_value_t = 1.0)]
+  eccentricity: f32,
+  /// Initial distance between the two galaxies of an encounter
+  #[arg(long, default_value_t = 2.0)]
+  separation: f32,
+  /// Inclination of each galaxy's disk to the encounter orbit in degrees; 0 is prograde and 180
+  /// retrograde
+  #[arg(long, value_delimiter = ',', num_args = 2, default_values_t = [0.0, 0.0])]
+  inclination: Vec<f32>,
+  /// Argument of pericenter of the encounter orbit measured from each galaxy's disk, in degrees
+  #[arg(long, value_delimiter = ',', num_args = 2, default_values_t = [0.0, 0.0])]
+  pericenter_argument: Vec<f32>,
   /// Run in headless mode (no window)
   #[arg(long, default_value_t = false)]
   headless: bool,
+  /// Where to run the particle update
+  #[arg(long, value_enum, default_value_t = Backend::Gpu)]
+  backend: Backend,
+  /// GPU kernel used for direct summation
+  #[arg(long, value_enum, default_value_t = Kernel::Simple)]
+  kernel: Kernel,
+  /// Time integration scheme
+  #[arg(long, value_enum, default_value_t = Integrator::Leapfrog)]
+  integrator: Integrator,
+  /// Analytic dark-matter halo around each galaxy, scaled by the halo velocity and radius.
+  /// Defaults to pseudo-isothermal, or none with --live-halo or --box-size
+  #[arg(long, value_enum)]
+  halo: Option<HaloProfile>,
+  /// Replace the analytic halo with halo particles (nfw or hernquist)
+  #[arg(long, value_enum, default_value_t = HaloProfile::None)]
+  live_halo: HaloProfile,
+  /// Particles of each galaxy given to the live halo; defaults to half of --particles
+  #[arg(long, value_delimiter = ',')]
+  halo_particles: Option<Vec<u32>>,
+  /// Dynamical friction on galaxy cores. Defaults to legacy, or none with --box-size
+  #[arg(long, value_enum)]
+  friction: Option<Friction>,
+  /// Fraction of each disk made of SPH gas
+  #[arg(long, default_value_t = 0.0)]
+  gas_fraction: f32,
+  /// Equation of state of the gas
+  #[arg(long, value_enum, default_value_t = Eos::Isothermal)]
+  eos: Eos,
+  /// Gas density above which stars form
+  #[arg(long, default_value_t = 100_000.0)]
+  star_formation_threshold: f32,
+  /// Fraction of dense gas turned into stars per free-fall time; 0 disables star formation
+  #[arg(long, default_value_t = 0.1)]
+  star_formation_efficiency: f32,
+  /// Write the star formation rate history to this CSV file when a headless run ends
+  #[arg(long)]
+  sfr_output: Option<PathBuf>,
+  /// Width of the star formation history bins in simulation time
+  #[arg(long, default_value_t = 0.01)]
+  sfr_bin: f32,
+  /// Radius within which black holes accrete bound particles and merge with each other; 0
+  /// disables sinks. Sinks are stepped on the host
+  #[arg(long, default_value_t = 0.0)]
+  accretion_radius: f32,
+  /// Separation below which bound pairs of galaxy cores are integrated exactly in regularized
+  /// (KS) coordinates, without softening; 0 disables it. Stepped on the host
+  #[arg(long, default_value_t = 0.0)]
+  regularization_radius: f32,
+  /// Post-Newtonian corrections to the orbit of regularized core binaries, up to radiation
+  /// reaction (2.5pn), which makes them inspiral and merge
+  #[arg(long, value_enum, default_value_t = PostNewtonian::None)]
+  post_newtonian: PostNewtonian,
+  /// Speed of light in simulation units
+  #[arg(long, default_value_t = 10.0)]
+  speed_of_light: f32,
+  /// Write the gravitational-wave strain (h+, hx) of regularized binaries to this CSV file every
+  /// step of a headless run; needs --regularization-radius
+  #[arg(long)]
+  gw_output: Option<PathBuf>,
+  /// Direction towards the gravitational-wave observer
+  #[arg(long, value_delimiter = ',', num_args = 3, default_values_t = [0.0, 0.0, 1.0])]
+  gw_observer: Vec<f32>,
+  /// Distance of the gravitational-wave observer; 1 reports the strain times the distance
+  #[arg(long, default_value_t = 1.0)]
+  gw_distance: f32,
+  /// Side of a periodic box integrated in comoving coordinates in an expanding universe; 0
+  /// disables cosmology mode. Forces use the nearest image of every particle, or a periodic mesh
+  /// with --solver particle-mesh. Stepped on the host
+  #[arg(long, default_value_t = 0.0)]
+  box_size: f32,
+  /// Matter density parameter of the cosmology; with the box's mass it sets the Hubble constant
+  #[arg(long, default_value_t = 0.3)]
+  omega_matter: f32,
+  /// Cosmological constant density parameter; 1 - omega_matter - omega_lambda is curvature
+  #[arg(long, default_value_t = 0.7)]
+  omega_lambda: f32,
+  /// Redshift at which the cosmological run starts
+  #[arg(long, default_value_t = 50.0)]
+  initial_redshift: f32,
+  /// Gravity solver used to compute accelerations. Only direct summation runs on the GPU: the
+  /// barnes-hut tree and the particle-mesh FFT are evaluated on the CPU, and with --backend gpu
+  /// every step copies the particles back to the host and uploads them again, so pair them with
+  /// --backend cpu
+  #[arg(long, value_enum, default_value_t = SolverKind::Direct)]
+  solver: SolverKind,
+  /// Barnes-Hut opening angle; smaller is more accurate and slower
+  #[arg(long, default_value_t = Solver::DEFAULT_THETA)]
+  theta: f32,
+  /// Particle-mesh grid points per axis. The mesh is solved on the CPU, see --solver
+  #[arg(long, default_value_t = Solver::DEFAULT_GRID)]
+  grid: u32,
+  /// Deepest block timestep level (smallest step is dt / 2^levels); 0 disables block timesteps
+  #[arg(long, default_value_t = 0)]
+  block_levels: u32,
+  /// Accuracy parameter for choosing block timestep levels
+  #[arg(long, default_value_t = BlockTimesteps::DEFAULT_ETA)]
+  block_eta: f32,
+  /// Steps between energy, momentum and virial reports; 0 disables them. Defaults to 100 in
+  /// headless mode and off in the window or with --box-size
+  #[arg(long)]
+  diagnostics_every: Option<u32>,
   #[command(subcommand)]
   command: Option<Commands>,
 }
 
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum SolverKind {
+  /// All-pairs summation
+  Direct,
+  /// Octree approximation controlled by --theta; CPU only
+  BarnesHut,
+  /// FFT Poisson solve on a mesh controlled by --grid; CPU only
+  ParticleMesh,
+}
+
+/// Value of a per-galaxy option for galaxy `i`, the single value applying to every galaxy.
+fn per_galaxy<T: Copy>(values: &[T], i: usize) -> T {
+  values[i.min(values.len() - 1)]
+}
+
+impl Args {
+  fn galaxies(&self) -> Vec<Galaxy> {
+    if self.initial_conditions == InitialConditions::Zeldovich {
+      return vec![Galaxy {
+        particles: self.lattice.pow(3),
+        ..Galaxy::default()
+      }];
+    }
+    (0..self.galaxies as usize)
+      .map(|i| {
+        let particles = per_galaxy(&self.particles, i);
+        Galaxy {
+          particles,
+          central_mass: self
+            .central_mass
+            .as_ref()
+            .map_or(self.initial_conditions.default_central_mass(), |masses| {
+              per_galaxy(masses, i)
+            }),
+          bulge_fraction: per_galaxy(&self.bulge_fraction, i),
+          bulge_radius: per_galaxy(&self.bulge_radius, i),
+          disk_radius: per_galaxy(&self.disk_radius, i),
+          disk_height: per_galaxy(&self.disk_height, i),
+          halo_velocity: per_galaxy(&self.halo_velocity, i),
+          halo_radius: per_galaxy(&self.halo_radius, i),
+          live_halo_particles: self
+            .halo_particles
+            .as_ref()
+            .map_or(Galaxy::default_live_halo_particles(particles), |counts| {
+              per_galaxy(counts, i)
+            }),
+          cloud_radius: per_galaxy(&self.cloud_radius, i),
+          sphere_radius: per_galaxy(&self.sphere_radius, i),
+          toomre_q: per_galaxy(&self.toomre_q, i),
+          arms: per_galaxy(&self.arms, i),
+          pitch_angle: per_galaxy(&self.pitch_angle, i),
+          arm_strength: per_galaxy(&self.arm_strength, i),
+          bar_strength: per_galaxy(&self.bar_strength, i),
+          generator: self.initial_conditions,
+          bulge: self.bulge,
+          disk: self.disk,
+          orbit: None,
+        }
+      })
+      .collect()
+  }
+
+  fn run_config(&self) -> RunConfig {
+    let mut config = RunConfig {
+      galaxies: self.galaxies(),
+      physics: Physics::default(),
+      headless: self.headless,
+      camera: CameraParams::default(),
+      backend: self.backend,
+      solver: match self.solver {
+        SolverKind::Direct => Solver::Direct,
+        SolverKind::BarnesHut => Solver::BarnesHut { theta: self.theta },
+        SolverKind::ParticleMesh => Solver::ParticleMesh { grid: self.grid },
+      },
+      kernel: self.kernel,
+      integrator: self.integrator,
+      live_halo: self.live_halo,
+      gas_fraction: self.gas_fraction,
+      eos: self.eos,
+      star_formation_threshold: self.star_formation_threshold,
+      star_formation_efficiency: self.star_formation_efficiency,
+      sfr_output: self.sfr_output.clone(),
+      sfr_bin: self.sfr_bin,
+      accretion_radius: self.accretion_radius,
+      regularization_radius: self.regularization_radius,
+      post_newtonian: self.post_newtonian,
+      speed_of_light: self.speed_of_light,
+      gw_output: self.gw_output.clone(),
+      gw_observer: [
+        self.gw_observer[0],
+        self.gw_observer[1],
+        self.gw_observer[2],
+      ],
+      gw_distance: self.gw_distance,
+      cosmology: (self.box_size > 0.0).then_some(Cosmology {
+        box_size: self.box_size,
+        omega_matter: self.omega_matter,
+        omega_lambda: self.omega_lambda,
+        initial_redshift: self.initial_redshift,
+      }),
+      encounter: self.pericenter.map(|pericenter| Encounter {
+        pericenter,
+        eccentricity: self.eccentricity,
+        separation: self.separation,
+        disks: [0, 1].map(|i| Orientation {
+          inclination: self.inclination[i],
+          pericenter_argument: self.pericenter_argument[i],
+        }),
+      }),
+      initial_conditions: self.initial_conditions,
+      seed: self.seed,
+      power_spectrum: self.power_spectrum,
+      spectral_index: self.spectral_index,
+      turnover_scale: self.turnover_scale,
+      fluctuation_amplitude: self.fluctuation_amplitude,
+      spin_parameter: self.spin_parameter,
+      virial_ratio: self.virial_ratio,
+      turbulent: self.turbulent,
+      block_timesteps: (self.block_levels > 0).then_some(BlockTimesteps {
+        max_level: self.block_levels,
+        eta: self.block_eta,
+      }),
+      // the halo, friction and diagnostics defaults depend on the rest, see `RunConfig::choose`
+      ..RunConfig::default()
+    };
+    config.choose(DependentChoices {
+      halo: self.halo,
+      friction: self.friction,
+      diagnostics_every: self.diagnostics_every,
+    });
+    config
+  }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
   /// Generate shell completion scripts
@@ -24,17 +382,165 @@ enum Commands {
     #[arg(value_enum)]
     shell: Shell,
   },
+  /// Step the GPU kernel and the CPU reference together and report their divergence
+  Validate {
+    /// Number of steps to run before comparing
+    #[arg(long, default_value_t = 100)]
+    steps: u32,
+  },
+  /// Report the selected solver's acceleration error against direct summation
+  ForceError {
+    /// Number of particles to sample
+    #[arg(long, default_value_t = 1000)]
+    samples: usize,
+  },
+  /// Time the simple and tiled direct-summation kernels at several particle counts
+  Bench {
+    /// Total particle counts to benchmark
+    #[arg(long, value_delimiter = ',', default_values_t = [1024, 4096, 16384, 65536])]
+    counts: Vec<u32>,
+    /// Timed steps per run
+    #[arg(long, default_value_t = 50)]
+    steps: u32,
+  },
+}
+
+/// The flag that sets `setting` on the command line.
+fn flag(setting: Setting) -> &'static str {
+  match setting {
+    Setting::DeltaT => "the timestep",
+    Setting::Gravity => "the gravitational constant",
+    Setting::Softening => "the softening",
+    Setting::SmoothingLength => "the smoothing length",
+    Setting::SoundSpeed => "the sound speed",
+    Setting::BlockLevels => "--block-levels",
+    Setting::BlockEta => "--block-eta",
+    Setting::GasFraction => "--gas-fraction",
+    Setting::RegularizationRadius => "--regularization-radius",
+    Setting::AccretionRadius => "--accretion-radius",
+    Setting::PostNewtonian => "--post-newtonian",
+    Setting::Halo => "--halo",
+    Setting::LiveHalo => "--live-halo",
+    Setting::Friction => "--friction",
+    Setting::GwOutput => "--gw-output",
+    Setting::GwObserver => "--gw-observer",
+    Setting::SfrBin => "--sfr-bin",
+    Setting::Theta => "--theta",
+    Setting::Grid => "--grid",
+    Setting::Cosmology | Setting::BoxSize => "--box-size",
+    Setting::OmegaMatter => "--omega-matter",
+    Setting::InitialRedshift => "--initial-redshift",
+    Setting::TurnoverScale => "--turnover-scale",
+    Setting::SpinParameter => "--spin-parameter",
+    Setting::VirialRatio => "--virial-ratio",
+    Setting::Galaxies => "--galaxies",
+    Setting::Encounter | Setting::Pericenter => "--pericenter",
+    Setting::Separation => "--separation",
+    Setting::Eccentricity => "--eccentricity",
+    Setting::Generator => "--initial-conditions",
+    Setting::Particles => "--particles",
+    Setting::CentralMass => "--central-mass",
+    Setting::BulgeFraction => "--bulge-fraction",
+    Setting::BulgeRadius => "--bulge-radius",
+    Setting::DiskRadius => "--disk-radius",
+    Setting::DiskHeight => "--disk-height",
+    Setting::HaloVelocity => "--halo-velocity",
+    Setting::HaloRadius => "--halo-radius",
+    Setting::CloudRadius => "--cloud-radius",
+    Setting::SphereRadius => "--sphere-radius",
+    Setting::ToomreQ => "--toomre-q",
+    Setting::PitchAngle => "--pitch-angle",
+    Setting::ArmStrength => "--arm-strength",
+    Setting::BarStrength => "--bar-strength",
+    // galaxies are placed on a circle on the command line
+    Setting::Orbit => "the galaxy's position and velocity",
+  }
 }
 
 fn main() {
   let args = Args::parse();
-
-  if let Some(Commands::Completions { shell }) = args.command {
-    let mut cmd = Args::command();
-    let name = cmd.get_name().to_string();
-    generate(shell, &mut cmd, name, &mut io::stdout());
-    return;
+  let config = match &args.scenario {
+    Some(path) => scenario::load(path).unwrap_or_else(|error| {
+      Args::command()
+        .error(
+          clap::error::ErrorKind::InvalidValue,
+          format!("scenario {}: {error}", path.display()),
+        )
+        .exit()
+    }),
+    None => {
+      check_values(&args);
+      let config = args.run_config();
+      if let Err(error) = config.validate() {
+        Args::command()
+          .error(clap::error::ErrorKind::InvalidValue, error.describe(flag))
+          .exit();
+      }
+      config
+    }
+  };
+  match args.command {
+    Some(Commands::Completions { shell }) => {
+      let mut cmd = Args::command();
+      let name = cmd.get_name().to_string();
+      generate(shell, &mut cmd, name, &mut io::stdout());
+    }
+    Some(Commands::Validate { steps }) => galaxy_sim::state::validate(config, steps),
+    Some(Commands::ForceError { samples }) => galaxy_sim::state::force_error(config, samples),
+    Some(Commands::Bench { counts, steps }) => galaxy_sim::state::bench(&counts, steps),
+    None => galaxy_sim::state::run(config),
   }
+}
 
-  galaxy_sim::state::run(args.galaxies, args.headless);
+/// Checks the options that have no counterpart in `RunConfig`: how many values each per-galaxy
+/// option takes, and the size of the Zel'dovich lattice.
+fn check_values(args: &Args) {
+  let lengths = [
+    ("--particles", args.particles.len()),
+    (
+      "--central-mass",
+      args.central_mass.as_ref().map_or(1, Vec::len),
+    ),
+    ("--bulge-fraction", args.bulge_fraction.len()),
+    ("--bulge-radius", args.bulge_radius.len()),
+    ("--disk-radius", args.disk_radius.len()),
+    ("--disk-height", args.disk_height.len()),
+    ("--halo-velocity", args.halo_velocity.len()),
+    ("--halo-radius", args.halo_radius.len()),
+    (
+      "--halo-particles",
+      args.halo_particles.as_ref().map_or(1, Vec::len),
+    ),
+    ("--cloud-radius", args.cloud_radius.len()),
+    ("--sphere-radius", args.sphere_radius.len()),
+    ("--toomre-q", args.toomre_q.len()),
+    ("--arms", args.arms.len()),
+    ("--pitch-angle", args.pitch_angle.len()),
+    ("--arm-strength", args.arm_strength.len()),
+    ("--bar-strength", args.bar_strength.len()),
+  ];
+  if let Some((option, _)) = lengths
+    .iter()
+    .find(|(_, length)| *length != 1 && *length != args.galaxies as usize)
+  {
+    Args::command()
+      .error(
+        clap::error::ErrorKind::WrongNumberOfValues,
+        format!(
+          "{option} takes one value or one for each of the {} galaxies",
+          args.galaxies
+        ),
+      )
+      .exit();
+  }
+  if args.initial_conditions == InitialConditions::Zeldovich
+    && (args.lattice < 2 || args.lattice.checked_pow(3).is_none())
+  {
+    Args::command()
+      .error(
+        clap::error::ErrorKind::InvalidValue,
+        "--lattice must be at least 2 with its cube fitting in 32 bits",
+      )
+      .exit();
+  }
 }
@@ -0,0 +1,436 @@
+use crate::{
+  diagnostics::to_f64,
+  friction,
+  post_newtonian::{self, PostNewtonian},
+  Particle, SimParams,
+};
+use cgmath::{InnerSpace, Vector3, Vector4, Zero};
+use std::fmt;
+
+/// Two galaxy cores whose mutual orbit is integrated exactly in Kustaanheimo-Stiefel (KS)
+/// coordinates instead of by the global integrator.
+///
+/// The pair's mutual force is left out of the force evaluation, so kicks only carry the
+/// perturbations from everything else, and every drift moves the pair's centre of mass in a
+/// straight line while the relative orbit follows the unsoftened Kepler problem over the same
+/// interval. In KS coordinates that orbit is a harmonic oscillator in the fictitious time
+/// `dτ = dt / r`, which stays regular however close the cores get, so a hardening binary is
+/// followed without softening and without shrinking `delta_t`. Optional post-Newtonian terms
+/// perturb the relative orbit inside the drift.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Binary {
+  pub primary: usize,
+  pub secondary: usize,
+}
+
+/// Semi-major axis and eccentricity of a binary's relative orbit.
+#[derive(Copy, Clone, Debug)]
+pub struct Orbit {
+  pub semi_major_axis: f64,
+  pub eccentricity: f64,
+}
+
+impl fmt::Display for Orbit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "a = {:.4e}, e = {:.4}",
+      self.semi_major_axis, self.eccentricity
+    )
+  }
+}
+
+/// Pairs of the cores at `cores` closer than `regularization_radius` and bound to each other,
+/// closest first; each core is in at most one pair.
+#[must_use]
+pub fn find_binaries(
+  particles: &[Particle],
+  cores: &[usize],
+  sim_params: &SimParams,
+) -> Vec<Binary> {
+  let radius = sim_params.regularization_radius;
+  if radius <= 0.0 {
+    return Vec::new();
+  }
+  let mut candidates: Vec<(f64, Binary)> = Vec::new();
+  for (i, &primary) in cores.iter().enumerate() {
+    for &secondary in &cores[i + 1..] {
+      let binary = Binary { primary, secondary };
+      let (r, v, mu) = relative(particles, binary, sim_params);
+      let separation = r.magnitude();
+      if separation < f64::from(radius) && 0.5 * v.magnitude2() - mu / separation < 0.0 {
+        candidates.push((separation, binary));
+      }
+    }
+  }
+  candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+  let mut binaries: Vec<Binary> = Vec::new();
+  for (_, binary) in candidates {
+    let taken = |index: usize| {
+      binaries
+        .iter()
+        .any(|other| other.primary == index || other.secondary == index)
+    };
+    if !taken(binary.primary) && !taken(binary.secondary) {
+      binaries.push(binary);
+    }
+  }
+  binaries
+}
+
+/// Relative position and velocity of the secondary and `G (m1 + m2)`, in double precision.
+fn relative(
+  particles: &[Particle],
+  binary: Binary,
+  sim_params: &SimParams,
+) -> (Vector3<f64>, Vector3<f64>, f64) {
+  let (a, b) = (particles[binary.primary], particles[binary.secondary]);
+  let r = to_f64(b.pos) - to_f64(a.pos);
+  let v = to_f64(b.vel) - to_f64(a.vel);
+  let mu = f64::from(sim_params.gravity) * f64::from(a.mass + b.mass);
+  (r, v, mu)
+}
+
+fn to_f32(v: Vector3<f64>) -> [f32; 3] {
+  [v.x as f32, v.y as f32, v.z as f32]
+}
+
+/// Orbital elements of `binary` in the unsoftened two-body potential.
+#[must_use]
+pub fn orbit(particles: &[Particle], binary: Binary, sim_params: &SimParams) -> Orbit {
+  let (r, v, mu) = relative(particles, binary, sim_params);
+  let energy = 0.5 * v.magnitude2() - mu / r.magnitude();
+  let angular_momentum = r.cross(v).magnitude2();
+  Orbit {
+    semi_major_axis: -mu / (2.0 * energy),
+    eccentricity: (1.0 + 2.0 * energy * angular_momentum / (mu * mu))
+      .max(0.0)
+      .sqrt(),
+  }
+}
+
+/// Softened acceleration of the particle at `index` towards `other`, as added by the force
+/// evaluation.
+fn mutual_acceleration(
+  particles: &[Particle],
+  index: usize,
+  other: usize,
+  sim_params: &SimParams,
+) -> Vector3<f32> {
+  let displacement = Vector3::from(particles[other].pos) - Vector3::from(particles[index].pos);
+  let dist_sq = displacement.magnitude2() + sim_params.calibrate;
+  displacement * (sim_params.gravity * particles[other].mass / (dist_sq * dist_sq.sqrt()))
+}
+
+/// Adds `sign` times each binary's softened mutual acceleration to its members' `acc`.
+fn add_mutual_forces(
+  particles: &mut [Particle],
+  binaries: &[Binary],
+  sign: f32,
+  sim_params: &SimParams,
+) {
+  for &Binary { primary, secondary } in binaries {
+    for (index, other) in [(primary, secondary), (secondary, primary)] {
+      let acceleration = mutual_acceleration(particles, index, other, sim_params) * sign;
+      particles[index].acc = (Vector3::from(particles[index].acc) + acceleration).into();
+    }
+  }
+}
+
+/// Takes the mutual attraction of each binary out of freshly evaluated accelerations.
+pub fn remove_mutual_forces(
+  particles: &mut [Particle],
+  binaries: &[Binary],
+  sim_params: &SimParams,
+) {
+  add_mutual_forces(particles, binaries, -1.0, sim_params);
+}
+
+/// Brings `acc` from the last force evaluation, which left out the `previous` binaries, in line
+/// with the `current` ones.
+pub fn rebind(
+  particles: &mut [Particle],
+  previous: &[Binary],
+  current: &[Binary],
+  sim_params: &SimParams,
+) {
+  let dissolved: Vec<Binary> = previous
+    .iter()
+    .filter(|binary| !current.contains(binary))
+    .copied()
+    .collect();
+  let formed: Vec<Binary> = current
+    .iter()
+    .filter(|binary| !previous.contains(binary))
+    .copied()
+    .collect();
+  add_mutual_forces(particles, &dissolved, 1.0, sim_params);
+  add_mutual_forces(particles, &formed, -1.0, sim_params);
+}
+
+/// Drift of every binary over `dt`: the positions in `src` and the velocities already in `dst`
+/// are advanced along the Kepler orbit and written to `dst`.
+pub fn drift(
+  src: &[Particle],
+  dst: &mut [Particle],
+  binaries: &[Binary],
+  dt: f32,
+  sim_params: &SimParams,
+) {
+  for &Binary { primary, secondary } in binaries {
+    let (m1, m2) = (f64::from(dst[primary].mass), f64::from(dst[secondary].mass));
+    let total = m1 + m2;
+    let (x1, x2) = (to_f64(src[primary].pos), to_f64(src[secondary].pos));
+    let (v1, v2) = (to_f64(dst[primary].vel), to_f64(dst[secondary].vel));
+    let center_velocity = (v1 * m1 + v2 * m2) / total;
+    let center = (x1 * m1 + x2 * m2) / total + center_velocity * f64::from(dt);
+    let mu = f64::from(sim_params.gravity) * total;
+    let (r, v) = match PostNewtonian::from_discriminant(sim_params.post_newtonian) {
+      PostNewtonian::None => kepler(x2 - x1, v2 - v1, mu, f64::from(dt)),
+      order => post_newtonian_kepler(
+        x2 - x1,
+        v2 - v1,
+        mu,
+        m1 * m2 / (total * total),
+        f64::from(dt),
+        order,
+        f64::from(sim_params.speed_of_light),
+      ),
+    };
+    dst[primary].pos = to_f32(center - r * (m2 / total));
+    dst[secondary].pos = to_f32(center + r * (m1 / total));
+    dst[primary].vel = to_f32(center_velocity - v * (m2 / total));
+    dst[secondary].vel = to_f32(center_velocity + v * (m1 / total));
+  }
+}
+
+/// Relative position and velocity after `dt` of unperturbed two-body motion with `G M = mu`.
+fn kepler(r: Vector3<f64>, v: Vector3<f64>, mu: f64, dt: f64) -> (Vector3<f64>, Vector3<f64>) {
+  let distance = r.magnitude();
+  if distance == 0.0 || dt == 0.0 {
+    return (r, v);
+  }
+  let u0 = ks_position(r);
+  // du/dτ = L(u)ᵀ v / 2
+  let w0 = ks_transpose(u0, v) * 0.5;
+  let oscillator = Oscillator {
+    u0,
+    w0,
+    half_energy: 0.5 * (0.5 * v.magnitude2() - mu / distance),
+  };
+  let tau = oscillator.solve_time(dt, distance);
+  let (u, w) = oscillator.state(tau);
+  let r = ks_vector(u, u);
+  // v = 2 L(u) du/dτ / r
+  let v = ks_vector(u, w) * (2.0 / u.magnitude2());
+  (r, v)
+}
+
+/// Substeps per orbital period when post-Newtonian terms perturb the Kepler drift, and the most
+/// substeps a single drift may take.
+const SUBSTEPS_PER_ORBIT: f64 = 64.0;
+const MAX_SUBSTEPS: f64 = 100_000.0;
+
+/// `kepler` with the post-Newtonian correction applied as kicks around each of a number of
+/// Kepler substeps that resolves the current orbit. The corrections are switched off once the
+/// binary is inside `post_newtonian::MERGER_SEPARATION`, where it is about to be merged.
+fn post_newtonian_kepler(
+  r: Vector3<f64>,
+  v: Vector3<f64>,
+  mu: f64,
+  eta: f64,
+  dt: f64,
+  order: PostNewtonian,
+  speed_of_light: f64,
+) -> (Vector3<f64>, Vector3<f64>) {
+  let period = 2.0 * std::f64::consts::PI * (r.magnitude().powi(3) / mu).sqrt();
+  let substeps = (dt.abs() * SUBSTEPS_PER_ORBIT / period)
+    .ceil()
+    .clamp(1.0, MAX_SUBSTEPS);
+  let h = dt / substeps;
+  let merger_separation =
+    post_newtonian::MERGER_SEPARATION * mu / (speed_of_light * speed_of_light);
+  let correction = |r: Vector3<f64>, v: Vector3<f64>| {
+    if r.magnitude() < merger_separation {
+      Vector3::zero()
+    } else {
+      post_newtonian::acceleration(order, r, v, mu, eta, speed_of_light)
+    }
+  };
+  let (mut r, mut v) = (r, v);
+  for _ in 0..substeps as usize {
+    v += correction(r, v) * (0.5 * h);
+    (r, v) = kepler(r, v, mu, h);
+    v += correction(r, v) * (0.5 * h);
+  }
+  (r, v)
+}
+
+/// A KS vector `u` with `L(u) u = r`.
+fn ks_position(r: Vector3<f64>) -> Vector4<f64> {
+  let distance = r.magnitude();
+  if r.x >= 0.0 {
+    let u1 = (0.5 * (distance + r.x)).sqrt();
+    Vector4::new(u1, 0.5 * r.y / u1, 0.5 * r.z / u1, 0.0)
+  } else {
+    let u2 = (0.5 * (distance - r.x)).sqrt();
+    Vector4::new(0.5 * r.y / u2, u2, 0.0, 0.5 * r.z / u2)
+  }
+}
+
+/// First three components of the KS matrix product `L(u) w`.
+fn ks_vector(u: Vector4<f64>, w: Vector4<f64>) -> Vector3<f64> {
+  Vector3::new(
+    u.x * w.x - u.y * w.y - u.z * w.z + u.w * w.w,
+    u.y * w.x + u.x * w.y - u.w * w.z - u.z * w.w,
+    u.z * w.x + u.w * w.y + u.x * w.z + u.y * w.w,
+  )
+}
+
+/// `L(u)ᵀ (v, 0)`.
+fn ks_transpose(u: Vector4<f64>, v: Vector3<f64>) -> Vector4<f64> {
+  Vector4::new(
+    u.x * v.x + u.y * v.y + u.z * v.z,
+    -u.y * v.x + u.x * v.y + u.w * v.z,
+    -u.z * v.x - u.w * v.y + u.x * v.z,
+    u.w * v.x - u.z * v.y + u.y * v.z,
+  )
+}
+
+/// Solution of `d²u/dτ² = (h / 2) u` from `u0` and `du/dτ = w0`, with `h` the specific orbital
+/// energy.
+struct Oscillator {
+  u0: Vector4<f64>,
+  w0: Vector4<f64>,
+  half_energy: f64,
+}
+
+impl Oscillator {
+  /// `u` and `du/dτ` at fictitious time `tau`.
+  fn state(&self, tau: f64) -> (Vector4<f64>, Vector4<f64>) {
+    let (u0, w0, k_sq) = (self.u0, self.w0, self.half_energy);
+    if k_sq < 0.0 {
+      let omega = (-k_sq).sqrt();
+      let (sin, cos) = (omega * tau).sin_cos();
+      (u0 * cos + w0 * (sin / omega), w0 * cos - u0 * (omega * sin))
+    } else if k_sq > 0.0 {
+      let k = k_sq.sqrt();
+      let (sinh, cosh) = ((k * tau).sinh(), (k * tau).cosh());
+      (u0 * cosh + w0 * (sinh / k), w0 * cosh + u0 * (k * sinh))
+    } else {
+      (u0 + w0 * tau, w0)
+    }
+  }
+
+  /// Physical time elapsed after fictitious time `tau`, `t = ∫ |u|² dτ`.
+  fn time(&self, tau: f64) -> f64 {
+    let (u0, w0, k_sq) = (self.u0, self.w0, self.half_energy);
+    let a = u0.magnitude2();
+    if k_sq < 0.0 {
+      let omega = (-k_sq).sqrt();
+      let b = w0.magnitude2() / (omega * omega);
+      let c = 2.0 * u0.dot(w0) / omega;
+      let sin = (omega * tau).sin();
+      0.5 * (a + b) * tau
+        + (a - b) * (2.0 * omega * tau).sin() / (4.0 * omega)
+        + c * sin * sin / (2.0 * omega)
+    } else if k_sq > 0.0 {
+      let k = k_sq.sqrt();
+      let b = w0.magnitude2() / k_sq;
+      let c = 2.0 * u0.dot(w0) / k;
+      let sinh = (k * tau).sinh();
+      0.5 * (a - b) * tau
+        + (a + b) * (2.0 * k * tau).sinh() / (4.0 * k)
+        + c * sinh * sinh / (2.0 * k)
+    } else {
+      a * tau + u0.dot(w0) * tau * tau + w0.magnitude2() * tau * tau * tau / 3.0
+    }
+  }
+
+  /// Fictitious time at which `dt` of physical time has passed. `t(τ)` is monotonic since
+  /// `dt/dτ = r`, so Newton's method is safeguarded by bisection.
+  fn solve_time(&self, dt: f64, distance: f64) -> f64 {
+    let mut tau = dt / distance;
+    let (mut lo, mut hi) = (0.0f64.min(tau), 0.0f64.max(tau));
+    // widen the bracket until it contains the solution
+    while (self.time(lo) - dt) * (self.time(hi) - dt) > 0.0 {
+      if dt > 0.0 {
+        hi *= 2.0;
+      } else {
+        lo *= 2.0;
+      }
+    }
+    for _ in 0..100 {
+      let error = self.time(tau) - dt;
+      if error.abs() <= 1e-14 * dt.abs() {
+        break;
+      }
+      if error > 0.0 {
+        hi = tau;
+      } else {
+        lo = tau;
+      }
+      let rate = self.state(tau).0.magnitude2();
+      let newton = tau - error / rate;
+      tau = if rate > 0.0 && newton > lo && newton < hi {
+        newton
+      } else {
+        0.5 * (lo + hi)
+      };
+    }
+    tau
+  }
+}
+
+/// Difference between the unsoftened and the softened mutual potential energy of every binary,
+/// i.e. what the Plummer pair sum misses for the regularized pairs.
+#[must_use]
+pub fn potential_correction(particles: &[Particle], sim_params: &SimParams) -> f64 {
+  find_binaries(particles, &friction::core_indices(particles), sim_params)
+    .into_iter()
+    .map(|binary| {
+      let (a, b) = (particles[binary.primary], particles[binary.secondary]);
+      let r_sq = (to_f64(b.pos) - to_f64(a.pos)).magnitude2();
+      let g_m1_m2 = f64::from(sim_params.gravity) * f64::from(a.mass) * f64::from(b.mass);
+      -g_m1_m2 / r_sq.sqrt() + g_m1_m2 / (r_sq + f64::from(sim_params.calibrate)).sqrt()
+    })
+    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn kepler_closes_the_orbit_and_conserves_energy() {
+    let mu = 2.5;
+    // eccentric orbit inclined to every coordinate plane
+    let r0 = Vector3::new(0.3, -0.2, 0.1);
+    let v0 = Vector3::new(1.1, 2.4, -0.7);
+    let energy = |r: Vector3<f64>, v: Vector3<f64>| 0.5 * v.magnitude2() - mu / r.magnitude();
+    let e0 = energy(r0, v0);
+    assert!(e0 < 0.0);
+    let semi_major_axis = -mu / (2.0 * e0);
+    let period = 2.0 * std::f64::consts::PI * (semi_major_axis.powi(3) / mu).sqrt();
+
+    let (r, v) = kepler(r0, v0, mu, period);
+    assert!(
+      (r - r0).magnitude() < 1e-9 * r0.magnitude(),
+      "{r:?} vs {r0:?}"
+    );
+    assert!(
+      (v - v0).magnitude() < 1e-9 * v0.magnitude(),
+      "{v:?} vs {v0:?}"
+    );
+
+    let (mut r, mut v) = (r0, v0);
+    for step in 0..100 {
+      (r, v) = kepler(r, v, mu, period * (0.013 + 0.001 * f64::from(step % 7)));
+      assert!(
+        ((energy(r, v) - e0) / e0).abs() < 1e-9,
+        "step {step}: energy {} vs {e0}",
+        energy(r, v)
+      );
+    }
+  }
+}
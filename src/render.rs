@@ -1,4 +1,10 @@
-use crate::{initialize, Particle, SimParams};
+use crate::{
+  cpu::Stepper,
+  friction::{self, Friction},
+  initialize,
+  integrator::{Integrator, Stage},
+  Particle, ParticleKind, RunConfig, SimParams,
+};
 use std::borrow::Cow;
 use wgpu::{util::DeviceExt, PipelineCompilationOptions};
 
@@ -6,11 +12,55 @@ pub struct Render {
   particle_bind_groups: Vec<wgpu::BindGroup>,
   particle_buffers: Vec<wgpu::Buffer>,
   vertices_buffer: Option<wgpu::Buffer>,
-  compute_pipeline: wgpu::ComputePipeline,
+  force_pipeline: wgpu::ComputePipeline,
+  kick_pipeline: wgpu::ComputePipeline,
+  drift_pipeline: wgpu::ComputePipeline,
+  verlet_drift_pipeline: wgpu::ComputePipeline,
+  core_environment_pipeline: wgpu::ComputePipeline,
+  num_cores: u32,
+  sph_pipelines: Option<SphPipelines>,
   render_pipeline: Option<wgpu::RenderPipeline>,
+  /// Gives the draw shader the simulation time, for the age of formed stars
+  draw_bind_group: Option<wgpu::BindGroup>,
   work_group_count: u32,
   frame_num: usize,
   sim_param_buffer: wgpu::Buffer,
+  stage_param_stride: u32,
+  integrator: Integrator,
+  primed: bool,
+  host_stepper: Option<Stepper>,
+}
+
+/// Per-pass uniform; padded to the 16 bytes a WGSL uniform struct occupies.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct StageParams {
+  coefficient: f32,
+  _pad: [f32; 3],
+}
+
+/// Must match `SPH_TABLE_SIZE` in `shaders/compute.wgsl`.
+const SPH_TABLE_SIZE: u32 = 65536;
+
+/// Neighbour grid, hydrodynamics and star formation passes, only created when there is gas.
+struct SphPipelines {
+  clear: wgpu::ComputePipeline,
+  count: wgpu::ComputePipeline,
+  scan: wgpu::ComputePipeline,
+  insert: wgpu::ComputePipeline,
+  density: wgpu::ComputePipeline,
+  force: wgpu::ComputePipeline,
+  star_formation: wgpu::ComputePipeline,
+}
+
+/// `Core` in `shaders/compute.wgsl`; the environment is filled in by `core_environment`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuCore {
+  index: u32,
+  density: f32,
+  sigma: f32,
+  background_vel: [f32; 3],
 }
 
 impl Render {
@@ -23,6 +73,7 @@ impl Render {
     _queue: &wgpu::Queue,
     camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
     sim_params: SimParams,
+    run_config: &RunConfig,
   ) -> Self {
     let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
       label: Some("compute_shader"),
@@ -37,6 +88,28 @@ impl Render {
       contents: bytemuck::cast_slice(&[sim_params]),
       usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
     });
+    // slot 0 is the initial force evaluation, slot i + 1 is stage i of the integrator
+    let stage_param_stride = device.limits().min_uniform_buffer_offset_alignment;
+    let stages = run_config.integrator.stages();
+    let mut stage_param_data = vec![0u8; (stages.len() + 1) * stage_param_stride as usize];
+    for (slot, stage) in stages.iter().enumerate() {
+      let coefficient = match *stage {
+        Stage::Kick(c) | Stage::Drift(c) => c,
+        Stage::Force | Stage::VerletDrift => 0.0,
+      };
+      let offset = (slot + 1) * stage_param_stride as usize;
+      stage_param_data[offset..offset + std::mem::size_of::<StageParams>()].copy_from_slice(
+        bytemuck::bytes_of(&StageParams {
+          coefficient,
+          _pad: [0.0; 3],
+        }),
+      );
+    }
+    let stage_param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Stage Parameter Buffer"),
+      contents: &stage_param_data,
+      usage: wgpu::BufferUsages::UNIFORM,
+    });
 
     // ========================================================================
     // compute pipeline stuff
@@ -62,8 +135,7 @@ impl Render {
               ty: wgpu::BufferBindingType::Storage { read_only: true },
               has_dynamic_offset: false,
               min_binding_size: wgpu::BufferSize::new(
-                ((sim_params.num_particles * sim_params.num_galaxies) as usize
-                  * std::mem::size_of::<Particle>()) as _,
+                (sim_params.num_particles as usize * std::mem::size_of::<Particle>()) as _,
               ),
             },
             count: None,
@@ -75,12 +147,61 @@ impl Render {
               ty: wgpu::BufferBindingType::Storage { read_only: false },
               has_dynamic_offset: false,
               min_binding_size: wgpu::BufferSize::new(
-                ((sim_params.num_particles * sim_params.num_galaxies) as usize
-                  * std::mem::size_of::<Particle>()) as _,
+                (sim_params.num_particles as usize * std::mem::size_of::<Particle>()) as _,
               ),
             },
             count: None,
           },
+          wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: true,
+              min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<StageParams>() as _),
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Storage { read_only: false },
+              has_dynamic_offset: false,
+              min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<GpuCore>() as _),
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 5,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Storage { read_only: false },
+              has_dynamic_offset: false,
+              min_binding_size: None,
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 6,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Storage { read_only: false },
+              has_dynamic_offset: false,
+              min_binding_size: None,
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 7,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Storage { read_only: false },
+              has_dynamic_offset: false,
+              min_binding_size: None,
+            },
+            count: None,
+          },
         ],
         label: Some("compute_bind_group_layout"),
       });
@@ -89,42 +210,73 @@ impl Render {
       bind_group_layouts: &[&compute_bind_group_layout],
       push_constant_ranges: &[],
     });
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-      label: Some("Compute pipeline"),
-      layout: Some(&compute_pipeline_layout),
-      module: &compute_shader,
-      entry_point: "main",
-      compilation_options: PipelineCompilationOptions::default(),
-      cache: None,
-    });
+    let create_compute_pipeline = |entry_point: &str| {
+      device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(&format!("Compute pipeline ({entry_point})")),
+        layout: Some(&compute_pipeline_layout),
+        module: &compute_shader,
+        entry_point,
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+      })
+    };
+    let force_pipeline = create_compute_pipeline(run_config.kernel.entry_point());
+    let kick_pipeline = create_compute_pipeline("kick");
+    let drift_pipeline = create_compute_pipeline("drift");
+    let verlet_drift_pipeline = create_compute_pipeline("verlet_drift");
+    let core_environment_pipeline = create_compute_pipeline("core_environment");
 
     // ========================================================================
     // render pipeline stuff
     // ========================================================================
 
+    // the draw shader reads the simulation time to age formed stars
+    let draw_bind_group_layout =
+      device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::VERTEX,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        }],
+        label: Some("draw_bind_group_layout"),
+      });
     let (render_pipeline, vertices_buffer) = if let (Some(config), Some(camera_layout)) =
       (config, camera_bind_group_layout)
     {
       let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("render"),
-        bind_group_layouts: &[camera_layout],
+        bind_group_layouts: &[camera_layout, &draw_bind_group_layout],
         push_constant_ranges: &[],
       });
+      let mut particle_attributes = wgpu::vertex_attr_array![
+        0 => Float32, 1 => Float32, 2 => Float32,   // pos[3]
+        3 => Float32, 4 => Float32, 5 => Float32,   // vel[3]
+        6 => Float32, 7 => Float32, 8 => Float32,   // acc[3]
+        9 => Float32,                                // mass
+        10 => Uint32,                                // galaxy_id
+        11 => Uint32                                 // kind
+      ]
+      .to_vec();
+      // the SPH fields in between are not drawn
+      particle_attributes.push(wgpu::VertexAttribute {
+        format: wgpu::VertexFormat::Float32,
+        offset: std::mem::offset_of!(Particle, formation_time) as u64,
+        shader_location: 13,
+      });
       let particle_buffer = wgpu::VertexBufferLayout {
         array_stride: std::mem::size_of::<Particle>() as u64,
         step_mode: wgpu::VertexStepMode::Instance,
-        attributes: &wgpu::vertex_attr_array![
-          0 => Float32, 1 => Float32, 2 => Float32,   // pos[3]
-          3 => Float32, 4 => Float32, 5 => Float32,   // vel[3]
-          6 => Float32, 7 => Float32, 8 => Float32,   // acc[3]
-          9 => Float32,                                // mass
-          10 => Uint32                                 // galaxy_id
-        ],
+        attributes: &particle_attributes,
       };
       let vertex_buffer = wgpu::VertexBufferLayout {
         array_stride: 3 * 4, // vertex data
         step_mode: wgpu::VertexStepMode::Vertex,
-        attributes: &wgpu::vertex_attr_array![11 => Float32x3],
+        attributes: &wgpu::vertex_attr_array![12 => Float32x3],
       };
       let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
@@ -171,7 +323,74 @@ impl Render {
     } else {
       (None, None)
     };
-    let initial_particle_data = initialize::create_galaxies(&sim_params);
+    let draw_bind_group = render_pipeline.is_some().then(|| {
+      device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &draw_bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+          binding: 0,
+          resource: sim_param_buffer.as_entire_binding(),
+        }],
+        label: Some("draw_bind_group"),
+      })
+    });
+    let initial_particle_data = initialize::create_galaxies(&sim_params, run_config);
+    let mut cores: Vec<GpuCore> = friction::core_indices(&initial_particle_data)
+      .into_iter()
+      .map(|index| GpuCore {
+        index: index as u32,
+        density: 0.0,
+        sigma: 0.0,
+        background_vel: [0.0; 3],
+      })
+      .collect();
+    let num_cores = cores.len() as u32;
+    if cores.is_empty() {
+      // bindings cannot be empty; nothing reads this entry without a core to kick
+      cores.push(bytemuck::Zeroable::zeroed());
+    }
+    let core_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Core Buffer"),
+      contents: bytemuck::cast_slice(&cores),
+      usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let has_gas = initial_particle_data
+      .iter()
+      .any(|particle| particle.kind() == ParticleKind::Gas);
+    // the grid is only bound, never touched, without gas; with it every particle has a slot, so
+    // however many share a cell none is dropped
+    let (table_size, item_count) = if has_gas {
+      (SPH_TABLE_SIZE, sim_params.num_particles)
+    } else {
+      (1, 1)
+    };
+    let cell_count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("SPH Cell Count Buffer"),
+      size: u64::from(table_size) * 4,
+      usage: wgpu::BufferUsages::STORAGE,
+      mapped_at_creation: false,
+    });
+    let cell_start_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("SPH Cell Start Buffer"),
+      size: u64::from(table_size) * 4,
+      usage: wgpu::BufferUsages::STORAGE,
+      mapped_at_creation: false,
+    });
+    let cell_item_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("SPH Cell Item Buffer"),
+      size: u64::from(item_count) * 4,
+      usage: wgpu::BufferUsages::STORAGE,
+      mapped_at_creation: false,
+    });
+    let sph_pipelines = has_gas.then(|| SphPipelines {
+      clear: create_compute_pipeline("sph_clear"),
+      count: create_compute_pipeline("sph_count"),
+      scan: create_compute_pipeline("sph_scan"),
+      insert: create_compute_pipeline("sph_insert"),
+      density: create_compute_pipeline("sph_density"),
+      force: create_compute_pipeline("sph_force"),
+      star_formation: create_compute_pipeline("star_formation"),
+    });
     let mut particle_buffers = Vec::<wgpu::Buffer>::new();
     let mut particle_bind_groups = Vec::<wgpu::BindGroup>::new();
 
@@ -182,7 +401,8 @@ impl Render {
           contents: bytemuck::cast_slice(&initial_particle_data),
           usage: wgpu::BufferUsages::VERTEX
             | wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::COPY_DST,
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
         }),
       );
     }
@@ -202,6 +422,30 @@ impl Render {
             binding: 2,
             resource: particle_buffers[(i + 1) % 2].as_entire_binding(),
           },
+          wgpu::BindGroupEntry {
+            binding: 3,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+              buffer: &stage_param_buffer,
+              offset: 0,
+              size: wgpu::BufferSize::new(std::mem::size_of::<StageParams>() as _),
+            }),
+          },
+          wgpu::BindGroupEntry {
+            binding: 4,
+            resource: core_buffer.as_entire_binding(),
+          },
+          wgpu::BindGroupEntry {
+            binding: 5,
+            resource: cell_count_buffer.as_entire_binding(),
+          },
+          wgpu::BindGroupEntry {
+            binding: 6,
+            resource: cell_item_buffer.as_entire_binding(),
+          },
+          wgpu::BindGroupEntry {
+            binding: 7,
+            resource: cell_start_buffer.as_entire_binding(),
+          },
         ],
         label: Some(&format!("Particle Bind Group {i}")),
       }));
@@ -211,22 +455,36 @@ impl Render {
       clippy::cast_sign_loss,
       clippy::cast_precision_loss
     )]
-    let work_group_count = (((sim_params.num_particles * sim_params.num_galaxies) as f32)
-      / (sim_params.particles_per_group as f32))
-      .ceil() as u32;
+    let work_group_count =
+      ((sim_params.num_particles as f32) / (sim_params.particles_per_group as f32)).ceil() as u32;
     Render {
       particle_bind_groups,
       particle_buffers,
       vertices_buffer,
-      compute_pipeline,
+      force_pipeline,
+      kick_pipeline,
+      drift_pipeline,
+      verlet_drift_pipeline,
+      core_environment_pipeline,
+      num_cores,
+      sph_pipelines,
       render_pipeline,
+      draw_bind_group,
       work_group_count,
       frame_num: 0,
       sim_param_buffer,
+      stage_param_stride,
+      integrator: run_config.integrator,
+      primed: false,
+      host_stepper: run_config.runs_on_host().then(|| Stepper::new(run_config)),
     }
   }
 
   pub fn compute(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, sim_params: &SimParams) {
+    if self.host_stepper.is_some() {
+      self.compute_on_host(device, queue, sim_params);
+      return;
+    }
     let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
       label: Some("Compute Command Encoder"),
     });
@@ -237,18 +495,205 @@ impl Render {
       bytemuck::cast_slice(&[*sim_params]),
     );
 
-    // Compute pass
-    {
-      let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-        label: Some("Compute Pass Descriptor"),
-        timestamp_writes: None,
-      });
-      cpass.set_pipeline(&self.compute_pipeline);
-      cpass.set_bind_group(0, &self.particle_bind_groups[self.frame_num % 2], &[]);
-      cpass.dispatch_workgroups(self.work_group_count, 1, 1);
+    let measure_cores = Friction::from_discriminant(sim_params.friction) == Friction::Chandrasekhar
+      && self.num_cores > 0;
+
+    // One compute pass per integrator stage, ping-ponging between the particle buffers
+    for (slot, stage) in self.pending_stages() {
+      let offset = slot * self.stage_param_stride;
+      if measure_cores && matches!(stage, Stage::Kick(_) | Stage::VerletDrift) {
+        dispatch(
+          &mut command_encoder,
+          &self.core_environment_pipeline,
+          &self.particle_bind_groups[self.frame_num % 2],
+          offset,
+          self.num_cores,
+        );
+      }
+
+      let pipeline = match stage {
+        Stage::Force => &self.force_pipeline,
+        Stage::Kick(_) => &self.kick_pipeline,
+        Stage::Drift(_) => &self.drift_pipeline,
+        Stage::VerletDrift => &self.verlet_drift_pipeline,
+      };
+      dispatch(
+        &mut command_encoder,
+        pipeline,
+        &self.particle_bind_groups[self.frame_num % 2],
+        offset,
+        self.work_group_count,
+      );
+      self.frame_num += 1;
+
+      // hydrodynamic forces are added on top of gravity: counting-sort the gas into the neighbour
+      // grid, then densities and pressure forces each ping-pong the particles like any other stage
+      if let (Stage::Force, Some(sph)) = (stage, &self.sph_pipelines) {
+        let bind_group = &self.particle_bind_groups[self.frame_num % 2];
+        dispatch(
+          &mut command_encoder,
+          &sph.clear,
+          bind_group,
+          offset,
+          SPH_TABLE_SIZE / 64,
+        );
+        dispatch(
+          &mut command_encoder,
+          &sph.count,
+          bind_group,
+          offset,
+          self.work_group_count,
+        );
+        dispatch(&mut command_encoder, &sph.scan, bind_group, offset, 1);
+        dispatch(
+          &mut command_encoder,
+          &sph.insert,
+          bind_group,
+          offset,
+          self.work_group_count,
+        );
+        dispatch(
+          &mut command_encoder,
+          &sph.density,
+          bind_group,
+          offset,
+          self.work_group_count,
+        );
+        self.frame_num += 1;
+        dispatch(
+          &mut command_encoder,
+          &sph.force,
+          &self.particle_bind_groups[self.frame_num % 2],
+          offset,
+          self.work_group_count,
+        );
+        self.frame_num += 1;
+      }
+    }
+
+    // gas that ended the step dense enough turns into stars
+    if let Some(sph) = &self.sph_pipelines {
+      dispatch(
+        &mut command_encoder,
+        &sph.star_formation,
+        &self.particle_bind_groups[self.frame_num % 2],
+        0,
+        self.work_group_count,
+      );
+      self.frame_num += 1;
+    }
+    queue.submit(Some(command_encoder.finish()));
+  }
+
+  /// Stages to run this step paired with their stage parameter slot, prefixed by the initial
+  /// force evaluation on the first step if the integrator needs one.
+  fn pending_stages(&mut self) -> Vec<(u32, Stage)> {
+    let mut stages: Vec<(u32, Stage)> = (1..).zip(self.integrator.stages()).collect();
+    if !self.primed && self.integrator.needs_initial_force() {
+      stages.insert(0, (0, Stage::Force));
+    }
+    self.primed = true;
+    stages
+  }
+
+  /// Steps the particles with a solver that has no GPU kernel: read back, step on the CPU, upload.
+  ///
+  /// The round trip over the bus every step makes this slower than the CPU backend on its own;
+  /// it only keeps host-only options working in the window of the GPU backend.
+  fn compute_on_host(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sim_params: &SimParams,
+  ) {
+    let mut particles = self.read_particles(device, queue);
+    if let Some(stepper) = &mut self.host_stepper {
+      stepper.step(&mut particles, sim_params);
+    }
+    self.upload_particles(queue, &particles);
+  }
+
+  /// Overwrites the particles that will be drawn next, e.g. with the output of the CPU backend.
+  pub fn upload_particles(&self, queue: &wgpu::Queue, particles: &[Particle]) {
+    queue.write_buffer(
+      &self.particle_buffers[self.frame_num % 2],
+      0,
+      bytemuck::cast_slice(particles),
+    );
+  }
+
+  /// Stars formed during the run that the sinks of the host stepper have swallowed.
+  #[must_use]
+  pub fn swallowed_stars(&self) -> &[Particle] {
+    self
+      .host_stepper
+      .as_ref()
+      .map_or(&[], Stepper::swallowed_stars)
+  }
+
+  /// Copies the most recently computed particles back to the host.
+  #[must_use]
+  pub fn read_particles(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Particle> {
+    let size = self.particle_buffers[self.frame_num % 2].size();
+    self.read_back(device, queue, &[(0, size)])
+  }
+
+  /// Copies the most recently computed particles at `indices` back to the host, in that order.
+  #[must_use]
+  pub fn read_particles_at(
+    &self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    indices: &[usize],
+  ) -> Vec<Particle> {
+    if indices.is_empty() {
+      return Vec::new();
+    }
+    let size = std::mem::size_of::<Particle>() as u64;
+    let ranges: Vec<(u64, u64)> = indices
+      .iter()
+      .map(|&index| (index as u64 * size, size))
+      .collect();
+    self.read_back(device, queue, &ranges)
+  }
+
+  /// Copies the `(offset, size)` byte ranges of the current particle buffer into one staging
+  /// buffer and reads it back.
+  fn read_back(
+    &self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    ranges: &[(u64, u64)],
+  ) -> Vec<Particle> {
+    let particle_buffer = &self.particle_buffers[self.frame_num % 2];
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particle Staging Buffer"),
+      size: ranges.iter().map(|(_, size)| size).sum(),
+      usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("Readback Command Encoder"),
+    });
+    let mut destination = 0;
+    for &(offset, size) in ranges {
+      command_encoder.copy_buffer_to_buffer(
+        particle_buffer,
+        offset,
+        &staging_buffer,
+        destination,
+        size,
+      );
+      destination += size;
     }
-    self.frame_num += 1;
     queue.submit(Some(command_encoder.finish()));
+
+    let buffer_slice = staging_buffer.slice(..);
+    buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let particles = bytemuck::cast_slice(&buffer_slice.get_mapped_range()).to_vec();
+    staging_buffer.unmap();
+    particles
   }
 
   pub fn render(
@@ -260,6 +705,24 @@ impl Render {
     sim_params: &SimParams,
   ) {
     self.compute(device, queue, sim_params);
+    self.draw(view, device, queue, camera_bind_group, sim_params);
+  }
+
+  /// Draws the current particle buffer without advancing the simulation.
+  pub fn draw(
+    &self,
+    view: &wgpu::TextureView,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    camera_bind_group: &wgpu::BindGroup,
+    sim_params: &SimParams,
+  ) {
+    // the CPU backend never writes the parameters in `compute`
+    queue.write_buffer(
+      &self.sim_param_buffer,
+      0,
+      bytemuck::cast_slice(&[*sim_params]),
+    );
     let color_attachments = [Some(wgpu::RenderPassColorAttachment {
       view,
       resolve_target: None,
@@ -279,17 +742,37 @@ impl Render {
       label: Some("Render Command Encoder"),
     });
 
-    if let (Some(render_pipeline), Some(vertices_buffer)) =
-      (&self.render_pipeline, &self.vertices_buffer)
-    {
+    if let (Some(render_pipeline), Some(vertices_buffer), Some(draw_bind_group)) = (
+      &self.render_pipeline,
+      &self.vertices_buffer,
+      &self.draw_bind_group,
+    ) {
       let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
       rpass.set_pipeline(render_pipeline);
       rpass.set_bind_group(0, camera_bind_group, &[]);
+      rpass.set_bind_group(1, draw_bind_group, &[]);
       rpass.set_vertex_buffer(0, self.particle_buffers[self.frame_num % 2].slice(..));
       rpass.set_vertex_buffer(1, vertices_buffer.slice(..));
-      rpass.draw(0..3, 0..sim_params.num_particles * sim_params.num_galaxies);
+      rpass.draw(0..3, 0..sim_params.num_particles);
     }
 
     queue.submit(Some(command_encoder.finish()));
   }
 }
+
+/// Records one compute pass of `workgroups` workgroups.
+fn dispatch(
+  command_encoder: &mut wgpu::CommandEncoder,
+  pipeline: &wgpu::ComputePipeline,
+  bind_group: &wgpu::BindGroup,
+  stage_offset: u32,
+  workgroups: u32,
+) {
+  let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+    label: Some("Compute Pass Descriptor"),
+    timestamp_writes: None,
+  });
+  cpass.set_pipeline(pipeline);
+  cpass.set_bind_group(0, bind_group, &[stage_offset]);
+  cpass.dispatch_workgroups(workgroups, 1, 1);
+}
@@ -0,0 +1,721 @@
+use crate::{
+  block_timestep::BlockTimesteps,
+  cosmology::Cosmology,
+  encounter::{Encounter, Orientation},
+  friction::Friction,
+  halo::HaloProfile,
+  initialize::{Bulge, Disk, InitialConditions},
+  integrator::Integrator,
+  post_newtonian::PostNewtonian,
+  sph::Eos,
+  zeldovich::PowerSpectrum,
+  Backend, DependentChoices, Galaxy, InvalidConfig, Kernel, Orbit, RunConfig, Setting, SimParams,
+  Solver,
+};
+use clap::ValueEnum;
+use serde::{de, Deserialize, Deserializer};
+use std::{
+  fmt, fs, io,
+  marker::PhantomData,
+  path::{Path, PathBuf},
+};
+
+/// A whole simulation described in a TOML file, loaded with `--scenario`.
+///
+/// Every table and key is optional and falls back to the command line's default; unknown keys
+/// are errors. Choices take the names of the matching command-line values, and output paths are
+/// relative to the working directory:
+///
+/// ```toml
+/// [physics]
+/// delta_t = 0.001
+/// halo = "nfw"
+///
+/// [integrator]
+/// scheme = "leapfrog"
+/// solver = "barnes-hut"
+/// theta = 0.7
+///
+/// [encounter]
+/// pericenter = 0.3
+/// inclination = [0.0, 60.0]
+///
+/// [camera]
+/// eye = [0.0, 3.0, 4.0]
+///
+/// [output]
+/// headless = true
+/// diagnostics_every = 50
+///
+/// [[galaxy]]
+/// generator = "spiral"
+/// disk = "exponential"
+/// toomre_q = 1.2
+///
+/// [[galaxy]]
+/// particles = 4000
+/// central_mass = 30000.0
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Scenario {
+  physics: PhysicsTable,
+  integrator: IntegratorTable,
+  initial_conditions: InitialConditionsTable,
+  cosmology: Option<CosmologyTable>,
+  encounter: Option<EncounterTable>,
+  camera: CameraTable,
+  output: OutputTable,
+  #[serde(rename = "galaxy")]
+  galaxies: Vec<GalaxyTable>,
+}
+
+/// `[physics]`: constants, halos, friction, gas and black holes.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PhysicsTable {
+  delta_t: Option<f32>,
+  gravity: Option<f32>,
+  /// Square of the softening length
+  softening: Option<f32>,
+  damping: Option<f32>,
+  coulomb_log: Option<f32>,
+  friction_radius: Option<f32>,
+  halo: Option<Choice<HaloProfile>>,
+  live_halo: Option<Choice<HaloProfile>>,
+  friction: Option<Choice<Friction>>,
+  gas_fraction: Option<f32>,
+  eos: Option<Choice<Eos>>,
+  smoothing_length: Option<f32>,
+  sound_speed: Option<f32>,
+  viscosity_alpha: Option<f32>,
+  star_formation_threshold: Option<f32>,
+  star_formation_efficiency: Option<f32>,
+  accretion_radius: Option<f32>,
+  regularization_radius: Option<f32>,
+  post_newtonian: Option<Choice<PostNewtonian>>,
+  speed_of_light: Option<f32>,
+}
+
+/// `[integrator]`: time integration and the gravity solver.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IntegratorTable {
+  scheme: Option<Choice<Integrator>>,
+  backend: Option<Choice<Backend>>,
+  kernel: Option<Choice<Kernel>>,
+  solver: Option<SolverName>,
+  theta: Option<f32>,
+  grid: Option<u32>,
+  block_levels: Option<u32>,
+  block_eta: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SolverName {
+  Direct,
+  BarnesHut,
+  ParticleMesh,
+}
+
+/// `[initial_conditions]`: the seed, defaults of the `[[galaxy]]` tables and the parameters of
+/// the Zel'dovich lattice and the collapsing clouds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct InitialConditionsTable {
+  /// `zeldovich` replaces the galaxies; anything else is the default of `[[galaxy]]` tables
+  generator: Option<Choice<InitialConditions>>,
+  bulge: Option<Choice<Bulge>>,
+  disk: Option<Choice<Disk>>,
+  toomre_q: Option<f32>,
+  arms: Option<u32>,
+  pitch_angle: Option<f32>,
+  arm_strength: Option<f32>,
+  bar_strength: Option<f32>,
+  sphere_radius: Option<f32>,
+  cloud_radius: Option<f32>,
+  seed: Option<u32>,
+  spin_parameter: Option<f32>,
+  virial_ratio: Option<f32>,
+  turbulent: Option<bool>,
+  lattice: Option<u32>,
+  power_spectrum: Option<Choice<PowerSpectrum>>,
+  spectral_index: Option<f32>,
+  turnover_scale: Option<f32>,
+  fluctuation_amplitude: Option<f32>,
+}
+
+/// `[cosmology]`: a periodic comoving box, enabled by the table's presence.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CosmologyTable {
+  box_size: f32,
+  omega_matter: Option<f32>,
+  omega_lambda: Option<f32>,
+  initial_redshift: Option<f32>,
+}
+
+/// `[encounter]`: a Kepler orbit for exactly two galaxies, enabled by the table's presence.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EncounterTable {
+  pericenter: f32,
+  #[serde(default = "parabolic")]
+  eccentricity: f32,
+  #[serde(default = "initial_separation")]
+  separation: f32,
+  #[serde(default)]
+  inclination: [f32; 2],
+  #[serde(default)]
+  pericenter_argument: [f32; 2],
+}
+
+fn parabolic() -> f32 {
+  1.0
+}
+
+fn initial_separation() -> f32 {
+  2.0
+}
+
+/// `[camera]`: where the window's camera starts and how fast it moves.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraTable {
+  eye: Option<[f32; 3]>,
+  target: Option<[f32; 3]>,
+  speed: Option<f32>,
+  rotational_speed: Option<f32>,
+}
+
+/// `[output]`: the window, diagnostics and files written by headless runs.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputTable {
+  headless: Option<bool>,
+  diagnostics_every: Option<u32>,
+  sfr_output: Option<PathBuf>,
+  sfr_bin: Option<f32>,
+  gw_output: Option<PathBuf>,
+  gw_observer: Option<[f32; 3]>,
+  gw_distance: Option<f32>,
+}
+
+/// `[[galaxy]]`: one galaxy, its generator and its orbit. There may be at most `MAX_GALAXIES`
+/// (16) of them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GalaxyTable {
+  generator: Option<Choice<InitialConditions>>,
+  bulge: Option<Choice<Bulge>>,
+  disk: Option<Choice<Disk>>,
+  particles: Option<u32>,
+  /// Defaults to 0 for the `plummer` and `hernquist` generators
+  central_mass: Option<f32>,
+  bulge_fraction: Option<f32>,
+  bulge_radius: Option<f32>,
+  disk_radius: Option<f32>,
+  disk_height: Option<f32>,
+  halo_velocity: Option<f32>,
+  halo_radius: Option<f32>,
+  /// Defaults to half of `particles`
+  halo_particles: Option<u32>,
+  cloud_radius: Option<f32>,
+  sphere_radius: Option<f32>,
+  toomre_q: Option<f32>,
+  arms: Option<u32>,
+  pitch_angle: Option<f32>,
+  arm_strength: Option<f32>,
+  bar_strength: Option<f32>,
+  /// Starting position of the centre; with `velocity`, replaces the placement on a circle
+  position: Option<[f32; 3]>,
+  velocity: Option<[f32; 3]>,
+}
+
+/// A `clap::ValueEnum` read from its command-line name.
+#[derive(Copy, Clone, Debug)]
+struct Choice<T>(T);
+
+impl<'de, T: ValueEnum> Deserialize<'de> for Choice<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_str(ChoiceVisitor(PhantomData))
+  }
+}
+
+struct ChoiceVisitor<T>(PhantomData<T>);
+
+impl<T: ValueEnum> de::Visitor<'_> for ChoiceVisitor<T> {
+  type Value = Choice<T>;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    let names: Vec<_> = T::value_variants()
+      .iter()
+      .filter_map(ValueEnum::to_possible_value)
+      .map(|value| format!("`{}`", value.get_name()))
+      .collect();
+    write!(formatter, "one of {}", names.join(", "))
+  }
+
+  fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+    T::from_str(name, false)
+      .map(Choice)
+      .map_err(|_| E::invalid_value(de::Unexpected::Str(name), &self))
+  }
+}
+
+/// Why a scenario could not be loaded.
+#[derive(Debug)]
+pub enum ScenarioError {
+  Read(io::Error),
+  /// Malformed TOML, an unknown key or a value of the wrong type
+  Parse(toml::de::Error),
+  /// Values that parse but describe an impossible simulation
+  Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ScenarioError::Read(error) => write!(f, "{error}"),
+      // the message ends with a newline after the quoted line
+      ScenarioError::Parse(error) => write!(f, "{}", error.to_string().trim_end()),
+      ScenarioError::Invalid(message) => write!(f, "{message}"),
+    }
+  }
+}
+
+impl std::error::Error for ScenarioError {}
+
+/// Reads the scenario at `path` into the configuration of a run.
+pub fn load(path: &Path) -> Result<RunConfig, ScenarioError> {
+  parse(&fs::read_to_string(path).map_err(ScenarioError::Read)?)
+}
+
+/// The configuration of a run described by the scenario `text`.
+fn parse(text: &str) -> Result<RunConfig, ScenarioError> {
+  let scenario: Scenario = toml::from_str(text).map_err(ScenarioError::Parse)?;
+  let config = scenario.run_config()?;
+  config
+    .validate()
+    .map_err(|error| ScenarioError::Invalid(error.describe(key)))?;
+  Ok(config)
+}
+
+/// The key or table that sets `setting` in a scenario.
+fn key(setting: Setting) -> &'static str {
+  match setting {
+    Setting::DeltaT => "physics.delta_t",
+    Setting::Gravity => "physics.gravity",
+    Setting::Softening => "physics.softening",
+    Setting::SmoothingLength => "physics.smoothing_length",
+    Setting::SoundSpeed => "physics.sound_speed",
+    Setting::BlockLevels => "integrator.block_levels",
+    Setting::BlockEta => "integrator.block_eta",
+    Setting::GasFraction => "physics.gas_fraction",
+    Setting::RegularizationRadius => "physics.regularization_radius",
+    Setting::AccretionRadius => "physics.accretion_radius",
+    Setting::PostNewtonian => "physics.post_newtonian",
+    Setting::Halo => "physics.halo",
+    Setting::LiveHalo => "physics.live_halo",
+    Setting::Friction => "physics.friction",
+    Setting::GwOutput => "output.gw_output",
+    Setting::GwObserver => "output.gw_observer",
+    Setting::SfrBin => "output.sfr_bin",
+    Setting::Theta => "integrator.theta",
+    Setting::Grid => "integrator.grid",
+    Setting::Cosmology => "[cosmology]",
+    Setting::BoxSize => "cosmology.box_size",
+    Setting::OmegaMatter => "cosmology.omega_matter",
+    Setting::InitialRedshift => "cosmology.initial_redshift",
+    Setting::TurnoverScale => "initial_conditions.turnover_scale",
+    Setting::SpinParameter => "initial_conditions.spin_parameter",
+    Setting::VirialRatio => "initial_conditions.virial_ratio",
+    Setting::Galaxies => "the number of [[galaxy]] tables",
+    Setting::Encounter => "[encounter]",
+    Setting::Pericenter => "encounter.pericenter",
+    Setting::Separation => "encounter.separation",
+    Setting::Eccentricity => "encounter.eccentricity",
+    Setting::Generator => "galaxy.generator",
+    Setting::Particles => "galaxy.particles",
+    Setting::CentralMass => "galaxy.central_mass",
+    Setting::BulgeFraction => "galaxy.bulge_fraction",
+    Setting::BulgeRadius => "galaxy.bulge_radius",
+    Setting::DiskRadius => "galaxy.disk_radius",
+    Setting::DiskHeight => "galaxy.disk_height",
+    Setting::HaloVelocity => "galaxy.halo_velocity",
+    Setting::HaloRadius => "galaxy.halo_radius",
+    Setting::CloudRadius => "galaxy.cloud_radius",
+    Setting::SphereRadius => "galaxy.sphere_radius",
+    Setting::ToomreQ => "galaxy.toomre_q",
+    Setting::PitchAngle => "galaxy.pitch_angle",
+    Setting::ArmStrength => "galaxy.arm_strength",
+    Setting::BarStrength => "galaxy.bar_strength",
+    Setting::Orbit => "galaxy.position and galaxy.velocity",
+  }
+}
+
+impl Scenario {
+  fn run_config(self) -> Result<RunConfig, ScenarioError> {
+    let mut config = RunConfig::default();
+    let defaults = SimParams::default();
+
+    let physics = self.physics;
+    let constants = &mut config.physics;
+    set(&mut constants.delta_t, physics.delta_t);
+    set(&mut constants.gravity, physics.gravity);
+    set(&mut constants.softening, physics.softening);
+    set(&mut constants.damping, physics.damping);
+    set(&mut constants.coulomb_log, physics.coulomb_log);
+    set(&mut constants.friction_radius, physics.friction_radius);
+    set(&mut constants.smoothing_length, physics.smoothing_length);
+    set(&mut constants.sound_speed, physics.sound_speed);
+    set(&mut constants.viscosity_alpha, physics.viscosity_alpha);
+    set(&mut config.gas_fraction, physics.gas_fraction);
+    choose(&mut config.eos, physics.eos);
+    set(
+      &mut config.star_formation_threshold,
+      physics.star_formation_threshold,
+    );
+    set(
+      &mut config.star_formation_efficiency,
+      physics.star_formation_efficiency,
+    );
+    set(&mut config.accretion_radius, physics.accretion_radius);
+    set(
+      &mut config.regularization_radius,
+      physics.regularization_radius,
+    );
+    choose(&mut config.post_newtonian, physics.post_newtonian);
+    set(&mut config.speed_of_light, physics.speed_of_light);
+
+    config.cosmology = self.cosmology.map(|cosmology| Cosmology {
+      box_size: cosmology.box_size,
+      omega_matter: cosmology.omega_matter.unwrap_or(defaults.omega_matter),
+      omega_lambda: cosmology.omega_lambda.unwrap_or(defaults.omega_lambda),
+      initial_redshift: cosmology
+        .initial_redshift
+        .unwrap_or(defaults.initial_redshift),
+    });
+    choose(&mut config.live_halo, physics.live_halo);
+
+    let integrator = self.integrator;
+    choose(&mut config.integrator, integrator.scheme);
+    choose(&mut config.backend, integrator.backend);
+    choose(&mut config.kernel, integrator.kernel);
+    let stray =
+      if integrator.theta.is_some() && !matches!(integrator.solver, Some(SolverName::BarnesHut)) {
+        Some((Setting::Theta, "barnes-hut"))
+      } else if integrator.grid.is_some()
+        && !matches!(integrator.solver, Some(SolverName::ParticleMesh))
+      {
+        Some((Setting::Grid, "particle-mesh"))
+      } else {
+        None
+      };
+    if let Some((setting, solver)) = stray {
+      let error = InvalidConfig::new(setting, format!("only applies to the {solver} solver"));
+      return Err(ScenarioError::Invalid(error.describe(key)));
+    }
+    config.solver = match integrator.solver {
+      None | Some(SolverName::Direct) => Solver::Direct,
+      Some(SolverName::BarnesHut) => Solver::BarnesHut {
+        theta: integrator.theta.unwrap_or(Solver::DEFAULT_THETA),
+      },
+      Some(SolverName::ParticleMesh) => Solver::ParticleMesh {
+        grid: integrator.grid.unwrap_or(Solver::DEFAULT_GRID),
+      },
+    };
+    config.block_timesteps =
+      integrator
+        .block_levels
+        .filter(|&levels| levels > 0)
+        .map(|max_level| BlockTimesteps {
+          max_level,
+          eta: integrator.block_eta.unwrap_or(BlockTimesteps::DEFAULT_ETA),
+        });
+
+    let initial = self.initial_conditions;
+    choose(&mut config.initial_conditions, initial.generator);
+    set(&mut config.seed, initial.seed);
+    set(&mut config.spin_parameter, initial.spin_parameter);
+    set(&mut config.virial_ratio, initial.virial_ratio);
+    set(&mut config.turbulent, initial.turbulent);
+    choose(&mut config.power_spectrum, initial.power_spectrum);
+    set(&mut config.spectral_index, initial.spectral_index);
+    set(&mut config.turnover_scale, initial.turnover_scale);
+    set(
+      &mut config.fluctuation_amplitude,
+      initial.fluctuation_amplitude,
+    );
+
+    if config.initial_conditions == InitialConditions::Zeldovich {
+      if !self.galaxies.is_empty() {
+        return Err(ScenarioError::Invalid(
+          "the zeldovich generator fills the box with a lattice and takes no [[galaxy]] tables"
+            .into(),
+        ));
+      }
+      let lattice = initial.lattice.unwrap_or(32);
+      let particles = lattice
+        .checked_pow(3)
+        .filter(|_| lattice >= 2)
+        .ok_or_else(|| {
+          ScenarioError::Invalid(
+            "initial_conditions.lattice must be at least 2 with its cube fitting in 32 bits".into(),
+          )
+        })?;
+      config.galaxies = vec![Galaxy {
+        particles,
+        ..Galaxy::default()
+      }];
+    } else {
+      let mut template = Galaxy {
+        generator: config.initial_conditions,
+        central_mass: config.initial_conditions.default_central_mass(),
+        ..Galaxy::default()
+      };
+      choose(&mut template.bulge, initial.bulge);
+      choose(&mut template.disk, initial.disk);
+      set(&mut template.cloud_radius, initial.cloud_radius);
+      set(&mut template.sphere_radius, initial.sphere_radius);
+      set(&mut template.toomre_q, initial.toomre_q);
+      set(&mut template.arms, initial.arms);
+      set(&mut template.pitch_angle, initial.pitch_angle);
+      set(&mut template.arm_strength, initial.arm_strength);
+      set(&mut template.bar_strength, initial.bar_strength);
+      config.galaxies = if self.galaxies.is_empty() {
+        vec![template]
+      } else {
+        self
+          .galaxies
+          .into_iter()
+          .map(|galaxy| galaxy.galaxy(template))
+          .collect()
+      };
+    }
+
+    config.encounter = self.encounter.map(|encounter| Encounter {
+      pericenter: encounter.pericenter,
+      eccentricity: encounter.eccentricity,
+      separation: encounter.separation,
+      disks: [0, 1].map(|i| Orientation {
+        inclination: encounter.inclination[i],
+        pericenter_argument: encounter.pericenter_argument[i],
+      }),
+    });
+
+    let camera = self.camera;
+    set(&mut config.camera.eye, camera.eye);
+    set(&mut config.camera.target, camera.target);
+    set(&mut config.camera.speed, camera.speed);
+    set(&mut config.camera.rotational_speed, camera.rotational_speed);
+
+    let output = self.output;
+    set(&mut config.headless, output.headless);
+    config.sfr_output = output.sfr_output;
+    set(&mut config.sfr_bin, output.sfr_bin);
+    config.gw_output = output.gw_output;
+    set(&mut config.gw_observer, output.gw_observer);
+    set(&mut config.gw_distance, output.gw_distance);
+    config.choose(DependentChoices {
+      halo: physics.halo.map(|Choice(halo)| halo),
+      friction: physics.friction.map(|Choice(friction)| friction),
+      diagnostics_every: output.diagnostics_every,
+    });
+    Ok(config)
+  }
+}
+
+impl GalaxyTable {
+  fn galaxy(self, template: Galaxy) -> Galaxy {
+    let mut galaxy = template;
+    choose(&mut galaxy.generator, self.generator);
+    choose(&mut galaxy.bulge, self.bulge);
+    choose(&mut galaxy.disk, self.disk);
+    set(&mut galaxy.particles, self.particles);
+    galaxy.central_mass = self
+      .central_mass
+      .unwrap_or(galaxy.generator.default_central_mass());
+    set(&mut galaxy.bulge_fraction, self.bulge_fraction);
+    set(&mut galaxy.bulge_radius, self.bulge_radius);
+    set(&mut galaxy.disk_radius, self.disk_radius);
+    set(&mut galaxy.disk_height, self.disk_height);
+    set(&mut galaxy.halo_velocity, self.halo_velocity);
+    set(&mut galaxy.halo_radius, self.halo_radius);
+    galaxy.live_halo_particles = self
+      .halo_particles
+      .unwrap_or(Galaxy::default_live_halo_particles(galaxy.particles));
+    set(&mut galaxy.cloud_radius, self.cloud_radius);
+    set(&mut galaxy.sphere_radius, self.sphere_radius);
+    set(&mut galaxy.toomre_q, self.toomre_q);
+    set(&mut galaxy.arms, self.arms);
+    set(&mut galaxy.pitch_angle, self.pitch_angle);
+    set(&mut galaxy.arm_strength, self.arm_strength);
+    set(&mut galaxy.bar_strength, self.bar_strength);
+    if self.position.is_some() || self.velocity.is_some() {
+      galaxy.orbit = Some(Orbit {
+        position: self.position.unwrap_or_default(),
+        velocity: self.velocity.unwrap_or_default(),
+      });
+    }
+    galaxy
+  }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+  if let Some(value) = value {
+    *field = value;
+  }
+}
+
+fn choose<T>(field: &mut T, value: Option<Choice<T>>) {
+  set(field, value.map(|Choice(value)| value));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The example in the documentation of `Scenario`.
+  fn documented_example() -> String {
+    include_str!("scenario.rs")
+      .lines()
+      .skip_while(|line| *line != "/// ```toml")
+      .skip(1)
+      .take_while(|line| *line != "/// ```")
+      .map(|line| line.trim_start_matches("///").trim_start())
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  #[test]
+  fn documented_example_loads() {
+    let config = parse(&documented_example()).unwrap();
+    assert_eq!(config.halo, HaloProfile::Nfw);
+    assert_eq!(config.solver, Solver::BarnesHut { theta: 0.7 });
+    assert_eq!(config.encounter.unwrap().disks[1].inclination, 60.0);
+    assert_eq!(config.camera.eye, [0.0, 3.0, 4.0]);
+    assert!(config.headless);
+    assert_eq!(config.diagnostics_every, 50);
+    let [first, second] = &config.galaxies[..] else {
+      panic!("{:?}", config.galaxies);
+    };
+    assert_eq!(
+      (first.generator, first.disk, first.toomre_q),
+      (InitialConditions::Spiral, Disk::Exponential, 1.2)
+    );
+    assert_eq!(second.toomre_q, Galaxy::default().toomre_q);
+    assert_eq!((second.particles, second.central_mass), (4000, 30000.0));
+  }
+
+  #[test]
+  fn defaults_follow_the_rest_of_the_scenario() {
+    let window = parse("").unwrap();
+    assert_eq!(
+      (window.halo, window.friction, window.diagnostics_every),
+      (HaloProfile::PseudoIsothermal, Friction::Legacy, 0)
+    );
+    assert_eq!(
+      window.galaxies[0].live_halo_particles,
+      window.galaxies[0].particles / 2
+    );
+    let headless_box = parse("[output]\nheadless = true\n[cosmology]\nbox_size = 1.0").unwrap();
+    assert_eq!(
+      (
+        headless_box.halo,
+        headless_box.friction,
+        headless_box.diagnostics_every
+      ),
+      (HaloProfile::None, Friction::None, 0)
+    );
+  }
+
+  #[test]
+  fn misspelt_keys_are_rejected() {
+    let error = parse("[physics]\ndelta = 0.001").unwrap_err();
+    assert!(
+      matches!(&error, ScenarioError::Parse(_))
+        && error.to_string().contains("unknown field `delta`"),
+      "{error}"
+    );
+  }
+
+  #[test]
+  fn unknown_choices_list_the_valid_names() {
+    let error = parse("[physics]\nhalo = \"nwf\"").unwrap_err();
+    let message = error.to_string();
+    assert!(matches!(error, ScenarioError::Parse(_)), "{message}");
+    for name in ["`none`", "`pseudo-isothermal`", "`nfw`", "`hernquist`"] {
+      assert!(message.contains(name), "{message}");
+    }
+  }
+
+  #[test]
+  fn impossible_galaxy_layouts_are_invalid() {
+    for text in [
+      "[encounter]\npericenter = 0.3",
+      "[encounter]\npericenter = 0.3\n[[galaxy]]\n[[galaxy]]\n[[galaxy]]",
+      "[initial_conditions]\ngenerator = \"zeldovich\"\n[cosmology]\nbox_size = 1.0\n[[galaxy]]",
+    ] {
+      assert!(
+        matches!(parse(text), Err(ScenarioError::Invalid(message)) if message.contains("[[galaxy]]")),
+        "{text}"
+      );
+    }
+    assert!(parse("[encounter]\npericenter = 0.3\n[[galaxy]]\n[[galaxy]]").is_ok());
+  }
+
+  #[test]
+  fn invalid_settings_are_named_by_their_keys() {
+    for (text, expected) in [
+      (
+        "[physics]\ndelta_t = 0.0",
+        "physics.delta_t must be positive",
+      ),
+      (
+        "[integrator]\nblock_levels = 40",
+        "integrator.block_levels must be at most 16",
+      ),
+      (
+        "[integrator]\nblock_levels = 4\nblock_eta = 0.0",
+        "integrator.block_eta must be positive",
+      ),
+      (
+        "[integrator]\nsolver = \"particle-mesh\"\ntheta = 0.7",
+        "integrator.theta only applies to the barnes-hut solver",
+      ),
+      (
+        "[integrator]\ngrid = 32",
+        "integrator.grid only applies to the particle-mesh solver",
+      ),
+      (
+        "[[galaxy]]\n[[galaxy]]\ntoomre_q = -1.0",
+        "galaxy.toomre_q must be positive in galaxy 2",
+      ),
+      (
+        "[[galaxy]]\nhalo_velocity = -0.1",
+        "galaxy.halo_velocity must not be negative in galaxy 1",
+      ),
+      (
+        "[[galaxy]]\nparticles = 4000000000\n[[galaxy]]\nparticles = 4000000000",
+        "galaxy.particles must fit in 32 bits summed over the galaxies",
+      ),
+    ] {
+      assert!(
+        matches!(parse(text), Err(ScenarioError::Invalid(message)) if message == expected),
+        "{text}"
+      );
+    }
+  }
+
+  #[test]
+  fn strain_output_needs_regularization() {
+    let text = "[output]\ngw_output = \"strain.csv\"";
+    assert!(
+      matches!(parse(text), Err(ScenarioError::Invalid(message)) if message.contains("physics.regularization_radius")),
+      "{text}"
+    );
+    assert!(parse(&format!("{text}\n[physics]\nregularization_radius = 0.01")).is_ok());
+  }
+}
@@ -4,8 +4,30 @@ struct Particle {
     acc: array<f32, 3>,
     mass: f32,
     galaxy_id: u32,
+    kind: u32,
+    density: f32,
+    internal_energy: f32,
+    energy_rate: f32,
+    formation_time: f32,
 };
 
+// `ParticleKind` discriminants
+const KIND_STAR: u32 = 0u;
+const KIND_GAS: u32 = 1u;
+const KIND_DARK_MATTER: u32 = 2u;
+const KIND_BLACK_HOLE: u32 = 3u;
+const KIND_ACCRETED: u32 = 4u;
+
+// Core and halo scale of one galaxy; see `halo.rs`
+struct GalaxyHalo {
+    core: u32,
+    velocity: f32,
+    radius: f32,
+    _pad: u32,
+};
+
+const MAX_GALAXIES: u32 = 16u;
+
 struct SimParams {
     dt: f32,
     g: f32,
@@ -20,31 +42,209 @@ struct SimParams {
     halo_v: f32,
     halo_r: f32,
     damping: f32,
+    time: f32,
+    halo_profile: u32,
+    live_halo: u32,
+    friction: u32,
+    coulomb_log: f32,
+    friction_radius: f32,
+    gas_fraction: f32,
+    smoothing_length: f32,
+    sound_speed: f32,
+    eos: u32,
+    viscosity_alpha: f32,
+    star_formation_threshold: f32,
+    star_formation_efficiency: f32,
+    accretion_radius: f32,
+    regularization_radius: f32,
+    post_newtonian: u32,
+    speed_of_light: f32,
+    // Cosmology mode is handled on the host
+    box_size: f32,
+    omega_matter: f32,
+    omega_lambda: f32,
+    initial_redshift: f32,
+    _pad0: u32,
+    _pad1: u32,
+    galaxies: array<GalaxyHalo, MAX_GALAXIES>,
+};
+
+// A galaxy core and the background it moves through; see `friction.rs`
+struct Core {
+    index: u32,
+    density: f32,
+    sigma: f32,
+    background_vel: array<f32, 3>,
+};
+
+const PI: f32 = 3.14159265;
+
+// `Eos` discriminants and the adiabatic index; see `sph.rs`
+const EOS_ADIABATIC: u32 = 1u;
+const GAMMA: f32 = 1.6666667;
+const VISCOSITY_BETA_PER_ALPHA: f32 = 2.0;
+// Spatial hash of gas particles with cells of side 2h, counting-sorted so no cell can overflow
+const SPH_TABLE_SIZE: u32 = 65536u;
+const SPH_SCAN_CHUNK: u32 = SPH_TABLE_SIZE / 64u;
+
+// `Friction` discriminants
+const FRICTION_LEGACY: u32 = 1u;
+const FRICTION_CHANDRASEKHAR: u32 = 2u;
+
+// Per-pass coefficient, bound with a dynamic offset so one submission can run every stage
+struct StageParams {
+    coefficient: f32,
+    _pad0: f32,
+    _pad1: f32,
+    _pad2: f32,
 };
 
 @group(0) @binding(0) var<uniform> params: SimParams;
 @group(0) @binding(1) var<storage, read> particlesSrc: array<Particle>;
 @group(0) @binding(2) var<storage, read_write> particlesDst: array<Particle>;
+@group(0) @binding(3) var<uniform> stage: StageParams;
+@group(0) @binding(4) var<storage, read_write> cores: array<Core>;
+@group(0) @binding(5) var<storage, read_write> cellCounts: array<atomic<u32>>;
+@group(0) @binding(6) var<storage, read_write> cellItems: array<u32>;
+@group(0) @binding(7) var<storage, read_write> cellStarts: array<u32>;
 
+// Particles staged per tile by `force_tiled`; must match its workgroup size
+const TILE_SIZE: u32 = 64u;
+var<workgroup> tile: array<vec4<f32>, TILE_SIZE>;
 
+// Per-thread partial sums reduced by `core_environment`: (mass, momentum) and m v^2
+var<workgroup> partialMomentum: array<vec4<f32>, 64>;
+var<workgroup> partialEnergy: array<f32, 64>;
+// Per-thread bucket totals scanned by `sph_scan`
+var<workgroup> partialCounts: array<u32, 64>;
 
+fn to_vec3(a: array<f32, 3>) -> vec3<f32> {
+    return vec3<f32>(a[0], a[1], a[2]);
+}
+
+fn to_array(v: vec3<f32>) -> array<f32, 3> {
+    return array<f32, 3>(v.x, v.y, v.z);
+}
+
+// Plummer-softened acceleration towards a body of `mass` at `otherPosition`
+fn plummer_acceleration(position: vec3<f32>, otherPosition: vec3<f32>, mass: f32) -> vec3<f32> {
+    let displacement = otherPosition - position;
+    let r = distance(position, otherPosition);
+
+    // Skip extremely close particles to prevent numerical instability
+    if (r < 0.000001) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+
+    // Calculate gravitational force
+    // Plummer potential: F = GM * r / (r^2 + e)^1.5
+    // Vector form: F_vec = (GM / (r^2 + e)^1.5) * displacement
+    let dist_sq = r * r + params.e;
+    let force_magnitude = params.g * mass / (dist_sq * sqrt(dist_sq));
+    return force_magnitude * displacement;
+}
+
+// Acceleration from the analytic halo around every galaxy's central mass; see `halo.rs`
+fn halo_acceleration(position: vec3<f32>) -> vec3<f32> {
+    var acceleration = vec3<f32>(0.0, 0.0, 0.0);
+    if (params.halo_profile == 0u) {
+        return acceleration;
+    }
+    for (var g: u32 = 0u; g < params.num_galaxies; g++) {
+        let halo = params.galaxies[g];
+        let v_sq = halo.velocity * halo.velocity;
+        let r_s = halo.radius;
+        let offset = position - to_vec3(particlesSrc[halo.core].pos);
+        let r = length(offset);
+        if (r < 0.000001) {
+            continue;
+        }
+        var speed_sq = 0.0;
+        if (params.halo_profile == 1u) {
+            speed_sq = v_sq * r * r / (r * r + r_s * r_s);
+        } else if (params.halo_profile == 2u) {
+            let x = r / r_s;
+            // ln(1 + x) - x / (1 + x) cancels badly near the centre
+            var enclosed = log(1.0 + x) - x / (1.0 + x);
+            if (x < 0.01) {
+                enclosed = x * x * (0.5 - 2.0 * x / 3.0);
+            }
+            speed_sq = v_sq * enclosed / x;
+        } else {
+            speed_sq = v_sq * r_s * r / ((r + r_s) * (r + r_s));
+        }
+        acceleration -= offset * (speed_sq / (r * r));
+    }
+    return acceleration;
+}
+
+// Abramowitz & Stegun 7.1.26
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * abs(x));
+    let poly = t * (0.2548296 + t * (-0.28449674 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    return sign(x) * (1.0 - poly * exp(-x * x));
+}
+
+// Chandrasekhar deceleration of a core of `mass` moving at `velocity` through `core`'s background
+fn chandrasekhar(core: Core, mass: f32, velocity: vec3<f32>) -> vec3<f32> {
+    let relative = velocity - to_vec3(core.background_vel);
+    let speed = length(relative);
+    if (core.density <= 0.0 || speed < 0.000001) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    let x = speed / (sqrt(2.0) * max(core.sigma, 0.000001));
+    let fraction = erf(x) - 2.0 * x / sqrt(PI) * exp(-x * x);
+    return -relative * (4.0 * PI * params.g * params.g * mass * core.density * params.coulomb_log * fraction
+        / (speed * speed * speed));
+}
+
+// Dynamical friction on galaxy cores, applied over `dt`; only walks the core list
+fn dynamical_friction(currentParticle: Particle, particleIndex: u32, velocity: vec3<f32>, dt: f32) -> vec3<f32> {
+    var newVelocity = velocity;
+    if (currentParticle.kind != KIND_BLACK_HOLE) {
+        return newVelocity;
+    }
+    let numCores = arrayLength(&cores);
+    for (var c: u32 = 0u; c < numCores; c++) {
+        let core = cores[c];
+        if (params.friction == FRICTION_LEGACY) {
+            let otherParticle = particlesSrc[core.index];
+            if (otherParticle.galaxy_id != currentParticle.galaxy_id) {
+                let relativeVelocity = newVelocity - to_vec3(otherParticle.vel);
+                let frictionForce = -params.damping * relativeVelocity;
+                newVelocity += frictionForce * dt;
+            }
+        } else if (params.friction == FRICTION_CHANDRASEKHAR && core.index == particleIndex) {
+            // an explicit step can overshoot; at most bring the core to rest in its background
+            let relative = newVelocity - to_vec3(core.background_vel);
+            var change = chandrasekhar(core, currentParticle.mass, newVelocity) * dt;
+            if (dot(change, change) > dot(relative, relative)) {
+                change = -relative;
+            }
+            newVelocity += change;
+        }
+    }
+    return newVelocity;
+}
+
+// vel += c * dt * acc, plus friction over the same interval
+fn kicked_velocity(currentParticle: Particle, particleIndex: u32, c: f32) -> vec3<f32> {
+    let dt = c * params.dt;
+    let velocity = to_vec3(currentParticle.vel) + to_vec3(currentParticle.acc) * dt;
+    return dynamical_friction(currentParticle, particleIndex, velocity, dt);
+}
+
+// Recomputes every particle's acceleration from the current positions
 @compute @workgroup_size(64)
-fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+fn force(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     let totalParticles = arrayLength(&particlesSrc);
     let particleIndex = global_invocation_id.x;
     if (particleIndex >= totalParticles) {
         return;
     }
 
-    let currentParticle = particlesSrc[particleIndex];
-    var position = vec3<f32>(currentParticle.pos[0], currentParticle.pos[1], currentParticle.pos[2]);
-    var velocity = vec3<f32>(currentParticle.vel[0], currentParticle.vel[1], currentParticle.vel[2]);
-    var acceleration = vec3<f32>(currentParticle.acc[0], currentParticle.acc[1], currentParticle.acc[2]);
-
-    // Leapfrog numerical integration
-    velocity += acceleration * params.dt / 2.0;
-    position += velocity * params.dt;
-
+    var currentParticle = particlesSrc[particleIndex];
+    let position = to_vec3(currentParticle.pos);
     var newAcceleration = vec3<f32>(0.0, 0.0, 0.0);
 
     for (var i: u32 = 0u; i < totalParticles; i++) {
@@ -53,44 +253,383 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
         }
 
         let otherParticle = particlesSrc[i];
-        let otherPosition = vec3<f32>(otherParticle.pos[0], otherParticle.pos[1], otherParticle.pos[2]);
-        let displacement = otherPosition - position;
-        let r = distance(position, otherPosition);
+        newAcceleration += plummer_acceleration(position, to_vec3(otherParticle.pos), otherParticle.mass);
+    }
 
-        // Skip extremely close particles to prevent numerical instability
-        if (r < 0.000001) {
-            continue;
+    newAcceleration += halo_acceleration(position);
+    currentParticle.acc = to_array(newAcceleration);
+    particlesDst[particleIndex] = currentParticle;
+}
+
+// Same as `force`, but each workgroup stages TILE_SIZE positions/masses at a time in workgroup
+// memory so every source particle is read from global memory once per workgroup instead of once
+// per thread.
+@compute @workgroup_size(64)
+fn force_tiled(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let totalParticles = arrayLength(&particlesSrc);
+    let particleIndex = global_invocation_id.x;
+    // out-of-range threads still have to help fill tiles and reach every barrier
+    let inRange = particleIndex < totalParticles;
+
+    var currentParticle = particlesSrc[min(particleIndex, totalParticles - 1u)];
+    let position = to_vec3(currentParticle.pos);
+    var newAcceleration = vec3<f32>(0.0, 0.0, 0.0);
+
+    let numTiles = (totalParticles + TILE_SIZE - 1u) / TILE_SIZE;
+    for (var t: u32 = 0u; t < numTiles; t++) {
+        let tileStart = t * TILE_SIZE;
+        let loadIndex = tileStart + local_index;
+        if (loadIndex < totalParticles) {
+            let loaded = particlesSrc[loadIndex];
+            tile[local_index] = vec4<f32>(to_vec3(loaded.pos), loaded.mass);
         }
+        workgroupBarrier();
 
-        // Calculate gravitational force
-        // Calculate gravitational force
-        // Plummer potential: F = GM * r / (r^2 + e)^1.5
-        // Vector form: F_vec = (GM / (r^2 + e)^1.5) * displacement
-        let dist_sq = r * r + params.e;
-        let force_magnitude = params.g * otherParticle.mass / (dist_sq * sqrt(dist_sq));
-        newAcceleration += force_magnitude * displacement;
-    }
-    velocity += newAcceleration * params.dt / 2.0;
-    
-    // Dynamical Friction 
-    if (currentParticle.mass == params.central_mass) {
-        for (var i: u32 = 0u; i < totalParticles; i++) {
-            let otherParticle = particlesSrc[i];
-            if (otherParticle.mass == params.central_mass && 
-                otherParticle.galaxy_id != currentParticle.galaxy_id) {
-                let otherVelocity = vec3<f32>(otherParticle.vel[0], otherParticle.vel[1], otherParticle.vel[2]);
-                let relativeVelocity = velocity - otherVelocity;
-                let frictionForce = -params.damping * relativeVelocity;
-                velocity += frictionForce * params.dt;
+        let tileCount = min(TILE_SIZE, totalParticles - tileStart);
+        for (var j: u32 = 0u; j < tileCount; j++) {
+            if (tileStart + j == particleIndex) {
+                continue;
+            }
+            newAcceleration += plummer_acceleration(position, tile[j].xyz, tile[j].w);
+        }
+        workgroupBarrier();
+    }
+
+    if (!inRange) {
+        return;
+    }
+    newAcceleration += halo_acceleration(position);
+    currentParticle.acc = to_array(newAcceleration);
+    particlesDst[particleIndex] = currentParticle;
+}
+
+@compute @workgroup_size(64)
+fn kick(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    currentParticle.vel = to_array(kicked_velocity(currentParticle, particleIndex, stage.coefficient));
+    currentParticle.internal_energy = heated_internal_energy(currentParticle, stage.coefficient * params.dt);
+    particlesDst[particleIndex] = currentParticle;
+}
+
+@compute @workgroup_size(64)
+fn drift(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    let position = to_vec3(currentParticle.pos) + to_vec3(currentParticle.vel) * stage.coefficient * params.dt;
+    currentParticle.pos = to_array(position);
+    particlesDst[particleIndex] = currentParticle;
+}
+
+// Velocity Verlet position update: half kick, then a full drift with the kicked velocity
+@compute @workgroup_size(64)
+fn verlet_drift(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    let velocity = kicked_velocity(currentParticle, particleIndex, 0.5);
+    currentParticle.vel = to_array(velocity);
+    currentParticle.internal_energy = heated_internal_energy(currentParticle, 0.5 * params.dt);
+    currentParticle.pos = to_array(to_vec3(currentParticle.pos) + velocity * params.dt);
+    particlesDst[particleIndex] = currentParticle;
+}
+
+// Measures the density, mean velocity and dispersion within `friction_radius` of core
+// `workgroup_id.x`, one workgroup per core. Only needed by the Chandrasekhar friction model.
+@compute @workgroup_size(64)
+fn core_environment(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let slot = workgroup_id.x;
+    let center = to_vec3(particlesSrc[cores[slot].index].pos);
+    let radiusSq = params.friction_radius * params.friction_radius;
+    let totalParticles = arrayLength(&particlesSrc);
+
+    var momentum = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var energy = 0.0;
+    for (var i: u32 = local_index; i < totalParticles; i += 64u) {
+        let otherParticle = particlesSrc[i];
+        let offset = to_vec3(otherParticle.pos) - center;
+        if (otherParticle.kind != KIND_BLACK_HOLE && dot(offset, offset) < radiusSq) {
+            let velocity = to_vec3(otherParticle.vel);
+            momentum += vec4<f32>(otherParticle.mass, velocity * otherParticle.mass);
+            energy += otherParticle.mass * dot(velocity, velocity);
+        }
+    }
+    partialMomentum[local_index] = momentum;
+    partialEnergy[local_index] = energy;
+    workgroupBarrier();
+
+    for (var stride: u32 = 32u; stride > 0u; stride /= 2u) {
+        if (local_index < stride) {
+            partialMomentum[local_index] += partialMomentum[local_index + stride];
+            partialEnergy[local_index] += partialEnergy[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        let mass = partialMomentum[0].x;
+        var core = cores[slot];
+        if (mass > 0.0) {
+            let mean = partialMomentum[0].yzw / mass;
+            let radius = params.friction_radius;
+            core.density = mass / (4.0 / 3.0 * PI * radius * radius * radius);
+            core.sigma = sqrt(max((partialEnergy[0] / mass - dot(mean, mean)) / 3.0, 0.0));
+            core.background_vel = to_array(mean);
+        } else {
+            core.density = 0.0;
+            core.sigma = 0.0;
+            core.background_vel = array<f32, 3>(0.0, 0.0, 0.0);
+        }
+        cores[slot] = core;
+    }
+}
+
+// M4 cubic spline with support 2h
+fn sph_kernel(r: f32, h: f32) -> f32 {
+    let q = r / h;
+    let sigma = 1.0 / (PI * h * h * h);
+    if (q < 1.0) {
+        return sigma * (1.0 - 1.5 * q * q + 0.75 * q * q * q);
+    } else if (q < 2.0) {
+        return sigma * 0.25 * (2.0 - q) * (2.0 - q) * (2.0 - q);
+    }
+    return 0.0;
+}
+
+fn sph_kernel_derivative(r: f32, h: f32) -> f32 {
+    let q = r / h;
+    let sigma = 1.0 / (PI * h * h * h * h);
+    if (q < 1.0) {
+        return sigma * (-3.0 * q + 2.25 * q * q);
+    } else if (q < 2.0) {
+        return -sigma * 0.75 * (2.0 - q) * (2.0 - q);
+    }
+    return 0.0;
+}
+
+fn pressure(p: Particle) -> f32 {
+    if (params.eos == EOS_ADIABATIC) {
+        return (GAMMA - 1.0) * p.density * p.internal_energy;
+    }
+    return params.sound_speed * params.sound_speed * p.density;
+}
+
+fn sound_speed(p: Particle) -> f32 {
+    if (params.eos == EOS_ADIABATIC) {
+        return sqrt(GAMMA * (GAMMA - 1.0) * max(p.internal_energy, 0.0));
+    }
+    return params.sound_speed;
+}
+
+fn heated_internal_energy(p: Particle, dt: f32) -> f32 {
+    if (p.kind != KIND_GAS || params.eos != EOS_ADIABATIC) {
+        return p.internal_energy;
+    }
+    return max(p.internal_energy + p.energy_rate * dt, 0.0);
+}
+
+fn sph_cell(position: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor(position / (2.0 * params.smoothing_length)));
+}
+
+fn sph_hash(cell: vec3<i32>) -> u32 {
+    let c = bitcast<vec3<u32>>(cell);
+    return ((c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u)) % SPH_TABLE_SIZE;
+}
+
+@compute @workgroup_size(64)
+fn sph_clear(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let cell = global_invocation_id.x;
+    if (cell < SPH_TABLE_SIZE) {
+        atomicStore(&cellCounts[cell], 0u);
+    }
+}
+
+@compute @workgroup_size(64)
+fn sph_count(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc) || particlesSrc[particleIndex].kind != KIND_GAS) {
+        return;
+    }
+    atomicAdd(&cellCounts[sph_hash(sph_cell(to_vec3(particlesSrc[particleIndex].pos)))], 1u);
+}
+
+// Exclusive prefix sum of the bucket sizes in a single workgroup: each bucket's items start at
+// `cellStarts`, and `cellCounts` becomes the insertion cursor, ending at the bucket's end
+@compute @workgroup_size(64)
+fn sph_scan(@builtin(local_invocation_index) local_index: u32) {
+    let first = local_index * SPH_SCAN_CHUNK;
+    var total = 0u;
+    for (var cell: u32 = first; cell < first + SPH_SCAN_CHUNK; cell++) {
+        total += atomicLoad(&cellCounts[cell]);
+    }
+    partialCounts[local_index] = total;
+    workgroupBarrier();
+
+    if (local_index == 0u) {
+        var offset = 0u;
+        for (var thread: u32 = 0u; thread < 64u; thread++) {
+            let count = partialCounts[thread];
+            partialCounts[thread] = offset;
+            offset += count;
+        }
+    }
+    workgroupBarrier();
+
+    var start = partialCounts[local_index];
+    for (var cell: u32 = first; cell < first + SPH_SCAN_CHUNK; cell++) {
+        let count = atomicLoad(&cellCounts[cell]);
+        cellStarts[cell] = start;
+        atomicStore(&cellCounts[cell], start);
+        start += count;
+    }
+}
+
+@compute @workgroup_size(64)
+fn sph_insert(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc) || particlesSrc[particleIndex].kind != KIND_GAS) {
+        return;
+    }
+    let hash = sph_hash(sph_cell(to_vec3(particlesSrc[particleIndex].pos)));
+    cellItems[atomicAdd(&cellCounts[hash], 1u)] = particleIndex;
+}
+
+@compute @workgroup_size(64)
+fn sph_density(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    if (currentParticle.kind == KIND_GAS) {
+        let position = to_vec3(currentParticle.pos);
+        let cell = sph_cell(position);
+        var density = 0.0;
+        for (var dx: i32 = -1; dx <= 1; dx++) {
+            for (var dy: i32 = -1; dy <= 1; dy++) {
+                for (var dz: i32 = -1; dz <= 1; dz++) {
+                    let neighbourCell = cell + vec3<i32>(dx, dy, dz);
+                    let hash = sph_hash(neighbourCell);
+                    let end = atomicLoad(&cellCounts[hash]);
+                    for (var k: u32 = cellStarts[hash]; k < end; k++) {
+                        let other = particlesSrc[cellItems[k]];
+                        let otherPosition = to_vec3(other.pos);
+                        // colliding cells share a bucket; only count particles of this cell
+                        if (any(sph_cell(otherPosition) != neighbourCell)) {
+                            continue;
+                        }
+                        density += other.mass * sph_kernel(distance(position, otherPosition), params.smoothing_length);
+                    }
+                }
             }
         }
+        currentParticle.density = density;
     }
+    particlesDst[particleIndex] = currentParticle;
+}
 
-    particlesDst[particleIndex] = Particle(
-        array<f32, 3>(position.x, position.y, position.z),
-        array<f32, 3>(velocity.x, velocity.y, velocity.z),
-        array<f32, 3>(newAcceleration.x, newAcceleration.y, newAcceleration.z),
-        currentParticle.mass,
-        currentParticle.galaxy_id
-    );
+// Pressure and Monaghan viscosity acceleration plus du/dt; expects densities from `sph_density`
+@compute @workgroup_size(64)
+fn sph_force(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    if (currentParticle.kind == KIND_GAS) {
+        let h = params.smoothing_length;
+        let alpha = params.viscosity_alpha;
+        let position = to_vec3(currentParticle.pos);
+        let velocity = to_vec3(currentParticle.vel);
+        let pressureTerm = pressure(currentParticle) / (currentParticle.density * currentParticle.density);
+        let soundSpeed = sound_speed(currentParticle);
+        let cell = sph_cell(position);
+        var acceleration = vec3<f32>(0.0, 0.0, 0.0);
+        var energyRate = 0.0;
+        for (var dx: i32 = -1; dx <= 1; dx++) {
+            for (var dy: i32 = -1; dy <= 1; dy++) {
+                for (var dz: i32 = -1; dz <= 1; dz++) {
+                    let neighbourCell = cell + vec3<i32>(dx, dy, dz);
+                    let hash = sph_hash(neighbourCell);
+                    let end = atomicLoad(&cellCounts[hash]);
+                    for (var k: u32 = cellStarts[hash]; k < end; k++) {
+                        let otherIndex = cellItems[k];
+                        let other = particlesSrc[otherIndex];
+                        let offset = position - to_vec3(other.pos);
+                        let r = length(offset);
+                        if (otherIndex == particleIndex || any(sph_cell(to_vec3(other.pos)) != neighbourCell) ||
+                            r >= 2.0 * h || r < 0.000001) {
+                            continue;
+                        }
+                        let relativeVelocity = velocity - to_vec3(other.vel);
+                        let approach = dot(relativeVelocity, offset);
+                        var viscosity = 0.0;
+                        if (approach < 0.0) {
+                            let mu = h * approach / (r * r + 0.01 * h * h);
+                            let meanSoundSpeed = 0.5 * (soundSpeed + sound_speed(other));
+                            let meanDensity = 0.5 * (currentParticle.density + other.density);
+                            viscosity = (-alpha * meanSoundSpeed * mu + VISCOSITY_BETA_PER_ALPHA * alpha * mu * mu) / meanDensity;
+                        }
+                        let gradient = offset * (sph_kernel_derivative(r, h) / r);
+                        let otherTerm = pressure(other) / (other.density * other.density);
+                        acceleration -= gradient * (other.mass * (pressureTerm + otherTerm + viscosity));
+                        energyRate += other.mass * (pressureTerm + 0.5 * viscosity) * dot(relativeVelocity, gradient);
+                    }
+                }
+            }
+        }
+        currentParticle.acc = to_array(to_vec3(currentParticle.acc) + acceleration);
+        currentParticle.energy_rate = energyRate;
+    }
+    particlesDst[particleIndex] = currentParticle;
+}
+
+// PCG hash; must match `star_formation.rs` so both backends form the same stars
+fn pcg_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Turns dense gas into stars with probability 1 - exp(-efficiency dt / t_ff); see `star_formation.rs`
+@compute @workgroup_size(64)
+fn star_formation(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particleIndex = global_invocation_id.x;
+    if (particleIndex >= arrayLength(&particlesSrc)) {
+        return;
+    }
+
+    var currentParticle = particlesSrc[particleIndex];
+    if (currentParticle.kind == KIND_GAS && params.star_formation_efficiency > 0.0 &&
+        currentParticle.density >= params.star_formation_threshold) {
+        let freeFallTime = sqrt(3.0 * PI / (32.0 * params.g * currentParticle.density));
+        let probability = 1.0 - exp(-params.star_formation_efficiency * params.dt / freeFallTime);
+        let seed = pcg_hash(bitcast<u32>(params.time));
+        let random = f32(pcg_hash(particleIndex ^ seed) >> 8u) / 16777216.0;
+        if (random < probability) {
+            currentParticle.kind = KIND_STAR;
+            currentParticle.formation_time = params.time;
+        }
+    }
+    particlesDst[particleIndex] = currentParticle;
 }
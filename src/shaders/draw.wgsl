@@ -5,6 +5,37 @@ struct CameraUniform {
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Leading fields of `SimParams`; only the time is needed to age stars
+struct SimParams {
+    dt: f32,
+    g: f32,
+    e: f32,
+    central_mass: f32,
+    num_particles: u32,
+    particles_per_group: u32,
+    triangle_size: f32,
+    num_galaxies: u32,
+    distance_between_galaxies: f32,
+    galaxy_velocity: f32,
+    halo_v: f32,
+    halo_r: f32,
+    damping: f32,
+    time: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> params: SimParams;
+
+// Stars formed less than this long ago are drawn as young, hot stars
+const YOUNG_STAR_AGE: f32 = 0.2;
+
+// `ParticleKind` discriminants
+const KIND_STAR: u32 = 0u;
+const KIND_GAS: u32 = 1u;
+const KIND_DARK_MATTER: u32 = 2u;
+const KIND_BLACK_HOLE: u32 = 3u;
+const KIND_ACCRETED: u32 = 4u;
+
 struct VertexInput {
     @location(0) particle_pos_x: f32,
     @location(1) particle_pos_y: f32,
@@ -17,7 +48,9 @@ struct VertexInput {
     @location(8) particle_acc_z: f32,
     @location(9) mass: f32,
     @location(10) galaxy_id: u32,
-    @location(11) position: vec3<f32>,
+    @location(11) kind: u32,
+    @location(12) position: vec3<f32>,
+    @location(13) formation_time: f32,
     @builtin(instance_index) particle_index: u32,
 }
 
@@ -61,7 +94,24 @@ fn main_vs(
         rgb = vec3<f32>(1.0, 0.0, x);
     }
     
+    // Stars take the galaxy's hue; other species are tinted so they can be told apart
+    if (model.kind == KIND_GAS) {
+        rgb = mix(rgb, vec3<f32>(0.6, 0.8, 1.0), 0.6);
+    } else if (model.kind == KIND_DARK_MATTER) {
+        rgb = rgb * 0.25;
+    } else if (model.kind == KIND_BLACK_HOLE) {
+        rgb = vec3<f32>(1.0, 1.0, 1.0);
+    } else if (model.kind == KIND_STAR && model.formation_time >= 0.0) {
+        // starbursts: newly formed stars start blue-white and fade to the galaxy's hue
+        let age = params.time - model.formation_time;
+        rgb = mix(vec3<f32>(0.7, 0.85, 1.0), rgb, clamp(age / YOUNG_STAR_AGE, 0.0, 1.0));
+    }
+
     out.color = vec4<f32>(rgb, 1.0);
+    if (model.kind == KIND_ACCRETED) {
+        // swallowed particles are moved outside the clip volume
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
 
     return out;
 }
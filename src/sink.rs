@@ -0,0 +1,177 @@
+use crate::{
+  friction,
+  post_newtonian::{self, PostNewtonian},
+  Particle, ParticleKind, SimParams,
+};
+use cgmath::{InnerSpace, Vector3};
+use rayon::prelude::*;
+use std::fmt;
+
+/// Two black holes that became one.
+#[derive(Copy, Clone, Debug)]
+pub struct Merger {
+  pub time: f32,
+  /// Index of the black hole that remains
+  pub survivor: usize,
+  /// Index of the black hole that was swallowed
+  pub absorbed: usize,
+  /// Mass of the remnant
+  pub mass: f32,
+}
+
+impl fmt::Display for Merger {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Black holes {} and {} merged at time {:.3}, final mass {:.1}",
+      self.survivor, self.absorbed, self.time, self.mass
+    )
+  }
+}
+
+/// What the sinks did in one step.
+#[derive(Debug, Default)]
+pub struct Accretion {
+  pub mergers: Vec<Merger>,
+  /// Stars formed during the run as they were just before being swallowed, since they no longer
+  /// count as stars afterwards
+  pub formed_stars: Vec<Particle>,
+}
+
+/// Treats every black hole as a sink of radius `accretion_radius`: particles inside it and bound
+/// to it are swallowed, and black holes that are bound and closer than the radius merge. Mass and
+/// momentum are conserved; swallowed particles keep their slot as massless `Accreted` particles.
+/// With post-Newtonian binaries, black holes that have inspiralled to
+/// `post_newtonian::MERGER_SEPARATION` merge as well.
+pub fn accrete(particles: &mut [Particle], sim_params: &SimParams) -> Accretion {
+  let inspiral = PostNewtonian::from_discriminant(sim_params.post_newtonian) != PostNewtonian::None;
+  if sim_params.accretion_radius <= 0.0 && !inspiral {
+    return Accretion::default();
+  }
+  let sinks = friction::core_indices(particles);
+  if sinks.is_empty() {
+    return Accretion::default();
+  }
+
+  // every particle goes to the sink it is most bound to
+  let claims: Vec<(usize, usize)> = if sim_params.accretion_radius <= 0.0 {
+    Vec::new()
+  } else {
+    particles
+      .par_iter()
+      .enumerate()
+      .filter(|(_, particle)| {
+        !matches!(
+          particle.kind(),
+          ParticleKind::BlackHole | ParticleKind::Accreted
+        )
+      })
+      .filter_map(|(index, particle)| {
+        sinks
+          .iter()
+          .filter_map(|&sink| {
+            binding_energy(&particles[sink], particle, sim_params).map(|energy| (sink, energy))
+          })
+          .min_by(|a, b| a.1.total_cmp(&b.1))
+          .map(|(sink, _)| (index, sink))
+      })
+      .collect()
+  };
+  let mut formed_stars = Vec::new();
+  for (index, sink) in claims {
+    let particle = particles[index];
+    if particle.kind() == ParticleKind::Star && particle.formation_time >= 0.0 {
+      formed_stars.push(particle);
+    }
+    absorb(particles, sink, index);
+  }
+
+  let mut mergers = Vec::new();
+  for (i, &a) in sinks.iter().enumerate() {
+    for &b in &sinks[i + 1..] {
+      if particles[a].kind() != ParticleKind::BlackHole
+        || particles[b].kind() != ParticleKind::BlackHole
+        || (binding_energy(&particles[a], &particles[b], sim_params).is_none()
+          && !(inspiral && coalesced(&particles[a], &particles[b], sim_params)))
+      {
+        continue;
+      }
+      let (survivor, absorbed) = if particles[a].mass >= particles[b].mass {
+        (a, b)
+      } else {
+        (b, a)
+      };
+      absorb(particles, survivor, absorbed);
+      mergers.push(Merger {
+        time: sim_params.time,
+        survivor,
+        absorbed,
+        mass: particles[survivor].mass,
+      });
+    }
+  }
+
+  follow_remnants(particles, sim_params);
+  Accretion {
+    mergers,
+    formed_stars,
+  }
+}
+
+/// Specific orbital energy of `particle` relative to `sink` in their softened mutual potential,
+/// or `None` if it is outside the accretion radius or unbound.
+fn binding_energy(sink: &Particle, particle: &Particle, sim_params: &SimParams) -> Option<f32> {
+  let r_sq = (Vector3::from(particle.pos) - Vector3::from(sink.pos)).magnitude2();
+  let radius = sim_params.accretion_radius;
+  if r_sq >= radius * radius {
+    return None;
+  }
+  let relative_velocity = Vector3::from(particle.vel) - Vector3::from(sink.vel);
+  let energy = 0.5 * relative_velocity.magnitude2()
+    - sim_params.gravity * (sink.mass + particle.mass) / (r_sq + sim_params.calibrate).sqrt();
+  (energy < 0.0).then_some(energy)
+}
+
+/// Whether two black holes are within `post_newtonian::MERGER_SEPARATION` of each other.
+fn coalesced(a: &Particle, b: &Particle, sim_params: &SimParams) -> bool {
+  let separation = f64::from((Vector3::from(a.pos) - Vector3::from(b.pos)).magnitude());
+  let c = f64::from(sim_params.speed_of_light);
+  separation
+    < post_newtonian::MERGER_SEPARATION * f64::from(sim_params.gravity * (a.mass + b.mass))
+      / (c * c)
+}
+
+/// Moves the mass and momentum of the particle at `index` into the sink at `sink`, which ends up
+/// at their centre of mass.
+fn absorb(particles: &mut [Particle], sink: usize, index: usize) {
+  let (absorber, absorbed) = (particles[sink], particles[index]);
+  let mass = absorber.mass + absorbed.mass;
+  let weighted = |a: [f32; 3], b: [f32; 3]| {
+    ((Vector3::from(a) * absorber.mass + Vector3::from(b) * absorbed.mass) / mass).into()
+  };
+  particles[sink].pos = weighted(absorber.pos, absorbed.pos);
+  particles[sink].vel = weighted(absorber.vel, absorbed.vel);
+  particles[sink].mass = mass;
+  particles[index].mass = 0.0;
+  particles[index].kind = ParticleKind::Accreted as u32;
+}
+
+/// The analytic halo of each galaxy is centred on its original core (see `halo.rs`), so a core
+/// swallowed in a merger is kept on the nearest surviving black hole.
+fn follow_remnants(particles: &mut [Particle], sim_params: &SimParams) {
+  let survivors = friction::core_indices(particles);
+  for galaxy in 0..sim_params.num_galaxies {
+    let core = sim_params.galaxies[galaxy as usize].core as usize;
+    if particles[core].kind() != ParticleKind::Accreted {
+      continue;
+    }
+    let position = Vector3::from(particles[core].pos);
+    if let Some(&nearest) = survivors.iter().min_by(|&&a, &&b| {
+      let distance = |index: usize| (Vector3::from(particles[index].pos) - position).magnitude2();
+      distance(a).total_cmp(&distance(b))
+    }) {
+      particles[core].pos = particles[nearest].pos;
+      particles[core].vel = particles[nearest].vel;
+    }
+  }
+}
@@ -0,0 +1,316 @@
+use crate::{Particle, ParticleKind, SimParams};
+use cgmath::{InnerSpace, Vector3};
+use rayon::prelude::*;
+use std::{collections::HashMap, f32::consts::PI};
+
+/// Adiabatic index of the gas.
+pub const GAMMA: f32 = 5.0 / 3.0;
+/// Monaghan viscosity `beta` in units of `alpha`.
+const BETA_PER_ALPHA: f32 = 2.0;
+
+/// Equation of state of the SPH gas. Must match `pressure` in `shaders/compute.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Eos {
+  /// `P = c_s² ρ` with the fixed `sound_speed`
+  #[default]
+  Isothermal = 0,
+  /// `P = (γ - 1) ρ u` with the internal energy evolved by pressure work and viscous heating
+  Adiabatic = 1,
+}
+
+impl Eos {
+  /// Inverse of `eos as u32`, as stored in `SimParams`.
+  #[must_use]
+  pub fn from_discriminant(discriminant: u32) -> Self {
+    match discriminant {
+      1 => Eos::Adiabatic,
+      _ => Eos::Isothermal,
+    }
+  }
+}
+
+/// Specific internal energy whose adiabatic sound speed is `sound_speed`.
+#[must_use]
+pub fn initial_internal_energy(sim_params: &SimParams) -> f32 {
+  sim_params.sound_speed * sim_params.sound_speed / (GAMMA * (GAMMA - 1.0))
+}
+
+/// M4 cubic spline with support `2h`.
+fn kernel(r: f32, h: f32) -> f32 {
+  let q = r / h;
+  let sigma = 1.0 / (PI * h * h * h);
+  if q < 1.0 {
+    sigma * (1.0 - 1.5 * q * q + 0.75 * q * q * q)
+  } else if q < 2.0 {
+    sigma * 0.25 * (2.0 - q).powi(3)
+  } else {
+    0.0
+  }
+}
+
+/// `dW/dr` of `kernel`.
+fn kernel_derivative(r: f32, h: f32) -> f32 {
+  let q = r / h;
+  let sigma = 1.0 / (PI * h * h * h * h);
+  if q < 1.0 {
+    sigma * (-3.0 * q + 2.25 * q * q)
+  } else if q < 2.0 {
+    -sigma * 0.75 * (2.0 - q) * (2.0 - q)
+  } else {
+    0.0
+  }
+}
+
+fn pressure(particle: &Particle, sim_params: &SimParams) -> f32 {
+  match Eos::from_discriminant(sim_params.eos) {
+    Eos::Isothermal => sim_params.sound_speed * sim_params.sound_speed * particle.density,
+    Eos::Adiabatic => (GAMMA - 1.0) * particle.density * particle.internal_energy,
+  }
+}
+
+fn sound_speed(particle: &Particle, sim_params: &SimParams) -> f32 {
+  match Eos::from_discriminant(sim_params.eos) {
+    Eos::Isothermal => sim_params.sound_speed,
+    Eos::Adiabatic => (GAMMA * (GAMMA - 1.0) * particle.internal_energy.max(0.0)).sqrt(),
+  }
+}
+
+/// Gas particles bucketed by cells of side `2h`, the kernel support.
+struct Grid {
+  cell_size: f32,
+  cells: HashMap<[i32; 3], Vec<usize>>,
+}
+
+impl Grid {
+  fn build(particles: &[Particle], cell_size: f32) -> Self {
+    let mut cells: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+    for (index, particle) in particles.iter().enumerate() {
+      if particle.kind() == ParticleKind::Gas {
+        cells
+          .entry(cell_of(particle.pos, cell_size))
+          .or_default()
+          .push(index);
+      }
+    }
+    Self { cell_size, cells }
+  }
+
+  /// Gas particles in the 27 cells around `position`.
+  fn neighbours(&self, position: [f32; 3]) -> impl Iterator<Item = usize> + '_ {
+    let [x, y, z] = cell_of(position, self.cell_size);
+    (-1..=1)
+      .flat_map(move |dx| {
+        (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz]))
+      })
+      .filter_map(|cell| self.cells.get(&cell))
+      .flatten()
+      .copied()
+  }
+}
+
+fn cell_of(position: [f32; 3], cell_size: f32) -> [i32; 3] {
+  position.map(|x| (x / cell_size).floor() as i32)
+}
+
+/// Adds the hydrodynamic acceleration to every gas particle's `acc` and sets its `density` and
+/// `energy_rate`. Expects `acc` to already hold the gravitational acceleration.
+pub fn apply(particles: &mut [Particle], sim_params: &SimParams) {
+  let h = sim_params.smoothing_length;
+  if !particles
+    .iter()
+    .any(|particle| particle.kind() == ParticleKind::Gas)
+  {
+    return;
+  }
+  let grid = Grid::build(particles, 2.0 * h);
+
+  let densities: Vec<f32> = particles
+    .par_iter()
+    .map(|particle| {
+      if particle.kind() != ParticleKind::Gas {
+        return particle.density;
+      }
+      grid
+        .neighbours(particle.pos)
+        .map(|j| {
+          let r = (Vector3::from(particles[j].pos) - Vector3::from(particle.pos)).magnitude();
+          particles[j].mass * kernel(r, h)
+        })
+        .sum()
+    })
+    .collect();
+  for (particle, density) in particles.iter_mut().zip(densities) {
+    particle.density = density;
+  }
+
+  let updates: Vec<(Vector3<f32>, f32)> = particles
+    .par_iter()
+    .enumerate()
+    .map(|(i, particle)| {
+      if particle.kind() != ParticleKind::Gas {
+        return (Vector3::from(particle.acc), particle.energy_rate);
+      }
+      let (acceleration, energy_rate) = hydro_force(particles, &grid, i, sim_params);
+      (Vector3::from(particle.acc) + acceleration, energy_rate)
+    })
+    .collect();
+  for (particle, (acc, energy_rate)) in particles.iter_mut().zip(updates) {
+    particle.acc = acc.into();
+    particle.energy_rate = energy_rate;
+  }
+}
+
+/// Pressure and Monaghan viscosity acceleration on gas particle `i`, and `du/dt`.
+fn hydro_force(
+  particles: &[Particle],
+  grid: &Grid,
+  i: usize,
+  sim_params: &SimParams,
+) -> (Vector3<f32>, f32) {
+  let h = sim_params.smoothing_length;
+  let alpha = sim_params.viscosity_alpha;
+  let current = &particles[i];
+  let position = Vector3::from(current.pos);
+  let velocity = Vector3::from(current.vel);
+  let pressure_term = pressure(current, sim_params) / (current.density * current.density);
+  let sound_speed_i = sound_speed(current, sim_params);
+
+  let mut acceleration = Vector3::new(0.0, 0.0, 0.0);
+  let mut energy_rate = 0.0;
+  for j in grid.neighbours(current.pos) {
+    if j == i {
+      continue;
+    }
+    let other = &particles[j];
+    let offset = position - Vector3::from(other.pos);
+    let r = offset.magnitude();
+    if r >= 2.0 * h || r < 0.000_001 {
+      continue;
+    }
+    let relative_velocity = velocity - Vector3::from(other.vel);
+    let approach = relative_velocity.dot(offset);
+    let viscosity = if approach < 0.0 {
+      let mu = h * approach / (r * r + 0.01 * h * h);
+      let mean_sound_speed = 0.5 * (sound_speed_i + sound_speed(other, sim_params));
+      let mean_density = 0.5 * (current.density + other.density);
+      (-alpha * mean_sound_speed * mu + BETA_PER_ALPHA * alpha * mu * mu) / mean_density
+    } else {
+      0.0
+    };
+    let gradient = offset * (kernel_derivative(r, h) / r);
+    let other_term = pressure(other, sim_params) / (other.density * other.density);
+    acceleration -= gradient * (other.mass * (pressure_term + other_term + viscosity));
+    energy_rate += other.mass * (pressure_term + 0.5 * viscosity) * relative_velocity.dot(gradient);
+  }
+  (acceleration, energy_rate)
+}
+
+/// Internal energy of `particle` after `dt` of its current heating rate; only the adiabatic gas
+/// evolves it.
+#[must_use]
+pub fn heated_internal_energy(particle: &Particle, dt: f32, sim_params: &SimParams) -> f32 {
+  if particle.kind() != ParticleKind::Gas
+    || Eos::from_discriminant(sim_params.eos) != Eos::Adiabatic
+  {
+    return particle.internal_energy;
+  }
+  (particle.internal_energy + particle.energy_rate * dt).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+  fn gas(pos: [f32; 3], vel: [f32; 3], mass: f32, internal_energy: f32) -> Particle {
+    Particle {
+      pos,
+      vel,
+      mass,
+      kind: ParticleKind::Gas as u32,
+      internal_energy,
+      ..bytemuck::Zeroable::zeroed()
+    }
+  }
+
+  /// Away from its edges a cubic lattice of spacing `d` has the density `m / d³`.
+  #[test]
+  fn uniform_lattice_has_its_mean_density() {
+    let (side, spacing, mass) = (16, 0.05, 0.001);
+    let mut particles: Vec<Particle> = (0..side * side * side)
+      .map(|i| {
+        let cell = [i % side, i / side % side, i / (side * side)];
+        gas(cell.map(|x| x as f32 * spacing), [0.0; 3], mass, 0.0)
+      })
+      .collect();
+    let sim_params = SimParams {
+      smoothing_length: 1.2 * spacing,
+      ..SimParams::default()
+    };
+    apply(&mut particles, &sim_params);
+
+    let expected = mass / (spacing * spacing * spacing);
+    let support = 2.0 * sim_params.smoothing_length;
+    let extent = (side - 1) as f32 * spacing;
+    let interior = particles.iter().filter(|particle| {
+      particle
+        .pos
+        .iter()
+        .all(|&x| x > support && x < extent - support)
+    });
+    let mut checked = 0;
+    for particle in interior {
+      assert!(
+        (particle.density / expected - 1.0).abs() < 0.005,
+        "{:?}: {} vs {expected}",
+        particle.pos,
+        particle.density
+      );
+      checked += 1;
+    }
+    assert!(checked > 100);
+  }
+
+  /// Pair forces are antisymmetric and viscous heating balances the work done, so a clump of
+  /// adiabatic gas with over a hundred particles per grid cell conserves momentum and energy.
+  #[test]
+  fn hydro_forces_conserve_momentum_and_energy() {
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut particles: Vec<Particle> = (0..2000)
+      .map(|_| {
+        let pos = [(); 3].map(|()| rng.gen_range(-0.05..0.05));
+        let vel = [(); 3].map(|()| rng.gen_range(-0.5..0.5));
+        gas(pos, vel, rng.gen_range(0.5..2.0), rng.gen_range(0.1..1.0))
+      })
+      .collect();
+    let sim_params = SimParams {
+      smoothing_length: 0.02,
+      eos: Eos::Adiabatic as u32,
+      viscosity_alpha: 1.0,
+      ..SimParams::default()
+    };
+    apply(&mut particles, &sim_params);
+
+    let mut momentum = Vector3::new(0.0, 0.0, 0.0);
+    let mut momentum_scale = 0.0;
+    let (mut energy, mut energy_scale) = (0.0, 0.0);
+    for particle in &particles {
+      let force = Vector3::from(particle.acc) * particle.mass;
+      momentum += force;
+      momentum_scale += force.magnitude();
+      let kinetic = particle.mass * Vector3::from(particle.vel).dot(Vector3::from(particle.acc));
+      let thermal = particle.mass * particle.energy_rate;
+      energy += kinetic + thermal;
+      energy_scale += kinetic.abs() + thermal.abs();
+    }
+    assert!(
+      momentum.magnitude() < 1e-5 * momentum_scale,
+      "{momentum:?} of {momentum_scale}"
+    );
+    assert!(
+      energy.abs() < 1e-5 * energy_scale,
+      "{energy} of {energy_scale}"
+    );
+  }
+}
@@ -0,0 +1,207 @@
+use crate::{
+  eddington::{self, Eddington},
+  halo::HaloProfile,
+  SimParams,
+};
+use rand::Rng;
+use rayon::prelude::*;
+
+/// Spheres are cut off at this many scale radii
+const SPHERE_TRUNCATION: f64 = 20.0;
+
+/// Density profile of a spherical star cluster or bulge with scale radius `a`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SphereProfile {
+  /// `ρ ∝ (1 + x²)^(-5/2)`, `Ψ = G M / sqrt(r² + a²)`
+  Plummer,
+  /// `ρ ∝ 1 / (x (1 + x)³)`, `Ψ = G M / (r + a)`
+  Hernquist,
+}
+
+impl SphereProfile {
+  /// Fraction of the mass inside `x = r / a`.
+  #[must_use]
+  pub fn enclosed_mass(self, x: f64) -> f64 {
+    match self {
+      SphereProfile::Plummer => x.powi(3) / (1.0 + x * x).powf(1.5),
+      SphereProfile::Hernquist => x * x / ((1.0 + x) * (1.0 + x)),
+    }
+  }
+
+  /// `x = r / a` inside which `fraction` of the mass lies.
+  #[must_use]
+  pub fn radius_enclosing(self, fraction: f64) -> f64 {
+    match self {
+      SphereProfile::Plummer => 1.0 / (fraction.powf(-2.0 / 3.0) - 1.0).sqrt(),
+      SphereProfile::Hernquist => {
+        let root = fraction.sqrt();
+        root / (1.0 - root)
+      }
+    }
+  }
+
+  /// Density at `x = r / a` up to a constant factor.
+  #[must_use]
+  pub fn density_shape(self, x: f64) -> f64 {
+    match self {
+      SphereProfile::Plummer => (1.0 + x * x).powf(-2.5),
+      SphereProfile::Hernquist => 1.0 / (x * (1.0 + x).powi(3)),
+    }
+  }
+}
+
+/// Sampler of an isotropic sphere of `count` unit-mass particles in equilibrium in its own
+/// softened gravity, the softened central mass and the halo `halo`.
+///
+/// Radii are drawn from the profile truncated at `SPHERE_TRUNCATION` scale radii, and speeds from
+/// the distribution function of the untruncated profile given by Eddington's formula; for a lone
+/// sphere without softening this is the exact `f(E)` (for Plummer `f ∝ E^(7/2)`).
+pub struct Sphere {
+  profile: SphereProfile,
+  scale_radius: f64,
+  /// Mass of the untruncated profile
+  mass: f64,
+  /// Mass fraction inside the truncation radius
+  truncated_fraction: f64,
+  /// `Ψ(r)` on `radii`
+  radii: Vec<f64>,
+  psi: Vec<f64>,
+  eddington: Eddington,
+}
+
+impl Sphere {
+  #[must_use]
+  pub fn new(
+    profile: SphereProfile,
+    count: u32,
+    scale_radius: f32,
+    halo: HaloProfile,
+    sim_params: &SimParams,
+  ) -> Self {
+    let scale_radius = f64::from(scale_radius);
+    let truncated_fraction = profile.enclosed_mass(SPHERE_TRUNCATION);
+    // the particles make up the truncated sphere; the profile extends beyond it
+    let mass = f64::from(count) / truncated_fraction;
+    let gravity = f64::from(sim_params.gravity);
+    let softening = f64::from(sim_params.calibrate);
+    let radii = eddington::log_radii(scale_radius);
+    // the sphere's own potential under the softened pair force, summed over thin shells: a shell
+    // of mass m at radius s adds G m (√((r + s)² + ε²) - √((r - s)² + ε²)) / (2 r s) to Ψ(r)
+    let shells: Vec<(f64, f64)> = radii
+      .windows(2)
+      .map(|pair| {
+        let [inner, outer] = [pair[0], pair[1]].map(|r| profile.enclosed_mass(r / scale_radius));
+        (0.5 * (pair[0] + pair[1]), gravity * mass * (outer - inner))
+      })
+      .collect();
+    let own = |r: f64| {
+      shells
+        .iter()
+        .map(|&(s, gravity_mass)| {
+          let far = ((r + s) * (r + s) + softening).sqrt();
+          let near = ((r - s) * (r - s) + softening).sqrt();
+          gravity_mass * (far - near) / (2.0 * r * s)
+        })
+        .sum::<f64>()
+    };
+    let density: Vec<f64> = radii
+      .iter()
+      .map(|r| profile.density_shape(r / scale_radius))
+      .collect();
+    let external = |r: f64| {
+      let central = gravity * f64::from(sim_params.central_mass) / (r * r + softening).sqrt();
+      central - f64::from(halo.potential(r as f32, sim_params))
+    };
+    // a halo whose potential does not vanish at infinity is cut off at the outermost radius
+    let outermost = external(radii[radii.len() - 1]).min(0.0);
+    let psi: Vec<f64> = radii
+      .par_iter()
+      .map(|&r| own(r) + external(r) - outermost)
+      .collect();
+    Self {
+      profile,
+      scale_radius,
+      mass,
+      truncated_fraction,
+      eddington: Eddington::new(&radii, &density, &psi),
+      radii,
+      psi,
+    }
+  }
+
+  /// Mass of the sampled particles inside radius `r`.
+  #[must_use]
+  pub fn enclosed_mass(&self, r: f64) -> f64 {
+    let x = (r / self.scale_radius).min(SPHERE_TRUNCATION);
+    self.mass * self.profile.enclosed_mass(x)
+  }
+
+  /// Draws the radius and speed of one particle.
+  pub fn sample(&self, rng: &mut impl Rng) -> (f32, f32) {
+    let fraction = rng.gen::<f64>() * self.truncated_fraction;
+    let r = self.profile.radius_enclosing(fraction) * self.scale_radius;
+    let i = self
+      .radii
+      .partition_point(|&radius| radius < r)
+      .clamp(1, self.radii.len() - 1);
+    let t = ((r - self.radii[i - 1]) / (self.radii[i] - self.radii[i - 1])).clamp(0.0, 1.0);
+    let psi = self.psi[i - 1] + (self.psi[i] - self.psi[i - 1]) * t;
+    (r as f32, self.eddington.sample_speed(rng, psi) as f32)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::{rngs::SmallRng, SeedableRng};
+
+  /// Kolmogorov-Smirnov distance between sorted `samples` and `cdf`.
+  fn distance(samples: &[f64], cdf: impl Fn(f64) -> f64) -> f64 {
+    samples
+      .iter()
+      .enumerate()
+      .map(|(i, &x)| (cdf(x) - (i as f64 + 0.5) / samples.len() as f64).abs())
+      .fold(0.0, f64::max)
+  }
+
+  /// A lone unsoftened Plummer sphere has the truncated Plummer mass profile and the exact
+  /// distribution of `q = v / v_esc`, see `eddington::plummer_speed_cdf`.
+  #[test]
+  fn plummer_samples_follow_the_profile_and_distribution_function() {
+    let sim_params = SimParams {
+      central_mass: 0.0,
+      calibrate: 0.0,
+      ..SimParams::default()
+    };
+    let sphere = Sphere::new(
+      SphereProfile::Plummer,
+      20_000,
+      2.0,
+      HaloProfile::None,
+      &sim_params,
+    );
+    let gravity_mass = f64::from(sim_params.gravity) * sphere.mass;
+
+    let mut rng = SmallRng::seed_from_u64(9);
+    let (mut radii, mut q): (Vec<f64>, Vec<f64>) = (0..20_000)
+      .map(|_| {
+        let (r, v) = sphere.sample(&mut rng);
+        let r = f64::from(r);
+        let escape_speed = (2.0 * gravity_mass / (r * r + 4.0).sqrt()).sqrt();
+        (r, f64::from(v) / escape_speed)
+      })
+      .unzip();
+    radii.sort_by(f64::total_cmp);
+    q.sort_by(f64::total_cmp);
+
+    let radial = distance(&radii, |r| {
+      sphere.enclosed_mass(r) / (sphere.mass * sphere.truncated_fraction)
+    });
+    let speed = distance(&q, eddington::plummer_speed_cdf());
+    assert!(
+      radial < 0.015,
+      "radial Kolmogorov-Smirnov distance {radial}"
+    );
+    assert!(speed < 0.015, "speed Kolmogorov-Smirnov distance {speed}");
+  }
+}
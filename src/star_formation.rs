@@ -0,0 +1,106 @@
+use crate::{Particle, ParticleKind, SimParams};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// `formation_time` of particles created by the initial conditions rather than by star formation.
+pub const INITIAL_POPULATION: f32 = -1.0;
+
+/// Stochastically turns gas denser than `star_formation_threshold` into stars, each with
+/// probability `1 - exp(-ε dt / t_ff)` per step where `t_ff = sqrt(3π / (32 G ρ))`. Converted
+/// particles record the current time as their `formation_time`. Must match `star_formation` in
+/// `shaders/compute.wgsl`, including the random numbers.
+pub fn form_stars(particles: &mut [Particle], sim_params: &SimParams) {
+  if sim_params.star_formation_efficiency <= 0.0 {
+    return;
+  }
+  let seed = hash(sim_params.time.to_bits());
+  particles
+    .par_iter_mut()
+    .enumerate()
+    .for_each(|(index, particle)| {
+      if forms_star(particle, index as u32, seed, sim_params) {
+        particle.kind = ParticleKind::Star as u32;
+        particle.formation_time = sim_params.time;
+      }
+    });
+}
+
+fn forms_star(particle: &Particle, index: u32, seed: u32, sim_params: &SimParams) -> bool {
+  if particle.kind() != ParticleKind::Gas || particle.density < sim_params.star_formation_threshold
+  {
+    return false;
+  }
+  let free_fall_time = (3.0 * PI / (32.0 * sim_params.gravity * particle.density)).sqrt();
+  let probability =
+    1.0 - (-sim_params.star_formation_efficiency * sim_params.delta_t / free_fall_time).exp();
+  uniform(hash(index ^ seed)) < probability
+}
+
+/// PCG hash; identical integer arithmetic on the CPU and GPU keeps both backends in step.
+fn hash(x: u32) -> u32 {
+  let state = x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+  let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+  (word >> 22) ^ word
+}
+
+/// Top 24 bits of `x` as a float in `[0, 1)`.
+fn uniform(x: u32) -> f32 {
+  (x >> 8) as f32 / 16_777_216.0
+}
+
+/// Star formation rate in bins of width `bin` from time 0 to `end_time`, as
+/// `(bin start, formed mass / bin)` pairs, reconstructed from the formation times of the stars in
+/// `particles` and of those in `swallowed`, the formed stars sinks have accreted.
+#[must_use]
+pub fn history(
+  particles: &[Particle],
+  swallowed: &[Particle],
+  bin: f32,
+  end_time: f32,
+) -> Vec<(f32, f32)> {
+  let bins = (end_time / bin).ceil().max(1.0) as usize;
+  let mut mass = vec![0.0; bins];
+  for particle in particles.iter().chain(swallowed) {
+    if particle.kind() == ParticleKind::Star && particle.formation_time >= 0.0 {
+      let index = ((particle.formation_time / bin) as usize).min(bins - 1);
+      mass[index] += particle.mass;
+    }
+  }
+  mass
+    .into_iter()
+    .enumerate()
+    .map(|(i, mass)| (i as f32 * bin, mass / bin))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn particle(kind: ParticleKind, mass: f32, formation_time: f32) -> Particle {
+    Particle {
+      mass,
+      kind: kind as u32,
+      formation_time,
+      ..bytemuck::Zeroable::zeroed()
+    }
+  }
+
+  /// Only stars formed during the run count, including those a sink has swallowed since.
+  #[test]
+  fn history_counts_formed_stars_including_swallowed_ones() {
+    let particles = [
+      particle(ParticleKind::Star, 1.0, 0.05),
+      particle(ParticleKind::Star, 2.0, 0.15),
+      particle(ParticleKind::Star, 4.0, INITIAL_POPULATION),
+      particle(ParticleKind::Gas, 8.0, INITIAL_POPULATION),
+      particle(ParticleKind::Accreted, 0.0, 0.12),
+    ];
+    let swallowed = [particle(ParticleKind::Star, 1.0, 0.12)];
+    let history = history(&particles, &swallowed, 0.1, 0.2);
+    assert_eq!(history.len(), 2);
+    for ((start, rate), expected) in history.into_iter().zip([(0.0, 10.0), (0.1, 30.0)]) {
+      assert!((start - expected.0).abs() < 1e-6 && (rate - expected.1).abs() < 1e-4);
+    }
+  }
+}
@@ -1,9 +1,22 @@
 use crate::{
   camera::{Camera, CameraController, CameraUniform},
+  cosmology::Expansion,
+  cpu::{self, CpuCompute},
+  diagnostics::Diagnostics,
+  friction,
+  gravitational_waves::Observer,
+  initialize,
+  post_newtonian::PostNewtonian,
   render::Render,
-  CameraParams, SimParams,
+  star_formation, Backend, CameraParams, Galaxy, Kernel, Particle, RunConfig, SimParams, Solver,
+};
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::Path,
+  sync::Arc,
+  time::Instant,
 };
-use std::{sync::Arc, time::Instant};
 use wgpu::util::DeviceExt;
 use wgpu::MemoryHints;
 use winit::{
@@ -109,7 +122,11 @@ impl State {
     );
   }
 
-  async fn init(surface: Option<&SurfaceWrapper>, size: &PhysicalSize<u32>) -> Self {
+  async fn init(
+    surface: Option<&SurfaceWrapper>,
+    size: &PhysicalSize<u32>,
+    camera_params: CameraParams,
+  ) -> Self {
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
       backends: wgpu::Backends::PRIMARY,
       ..Default::default()
@@ -137,9 +154,8 @@ impl State {
       .await
       .unwrap();
     let camera = Camera {
-      // position the camera 1 unit up and 2 units back
-      eye: (0.0, 1.0, 2.0).into(),
-      target: (0.0, 0.0, 0.0).into(),
+      eye: camera_params.eye.into(),
+      target: camera_params.target.into(),
       up: cgmath::Vector3::unit_y(),
       aspect: size.width as f32 / size.height as f32,
       fovy: 45.0,
@@ -176,7 +192,6 @@ impl State {
       }],
       label: Some("camera_bind_group"),
     });
-    let camera_params = CameraParams::default();
     let camera_controller =
       CameraController::init(camera_params.speed, camera_params.rotational_speed);
 
@@ -195,100 +210,375 @@ impl State {
   }
 }
 
-pub async fn start(galaxy_count: u32, headless: bool) {
-  env_logger::init();
-  let mut sim_params = SimParams::default();
-  sim_params.num_galaxies = galaxy_count;
+/// Advances the simulation for the headless loop on whichever backend was selected.
+enum Compute {
+  Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    renderer: Box<Render>,
+  },
+  Cpu(CpuCompute),
+}
 
-  if headless {
-    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-      backends: wgpu::Backends::PRIMARY,
-      ..Default::default()
-    });
+impl Compute {
+  async fn init(config: &RunConfig) -> Self {
+    let sim_params = config.sim_params();
+    match config.backend {
+      Backend::Gpu => {
+        let (adapter, device, queue) = headless_device().await;
+        let renderer = Box::new(Render::init(
+          None, &adapter, &device, &queue, None, sim_params, config,
+        ));
+        Compute::Gpu {
+          device,
+          queue,
+          renderer,
+        }
+      }
+      Backend::Cpu => Compute::Cpu(CpuCompute::init(&sim_params, config)),
+    }
+  }
 
-    let adapter = instance
-      .request_adapter(&wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::default(),
-        compatible_surface: None,
-        force_fallback_adapter: false,
-      })
-      .await
-      .unwrap();
+  fn step(&mut self, sim_params: &SimParams) {
+    match self {
+      Compute::Gpu {
+        device,
+        queue,
+        renderer,
+      } => renderer.compute(device, queue, sim_params),
+      Compute::Cpu(cpu) => cpu.compute(sim_params),
+    }
+  }
 
-    let (device, queue) = adapter
-      .request_device(
-        &wgpu::DeviceDescriptor {
-          label: Some("Device Descriptor"),
-          required_features: wgpu::Features::empty(),
-          required_limits: wgpu::Limits::default(),
-          memory_hints: MemoryHints::default(),
-        },
-        None,
-      )
-      .await
-      .unwrap();
+  fn particles(&self) -> Vec<Particle> {
+    match self {
+      Compute::Gpu {
+        device,
+        queue,
+        renderer,
+      } => renderer.read_particles(device, queue),
+      Compute::Cpu(cpu) => cpu.particles().to_vec(),
+    }
+  }
 
-    let mut renderer = Render::init(None, &adapter, &device, &queue, None, sim_params);
-    let mut frame_count = 0;
-    let mut frame_deltas = Vec::new();
+  /// See `Stepper::swallowed_stars`.
+  fn swallowed_stars(&self) -> &[Particle] {
+    match self {
+      Compute::Gpu { renderer, .. } => renderer.swallowed_stars(),
+      Compute::Cpu(cpu) => cpu.swallowed_stars(),
+    }
+  }
+
+  /// The particles at `indices`, without reading back the others.
+  fn particles_at(&self, indices: &[usize]) -> Vec<Particle> {
+    match self {
+      Compute::Gpu {
+        device,
+        queue,
+        renderer,
+      } => renderer.read_particles_at(device, queue, indices),
+      Compute::Cpu(cpu) => indices
+        .iter()
+        .map(|&index| cpu.particles()[index])
+        .collect(),
+    }
+  }
+}
 
-    let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
-    let r = running.clone();
+/// Prints `diagnostics` with its energy drift since `initial` as the headline number.
+fn report_diagnostics(diagnostics: &Diagnostics, initial: &Diagnostics, time: f32) {
+  println!(
+    "Time: {time:.3}, dE/E0: {:+.3e}, {diagnostics}",
+    diagnostics.energy_drift(initial)
+  );
+}
+
+/// Writes `history` from `star_formation::history` as a `time,sfr` CSV.
+fn write_star_formation_history(path: &Path, history: &[(f32, f32)]) -> std::io::Result<()> {
+  let mut file = BufWriter::new(File::create(path)?);
+  writeln!(file, "time,sfr")?;
+  for (time, rate) in history {
+    writeln!(file, "{time},{rate}")?;
+  }
+  file.flush()
+}
 
-    ctrlc::set_handler(move || {
-      r.store(false, std::sync::atomic::Ordering::SeqCst);
+async fn headless_device() -> (wgpu::Adapter, wgpu::Device, wgpu::Queue) {
+  let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+    backends: wgpu::Backends::PRIMARY,
+    ..Default::default()
+  });
+
+  let adapter = instance
+    .request_adapter(&wgpu::RequestAdapterOptions {
+      power_preference: wgpu::PowerPreference::default(),
+      compatible_surface: None,
+      force_fallback_adapter: false,
     })
-    .expect("Error setting Ctrl-C handler");
+    .await
+    .unwrap();
+
+  let (device, queue) = adapter
+    .request_device(
+      &wgpu::DeviceDescriptor {
+        label: Some("Device Descriptor"),
+        required_features: wgpu::Features::empty(),
+        required_limits: wgpu::Limits::default(),
+        memory_hints: MemoryHints::default(),
+      },
+      None,
+    )
+    .await
+    .unwrap();
+  (adapter, device, queue)
+}
+
+async fn start_headless(config: RunConfig) {
+  let (backend, solver) = (config.backend, config.solver);
+  let mut sim_params = config.sim_params();
+  let mut compute = Compute::init(&config).await;
+  let mut frame_count = 0;
+  let mut frame_deltas = Vec::new();
 
-    println!("Running in headless mode. Press Ctrl+C to exit.");
+  let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
+  let r = running.clone();
 
-    let mut last_frame_time = Instant::now();
-    let mut timer = Instant::now();
+  ctrlc::set_handler(move || {
+    r.store(false, std::sync::atomic::Ordering::SeqCst);
+  })
+  .expect("Error setting Ctrl-C handler");
 
-    while running.load(std::sync::atomic::Ordering::SeqCst) {
-      let now = Instant::now();
-      let delta = now.duration_since(last_frame_time);
-      last_frame_time = now;
+  println!(
+    "Running in headless mode ({backend:?} backend, {solver:?}, {:?}). Press Ctrl+C to exit.",
+    config.integrator
+  );
 
-      frame_deltas.push(delta.as_secs_f32());
+  let diagnostics_every = config.diagnostics_every;
+  let initial_diagnostics = (diagnostics_every > 0).then(|| {
+    let initial = Diagnostics::measure(&compute.particles(), &sim_params);
+    report_diagnostics(&initial, &initial, sim_params.time);
+    initial
+  });
+  let mut steps = 0u32;
+  let observer = Observer::new(config.gw_observer, config.gw_distance);
+  let mut gw_output = config.gw_output.as_ref().map(|path| {
+    let mut file = BufWriter::new(File::create(path).expect("Error creating the strain file"));
+    writeln!(file, "time,h_plus,h_cross").expect("Error writing the strain file");
+    file
+  });
+  // only cores are regularized, so the strain needs just them read back each step
+  let gw_cores = if gw_output.is_some() {
+    friction::core_indices(&compute.particles())
+  } else {
+    Vec::new()
+  };
+
+  let mut expansion = config
+    .cosmology
+    .map(|cosmology| Expansion::new(cosmology, &compute.particles(), &sim_params));
+  if let Some(expansion) = &expansion {
+    println!(
+      "Cosmology: H0 = {:.4} per unit time",
+      expansion.hubble_constant()
+    );
+  }
 
-      if timer.elapsed().as_secs_f32() >= 1.0 {
-        println!(
-          "FPS: {:.2}, Time: {:.2}",
-          frame_count as f32 / timer.elapsed().as_secs_f32(),
-          sim_params.time
-        );
-        timer = Instant::now();
-        frame_count = 0;
+  let mut last_frame_time = Instant::now();
+  let mut timer = Instant::now();
+
+  while running.load(std::sync::atomic::Ordering::SeqCst) {
+    let now = Instant::now();
+    let delta = now.duration_since(last_frame_time);
+    last_frame_time = now;
+
+    frame_deltas.push(delta.as_secs_f32());
+
+    if timer.elapsed().as_secs_f32() >= 1.0 {
+      let fps = frame_count as f32 / timer.elapsed().as_secs_f32();
+      match &mut expansion {
+        Some(expansion) => println!(
+          "FPS: {fps:.2}, Time: {:.2}, z: {:.3}",
+          sim_params.time,
+          1.0 / expansion.scale_factor(sim_params.time) - 1.0
+        ),
+        None => println!("FPS: {fps:.2}, Time: {:.2}", sim_params.time),
       }
+      timer = Instant::now();
+      frame_count = 0;
+    }
 
-      sim_params.time += sim_params.delta_t;
-      renderer.compute(&device, &queue, &sim_params);
-      frame_count += 1;
+    sim_params.time += sim_params.delta_t;
+    compute.step(&sim_params);
+    frame_count += 1;
+    steps += 1;
+
+    if let Some(initial) = &initial_diagnostics {
+      if steps.is_multiple_of(diagnostics_every) {
+        let diagnostics = Diagnostics::measure(&compute.particles(), &sim_params);
+        report_diagnostics(&diagnostics, initial, sim_params.time);
+      }
+    }
+    if let Some(file) = &mut gw_output {
+      let (h_plus, h_cross) = observer.strain(&compute.particles_at(&gw_cores), &sim_params);
+      writeln!(file, "{},{h_plus:e},{h_cross:e}", sim_params.time)
+        .expect("Error writing the strain file");
+    }
+  }
+  if let Some(file) = &mut gw_output {
+    file.flush().expect("Error writing the strain file");
+  }
+
+  println!("\nSimulation stopped.");
+  if let Some(path) = &config.sfr_output {
+    let history = star_formation::history(
+      &compute.particles(),
+      compute.swallowed_stars(),
+      config.sfr_bin,
+      sim_params.time,
+    );
+    match write_star_formation_history(path, &history) {
+      Ok(()) => println!("Star formation history written to {}", path.display()),
+      Err(error) => eprintln!("Failed to write {}: {error}", path.display()),
     }
+  }
+  if !frame_deltas.is_empty() {
+    let total_time: f32 = frame_deltas.iter().sum();
+    let avg_fps = frame_deltas.len() as f32 / total_time;
+
+    // find 1% lows
+    frame_deltas.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let one_percent_index = (frame_deltas.len() as f32 * 0.99) as usize;
+    let low_1_percent_delta = frame_deltas[one_percent_index];
+    let low_1_percent_fps = 1.0 / low_1_percent_delta;
+
+    println!("Average FPS: {:.2}", avg_fps);
+    println!("1% Low FPS:  {:.2}", low_1_percent_fps);
+  }
+}
+
+/// Runs the GPU kernel and the CPU reference side by side and reports how far they diverge.
+pub async fn start_validate(config: RunConfig, steps: u32) {
+  init_logger();
+  // only features the kernel implements, so the GPU side does not fall back to the host stepper
+  let config = RunConfig {
+    solver: Solver::Direct,
+    cosmology: None,
+    block_timesteps: None,
+    accretion_radius: 0.0,
+    regularization_radius: 0.0,
+    post_newtonian: PostNewtonian::None,
+    ..config
+  };
+  debug_assert!(!config.runs_on_host());
+  let mut sim_params = config.sim_params();
+  let (adapter, device, queue) = headless_device().await;
+  let mut renderer = Render::init(None, &adapter, &device, &queue, None, sim_params, &config);
+  let mut cpu = CpuCompute::init(&sim_params, &config);
+
+  for _ in 0..steps {
+    sim_params.time += sim_params.delta_t;
+    renderer.compute(&device, &queue, &sim_params);
+    cpu.compute(&sim_params);
+  }
 
-    println!("\nSimulation stopped.");
-    if !frame_deltas.is_empty() {
-      let total_time: f32 = frame_deltas.iter().sum();
-      let avg_fps = frame_deltas.len() as f32 / total_time;
+  let gpu_particles = renderer.read_particles(&device, &queue);
+  let (pos_error, vel_error) = cpu::max_deviation(&gpu_particles, cpu.particles());
+  println!(
+    "After {steps} steps ({} particles): max |dpos| = {pos_error:e}, max |dvel| = {vel_error:e}",
+    gpu_particles.len()
+  );
+}
 
-      // find 1% lows
-      frame_deltas.sort_by(|a, b| a.partial_cmp(b).unwrap());
-      let one_percent_index = (frame_deltas.len() as f32 * 0.99) as usize;
-      let low_1_percent_delta = frame_deltas[one_percent_index];
-      let low_1_percent_fps = 1.0 / low_1_percent_delta;
+/// Times each direct-summation kernel for `steps` steps at every particle count in `counts`.
+pub async fn start_bench(counts: &[u32], steps: u32) {
+  init_logger();
+  let (adapter, device, queue) = headless_device().await;
+  let kernels = [Kernel::Simple, Kernel::Tiled];
 
-      println!("Average FPS: {:.2}", avg_fps);
-      println!("1% Low FPS:  {:.2}", low_1_percent_fps);
+  println!(
+    "{:>10} {:>14} {:>14} {:>8}",
+    "particles", "simple ms", "tiled ms", "speedup"
+  );
+  for &count in counts {
+    let mut millis_per_step = [0.0; 2];
+    for (kernel, millis) in kernels.iter().zip(&mut millis_per_step) {
+      let config = RunConfig {
+        galaxies: vec![Galaxy {
+          particles: count,
+          ..Galaxy::default()
+        }],
+        headless: true,
+        kernel: *kernel,
+        ..RunConfig::default()
+      };
+      let mut sim_params = config.sim_params();
+      let mut renderer = Render::init(None, &adapter, &device, &queue, None, sim_params, &config);
+      // warm up so pipeline creation and first submission are not timed
+      renderer.compute(&device, &queue, &sim_params);
+      device.poll(wgpu::Maintain::Wait);
+
+      let timer = Instant::now();
+      for _ in 0..steps {
+        sim_params.time += sim_params.delta_t;
+        renderer.compute(&device, &queue, &sim_params);
+      }
+      device.poll(wgpu::Maintain::Wait);
+      *millis = timer.elapsed().as_secs_f64() * 1000.0 / f64::from(steps.max(1));
     }
+    println!(
+      "{count:>10} {:>14.3} {:>14.3} {:>7.2}x",
+      millis_per_step[0],
+      millis_per_step[1],
+      millis_per_step[0] / millis_per_step[1]
+    );
   }
+}
+
+/// Compares `solver` against direct summation on the initial conditions.
+pub fn force_error(config: RunConfig, samples: usize) {
+  init_logger();
+  let solver = config.solver;
+  let sim_params = config.sim_params();
+  let particles = initialize::create_galaxies(&sim_params, &config);
+  let stride = (particles.len() / samples.max(1)).max(1);
+  let timer = Instant::now();
+  let error = cpu::force_error(&particles, &sim_params, solver, stride);
+  println!(
+    "{solver:?} vs direct over {} of {} particles: mean {:.3e}, 99th percentile {:.3e}, max {:.3e} ({:.2?})",
+    particles.len().div_ceil(stride),
+    particles.len(),
+    error.mean,
+    error.p99,
+    error.max,
+    timer.elapsed()
+  );
+}
+
+pub async fn start(config: RunConfig) {
+  init_logger();
+  if config.headless {
+    start_headless(config).await;
+    return;
+  }
+
+  let backend = config.backend;
+  let mut sim_params = config.sim_params();
 
   let window_loop = EventLoopWrapper::new("Galaxy Sim");
   let mut surface = SurfaceWrapper::new();
-  let mut context = State::init(Some(&surface), &window_loop.window.inner_size()).await;
+  let mut context = State::init(
+    Some(&surface),
+    &window_loop.window.inner_size(),
+    config.camera,
+  )
+  .await;
   let event_loop_function = EventLoop::run;
   let mut example = None;
+  let mut cpu_compute = (backend == Backend::Cpu).then(|| CpuCompute::init(&sim_params, &config));
   let mut tick = Instant::now();
+  let diagnostics_every = config.diagnostics_every;
+  let mut initial_diagnostics = None;
+  let mut steps = 0u32;
 
   // main runner
   let _ = (event_loop_function)(
@@ -304,8 +594,19 @@ pub async fn start(galaxy_count: u32, headless: bool) {
             &context.queue,
             Some(&context.camera_bind_group_layout),
             sim_params,
+            &config,
           ));
         }
+        if diagnostics_every > 0 && initial_diagnostics.is_none() {
+          let particles = match (&cpu_compute, &example) {
+            (Some(cpu_compute), _) => cpu_compute.particles().to_vec(),
+            (None, Some(example)) => example.read_particles(&context.device, &context.queue),
+            (None, None) => unreachable!("renderer is created above"),
+          };
+          let initial = Diagnostics::measure(&particles, &sim_params);
+          report_diagnostics(&initial, &initial, sim_params.time);
+          initial_diagnostics = Some(initial);
+        }
       }
       Event::Suspended => {
         surface.suspend();
@@ -358,14 +659,38 @@ pub async fn start(galaxy_count: u32, headless: bool) {
                   ..wgpu::TextureViewDescriptor::default()
                 });
                 // start rendering
-                example.render(
-                  &view,
-                  &context.device,
-                  &context.queue,
-                  &context.camera_bind_group,
-                  &sim_params,
-                );
+                if let Some(cpu_compute) = &mut cpu_compute {
+                  cpu_compute.compute(&sim_params);
+                  example.upload_particles(&context.queue, cpu_compute.particles());
+                  example.draw(
+                    &view,
+                    &context.device,
+                    &context.queue,
+                    &context.camera_bind_group,
+                    &sim_params,
+                  );
+                } else {
+                  example.render(
+                    &view,
+                    &context.device,
+                    &context.queue,
+                    &context.camera_bind_group,
+                    &sim_params,
+                  );
+                }
                 frame.present();
+
+                steps += 1;
+                if let Some(initial) = &initial_diagnostics {
+                  if steps.is_multiple_of(diagnostics_every) {
+                    let particles = match &cpu_compute {
+                      Some(cpu_compute) => cpu_compute.particles().to_vec(),
+                      None => example.read_particles(&context.device, &context.queue),
+                    };
+                    let diagnostics = Diagnostics::measure(&particles, &sim_params);
+                    report_diagnostics(&diagnostics, initial, sim_params.time);
+                  }
+                }
               }
             }
             _ => {}
@@ -377,6 +702,23 @@ pub async fn start(galaxy_count: u32, headless: bool) {
   );
 }
 
-pub fn run(galaxy_count: u32, headless: bool) {
-  pollster::block_on(start(galaxy_count, headless));
+/// Shows this crate's events, such as black hole mergers and regularized binaries, unless
+/// `RUST_LOG` says otherwise; other crates only report warnings.
+fn init_logger() {
+  env_logger::Builder::from_env(
+    env_logger::Env::default().default_filter_or("warn,galaxy_sim=info"),
+  )
+  .init();
+}
+
+pub fn run(config: RunConfig) {
+  pollster::block_on(start(config));
+}
+
+pub fn validate(config: RunConfig, steps: u32) {
+  pollster::block_on(start_validate(config, steps));
+}
+
+pub fn bench(counts: &[u32], steps: u32) {
+  pollster::block_on(start_bench(counts, steps));
 }
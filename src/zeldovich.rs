@@ -0,0 +1,146 @@
+use crate::{
+  cosmology::{self, Cosmology},
+  particle_mesh::fft3,
+  star_formation, Particle, ParticleKind, RunConfig, SimParams,
+};
+use cgmath::Vector3;
+use rand::{rngs::SmallRng, SeedableRng};
+use rand_distr::{Distribution, StandardNormal};
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::f64::consts::PI;
+
+/// Shape of the linear matter power spectrum `P(k) = k^n T(k)²`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u32)]
+pub enum PowerSpectrum {
+  /// `T = 1`
+  PowerLaw = 0,
+  /// BBKS cold dark matter transfer function, turning over at `turnover_scale`
+  #[default]
+  Cdm = 1,
+}
+
+impl PowerSpectrum {
+  /// `P(k)` up to normalisation, for `k` in inverse simulation lengths.
+  #[must_use]
+  pub fn power(self, k: f64, config: &RunConfig) -> f64 {
+    let transfer = match self {
+      PowerSpectrum::PowerLaw => 1.0,
+      PowerSpectrum::Cdm => {
+        // Bardeen et al. 1986, with q = k times the turnover scale in place of k / Γh
+        let q = k * f64::from(config.turnover_scale);
+        (1.0 + 2.34 * q).ln() / (2.34 * q)
+          * (1.0 + 3.89 * q + (16.1 * q).powi(2) + (5.46 * q).powi(3) + (6.71 * q).powi(4))
+            .powf(-0.25)
+      }
+    };
+    k.powf(f64::from(config.spectral_index)) * transfer * transfer
+  }
+}
+
+/// Dark matter particles on a cubic lattice filling the periodic box of `sim_params`, displaced
+/// and set moving along the growing mode of a Gaussian random field with the Zel'dovich
+/// approximation `x = q + D(a) ψ(q)`, `p = a² H f D ψ`.
+///
+/// The field is white noise from the `seed` of `config` shaped by its `power_spectrum` and
+/// normalised so the linear density contrast extrapolated to redshift 0 has an rms of
+/// `fluctuation_amplitude` on the lattice; it is then scaled back to the initial redshift with the
+/// growth factor. Every particle has unit mass, which with the box size sets the Hubble constant
+/// (see `cosmology.rs`).
+#[must_use]
+pub fn create_lattice(sim_params: &SimParams, config: &RunConfig) -> Vec<Particle> {
+  let cosmology = Cosmology::from_sim_params(sim_params)
+    .expect("Zel'dovich initial conditions need a periodic box");
+  let n = (f64::from(sim_params.num_particles).cbrt().round() as usize).max(2);
+  let cells = n * n * n;
+  let box_size = f64::from(cosmology.box_size);
+  let spacing = box_size / n as f64;
+
+  let mut rng = SmallRng::seed_from_u64(u64::from(config.seed));
+  let mut density: Vec<Complex<f64>> = (0..cells)
+    .map(|_| Complex::new(StandardNormal.sample(&mut rng), 0.0))
+    .collect();
+  let mut planner = FftPlanner::new();
+  let forward = planner.plan_fft_forward(n);
+  let inverse = planner.plan_fft_inverse(n);
+  fft3(&mut density, n, &forward);
+
+  // δ_k shaped by sqrt(P), and ψ_k = i k δ_k / k² so that δ = -∇·ψ
+  let spectrum = config.power_spectrum;
+  let wavenumber = |d: usize| {
+    let d = if d > n / 2 {
+      d as f64 - n as f64
+    } else {
+      d as f64
+    };
+    2.0 * PI * d / box_size
+  };
+  let mut displacement = [
+    vec![Complex::default(); cells],
+    vec![Complex::default(); cells],
+    vec![Complex::default(); cells],
+  ];
+  for (i, delta) in density.iter_mut().enumerate() {
+    let index = [i % n, i / n % n, i / (n * n)];
+    let k = index.map(wavenumber);
+    let k_sq = k[0] * k[0] + k[1] * k[1] + k[2] * k[2];
+    if i == 0 {
+      *delta = Complex::default();
+      continue;
+    }
+    *delta *= spectrum.power(k_sq.sqrt(), config).sqrt();
+    for axis in 0..3 {
+      // the Nyquist mode has no sign, so it cannot carry a real gradient
+      if index[axis] != n / 2 || n % 2 == 1 {
+        displacement[axis][i] = *delta * Complex::new(0.0, k[axis] / k_sq);
+      }
+    }
+  }
+  fft3(&mut density, n, &inverse);
+  for field in &mut displacement {
+    fft3(field, n, &inverse);
+  }
+
+  let rms = (density.iter().map(|delta| delta.re * delta.re).sum::<f64>() / cells as f64).sqrt();
+  let a = f64::from(cosmology.initial_scale_factor());
+  let growth = cosmology.growth_factor(a);
+  let scale = if rms > 0.0 {
+    f64::from(config.fluctuation_amplitude) * growth / rms
+  } else {
+    0.0
+  };
+  let hubble_constant = cosmology.hubble_constant(cells as f64, sim_params.gravity);
+  // p = a² dx/dt with dx/dt = dD/dt ψ = H f D ψ
+  let momentum = a * a * hubble_constant * cosmology.hubble_ratio(a) * cosmology.growth_rate(a);
+  log::info!(
+    "Zel'dovich lattice: {cells} particles, z = {}, rms density contrast {:.3}",
+    cosmology.initial_redshift,
+    f64::from(config.fluctuation_amplitude) * growth
+  );
+
+  (0..cells)
+    .into_par_iter()
+    .map(|i| {
+      let lattice = [i % n, i / n % n, i / (n * n)].map(|d| (d as f64 + 0.5) * spacing);
+      let psi = [0, 1, 2].map(|axis| displacement[axis][i].re * scale);
+      let position = Vector3::new(
+        (lattice[0] + psi[0] - 0.5 * box_size) as f32,
+        (lattice[1] + psi[1] - 0.5 * box_size) as f32,
+        (lattice[2] + psi[2] - 0.5 * box_size) as f32,
+      );
+      Particle {
+        pos: cosmology::wrap(position, cosmology.box_size).into(),
+        vel: psi.map(|psi| (psi * momentum) as f32),
+        acc: [0.0; 3],
+        mass: 1.0,
+        galaxy_id: 0,
+        kind: ParticleKind::DarkMatter as u32,
+        density: 0.0,
+        internal_energy: 0.0,
+        energy_rate: 0.0,
+        formation_time: star_formation::INITIAL_POPULATION,
+      }
+    })
+    .collect()
+}